@@ -0,0 +1,14 @@
+-- Derived excerpts, reading time and table of contents for posts.
+-- Existing posts keep reading_time_minutes = 0 until the server fills them
+-- in on its next start (see content::backfill_post_meta).
+ALTER TABLE posts
+    ADD COLUMN excerpt VARCHAR(500) NOT NULL DEFAULT '' AFTER content,
+    ADD COLUMN excerpt_is_custom BOOLEAN NOT NULL DEFAULT FALSE AFTER excerpt,
+    ADD COLUMN reading_time_minutes INT NOT NULL DEFAULT 0 AFTER excerpt_is_custom,
+    ADD COLUMN toc JSON NULL AFTER reading_time_minutes;
+
+UPDATE posts SET toc = JSON_ARRAY();
+
+ALTER TABLE posts
+    MODIFY COLUMN reading_time_minutes INT NOT NULL DEFAULT 1,
+    MODIFY COLUMN toc JSON NOT NULL;
@@ -0,0 +1,3 @@
+-- Roles for route permissions; existing users become authors.
+ALTER TABLE users
+    ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'author' AFTER password_hash;
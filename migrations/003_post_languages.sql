@@ -0,0 +1,6 @@
+-- Post languages and translation groups. Existing posts are marked 'en';
+-- sites with DEFAULT_POST_LANGUAGE set to something else should update them.
+ALTER TABLE posts
+    ADD COLUMN language VARCHAR(16) NOT NULL DEFAULT 'en' AFTER toc,
+    ADD COLUMN translation_group INT NULL AFTER language,
+    ADD UNIQUE INDEX idx_translation_language (translation_group, language);
@@ -0,0 +1,3 @@
+-- Cutoff for "log out everywhere": access tokens issued before it are rejected.
+ALTER TABLE users
+    ADD COLUMN tokens_valid_after TIMESTAMP NULL AFTER role;
@@ -0,0 +1,4 @@
+-- When the user's email address was verified. Existing users start out
+-- unverified; with REQUIRE_VERIFIED_EMAIL on they must verify before posting.
+ALTER TABLE users
+    ADD COLUMN email_verified_at TIMESTAMP NULL AFTER role;
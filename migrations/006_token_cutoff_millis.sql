@@ -0,0 +1,4 @@
+-- Millisecond precision, so a role change can cut off tokens issued earlier
+-- in the same second as the new ones.
+ALTER TABLE users
+    MODIFY COLUMN tokens_valid_after TIMESTAMP(3) NULL;
@@ -0,0 +1,5 @@
+-- Scheduled account deletion and its outcome.
+ALTER TABLE users
+    ADD COLUMN deletion_scheduled_at TIMESTAMP NULL AFTER tokens_valid_after,
+    ADD COLUMN deletion_post_action VARCHAR(16) NULL AFTER deletion_scheduled_at,
+    ADD COLUMN deleted_at TIMESTAMP NULL AFTER deletion_post_action;
@@ -0,0 +1,6 @@
+-- TOTP two-factor authentication.
+ALTER TABLE users
+    ADD COLUMN totp_secret VARCHAR(64) NULL AFTER deleted_at,
+    ADD COLUMN totp_pending_secret VARCHAR(64) NULL AFTER totp_secret,
+    ADD COLUMN totp_enabled_at TIMESTAMP NULL AFTER totp_pending_secret,
+    ADD COLUMN totp_last_step BIGINT NULL AFTER totp_enabled_at;
@@ -0,0 +1,3 @@
+-- Address linked through Sign-In With Ethereum.
+ALTER TABLE users
+    ADD COLUMN ethereum_address CHAR(42) NULL UNIQUE AFTER totp_last_step;
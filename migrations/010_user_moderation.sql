@@ -0,0 +1,6 @@
+-- Suspensions, bans and admin-forced password resets.
+ALTER TABLE users
+    ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT FALSE AFTER ethereum_address,
+    ADD COLUMN suspended_until TIMESTAMP NULL AFTER password_reset_required,
+    ADD COLUMN banned_at TIMESTAMP NULL AFTER suspended_until,
+    ADD COLUMN moderation_reason VARCHAR(500) NULL AFTER banned_at;
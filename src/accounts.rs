@@ -0,0 +1,186 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+use crate::auth::{generate_opaque_token, hash_password};
+use crate::db::DbPool;
+use crate::recommend;
+
+/// What happens to an account's posts once its deletion goes through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostDisposal {
+    /// Keep the posts under a scrubbed placeholder account.
+    Anonymize,
+    Delete,
+}
+
+impl PostDisposal {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PostDisposal::Anonymize => "anonymize",
+            PostDisposal::Delete => "delete",
+        }
+    }
+}
+
+pub fn deletion_grace_period() -> Duration {
+    let days = env::var("ACCOUNT_DELETION_GRACE_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|days| *days > 0)
+        .unwrap_or(14);
+    Duration::days(days)
+}
+
+/// Marks the account for deletion after the grace period and returns when it
+/// becomes due. Signing in again before then cancels the request.
+pub async fn schedule_deletion(
+    pool: &DbPool,
+    user_id: i32,
+    posts: PostDisposal,
+) -> Result<DateTime<Utc>, sqlx::Error> {
+    let delete_after = Utc::now() + deletion_grace_period();
+    sqlx::query(
+        "UPDATE users SET deletion_scheduled_at = ?, deletion_post_action = ?
+         WHERE id = ?",
+    )
+    .bind(delete_after)
+    .bind(posts.as_str())
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(delete_after)
+}
+
+/// Returns whether a pending deletion was cancelled.
+pub async fn cancel_deletion(pool: &DbPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET deletion_scheduled_at = NULL, deletion_post_action = NULL
+         WHERE id = ? AND deletion_scheduled_at IS NOT NULL",
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Scrubs the account's personal data but keeps the row, so its posts stay
+/// published under a `deleted-<id>` placeholder. Returns false, changing
+/// nothing, when the deletion is no longer due.
+async fn anonymize_account(
+    pool: &DbPool,
+    user_id: i32,
+    password_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query(
+        "UPDATE users
+         SET username = CONCAT('deleted-', id), email = CONCAT('deleted-', id, '@invalid'),
+             password_hash = ?, role = 'author', email_verified_at = NULL,
+             totp_secret = NULL, totp_pending_secret = NULL, totp_enabled_at = NULL,
+             totp_last_step = NULL, ethereum_address = NULL,
+             deletion_scheduled_at = NULL, deletion_post_action = NULL, deleted_at = NOW()
+         WHERE id = ? AND deletion_scheduled_at IS NOT NULL AND deletion_scheduled_at <= NOW()",
+    )
+    .bind(password_hash)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    for table in [
+        "refresh_tokens",
+        "sessions",
+        "password_reset_tokens",
+        "email_verification_tokens",
+        "totp_recovery_codes",
+        "totp_challenges",
+        "api_keys",
+        "user_identities",
+        "ap_followers",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE user_id = ?"))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Carries out every deletion whose grace period has passed. Each account is
+/// checked again as it is purged, so one that signed in and cancelled since
+/// the list was read is left alone.
+pub async fn purge_due_accounts(pool: &DbPool) -> Result<usize, sqlx::Error> {
+    let due: Vec<(i32, Option<String>)> = sqlx::query_as(
+        "SELECT id, deletion_post_action FROM users
+         WHERE deletion_scheduled_at IS NOT NULL AND deletion_scheduled_at <= NOW()",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut purged = 0;
+    for (user_id, action) in &due {
+        if action.as_deref() == Some(PostDisposal::Anonymize.as_str()) {
+            // A random password nobody knows keeps the row unusable for sign-in.
+            let password_hash = match hash_password(&generate_opaque_token()) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    tracing::error!("Failed to anonymize account {}: {}", user_id, e);
+                    continue;
+                }
+            };
+            if anonymize_account(pool, *user_id, &password_hash).await? {
+                purged += 1;
+            }
+        } else {
+            // Posts, comments, tokens and the rest go with the row.
+            let result = sqlx::query(
+                "DELETE FROM users
+                 WHERE id = ? AND deletion_scheduled_at IS NOT NULL
+                   AND deletion_scheduled_at <= NOW()",
+            )
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+            if result.rows_affected() > 0 {
+                purged += 1;
+            }
+        }
+    }
+
+    if purged > 0 {
+        recommend::invalidate();
+    }
+    Ok(purged)
+}
+
+pub fn spawn_purge_loop(pool: DbPool) {
+    let interval_secs = env::var("ACCOUNT_PURGE_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(3600);
+
+    if interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            match purge_due_accounts(&pool).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {} deleted accounts", purged),
+                Err(e) => tracing::error!("Failed to purge deleted accounts: {}", e),
+            }
+        }
+    });
+}
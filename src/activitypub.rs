@@ -0,0 +1,635 @@
+use axum::http::HeaderMap;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use rsa::{
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    signature::{SignatureEncoding, Signer, Verifier},
+    RsaPrivateKey, RsaPublicKey,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::config::base_url;
+use crate::db::DbPool;
+use crate::http_client;
+
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+const KEY_BITS: usize = 2048;
+const MAX_CLOCK_SKEW_SECS: i64 = 12 * 60 * 60;
+const ACTOR_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub struct ApError(pub String);
+
+impl std::fmt::Display for ApError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ApError {}
+
+pub fn domain() -> String {
+    let url = base_url();
+    match Url::parse(&url) {
+        Ok(parsed) => match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            _ => url,
+        },
+        Err(_) => url,
+    }
+}
+
+pub fn actor_url(username: &str) -> String {
+    format!("{}/users/{}", base_url(), username)
+}
+
+pub fn article_url(username: &str, post_id: i32) -> String {
+    format!("{}/posts/{}", actor_url(username), post_id)
+}
+
+pub fn post_id_from_object(object_url: &str) -> Option<i32> {
+    let path = object_url.strip_prefix(&base_url())?;
+    let (prefix, id) = path.rsplit_once("/posts/")?;
+    if !prefix.is_empty() && !prefix.starts_with("/users/") {
+        return None;
+    }
+    id.parse().ok()
+}
+
+pub struct ActorKeys {
+    pub public_key_pem: String,
+    pub private_key_pem: String,
+}
+
+pub fn generate_keys() -> Result<ActorKeys, ApError> {
+    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+        .map_err(|e| ApError(format!("Key generation failed: {e}")))?;
+    let public_key_pem = RsaPublicKey::from(&private_key)
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| ApError(format!("Key encoding failed: {e}")))?;
+    let private_key_pem = private_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| ApError(format!("Key encoding failed: {e}")))?
+        .to_string();
+
+    Ok(ActorKeys {
+        public_key_pem,
+        private_key_pem,
+    })
+}
+
+pub async fn actor_keys(pool: &DbPool, user_id: i32) -> Result<ActorKeys, ApError> {
+    let existing: Option<(String, String)> = sqlx::query_as(
+        "SELECT public_key_pem, private_key_pem FROM ap_actor_keys WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| ApError(format!("Database error: {e}")))?;
+
+    if let Some((public_key_pem, private_key_pem)) = existing {
+        return Ok(ActorKeys {
+            public_key_pem,
+            private_key_pem,
+        });
+    }
+
+    let keys = tokio::task::spawn_blocking(generate_keys)
+        .await
+        .map_err(|e| ApError(format!("Key generation task failed: {e}")))??;
+
+    sqlx::query(
+        "INSERT IGNORE INTO ap_actor_keys (user_id, public_key_pem, private_key_pem) VALUES (?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(&keys.public_key_pem)
+    .bind(&keys.private_key_pem)
+    .execute(pool)
+    .await
+    .map_err(|e| ApError(format!("Database error: {e}")))?;
+
+    let (public_key_pem, private_key_pem): (String, String) = sqlx::query_as(
+        "SELECT public_key_pem, private_key_pem FROM ap_actor_keys WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| ApError(format!("Database error: {e}")))?;
+
+    Ok(ActorKeys {
+        public_key_pem,
+        private_key_pem,
+    })
+}
+
+pub fn person(username: &str, created_at: DateTime<Utc>, public_key_pem: &str) -> Value {
+    let actor = actor_url(username);
+    json!({
+        "@context": [
+            "https://www.w3.org/ns/activitystreams",
+            "https://w3id.org/security/v1"
+        ],
+        "id": actor,
+        "type": "Person",
+        "preferredUsername": username,
+        "name": username,
+        "url": actor,
+        "inbox": format!("{actor}/inbox"),
+        "outbox": format!("{actor}/outbox"),
+        "followers": format!("{actor}/followers"),
+        "published": created_at.to_rfc3339(),
+        "publicKey": {
+            "id": format!("{actor}#main-key"),
+            "owner": actor,
+            "publicKeyPem": public_key_pem
+        }
+    })
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+/// Whether `to` or `cc` of an object addresses the public collection, in its
+/// full form or the compacted `as:Public` / `Public` forms.
+pub fn is_public(object: &Value) -> bool {
+    ["to", "cc"]
+        .iter()
+        .filter_map(|field| object.get(*field))
+        .flat_map(|audience| match audience {
+            Value::Array(items) => items.iter().collect::<Vec<_>>(),
+            other => vec![other],
+        })
+        .filter_map(Value::as_str)
+        .any(|audience| matches!(audience, PUBLIC | "as:Public" | "Public"))
+}
+
+pub fn article(
+    post_id: i32,
+    username: &str,
+    title: &str,
+    content: &str,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+) -> Value {
+    let actor = actor_url(username);
+    let html = content
+        .split("\n\n")
+        .filter(|paragraph| !paragraph.trim().is_empty())
+        .map(|paragraph| format!("<p>{}</p>", escape_html(paragraph.trim())))
+        .collect::<String>();
+
+    json!({
+        "id": article_url(username, post_id),
+        "type": "Article",
+        "attributedTo": actor,
+        "name": title,
+        "content": html,
+        "mediaType": "text/html",
+        "url": format!("{}/posts/{}", base_url(), post_id),
+        "published": created_at.to_rfc3339(),
+        "updated": updated_at.to_rfc3339(),
+        "to": [PUBLIC],
+        "cc": [format!("{actor}/followers")]
+    })
+}
+
+pub fn wrap_activity(kind: &str, actor: &str, object: Value) -> Value {
+    let object_id = object
+        .get("id")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{object_id}#{}-{}", kind.to_lowercase(), Utc::now().timestamp_millis()),
+        "type": kind,
+        "actor": actor,
+        "published": Utc::now().to_rfc3339(),
+        "to": object.get("to").cloned().unwrap_or_else(|| json!([PUBLIC])),
+        "cc": object.get("cc").cloned().unwrap_or_else(|| json!([])),
+        "object": object
+    })
+}
+
+pub fn digest_header(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64.encode(Sha256::digest(body)))
+}
+
+fn host_header(url: &Url) -> String {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn request_target(method: &str, url: &Url) -> String {
+    let mut target = url.path().to_string();
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    format!("{} {}", method.to_lowercase(), target)
+}
+
+pub fn sign_request(
+    method: &str,
+    url: &str,
+    body: &[u8],
+    key_id: &str,
+    private_key_pem: &str,
+) -> Result<Vec<(String, String)>, ApError> {
+    let url = Url::parse(url).map_err(|e| ApError(format!("Invalid URL: {e}")))?;
+    let host = host_header(&url);
+    let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let digest = digest_header(body);
+
+    let signing_string = format!(
+        "(request-target): {}\nhost: {}\ndate: {}\ndigest: {}",
+        request_target(method, &url),
+        host,
+        date,
+        digest
+    );
+
+    let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+        .map_err(|e| ApError(format!("Invalid private key: {e}")))?;
+    let signature = SigningKey::<Sha256>::new(private_key).sign(signing_string.as_bytes());
+
+    let signature_header = format!(
+        "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest\",signature=\"{}\"",
+        key_id,
+        BASE64.encode(signature.to_bytes())
+    );
+
+    Ok(vec![
+        ("Host".to_string(), host),
+        ("Date".to_string(), date),
+        ("Digest".to_string(), digest),
+        ("Signature".to_string(), signature_header),
+    ])
+}
+
+fn parse_signature_header(header: &str) -> HashMap<String, String> {
+    header
+        .split(',')
+        .filter_map(|part| {
+            let (key, value) = part.trim().split_once('=')?;
+            Some((key.to_string(), value.trim_matches('"').to_string()))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    pub preferred_username: Option<String>,
+    pub key_id: String,
+    pub public_key_pem: String,
+}
+
+type ActorCache = Mutex<HashMap<String, (Instant, RemoteActor)>>;
+
+fn actor_cache() -> MutexGuard<'static, HashMap<String, (Instant, RemoteActor)>> {
+    static CACHE: OnceLock<ActorCache> = OnceLock::new();
+    CACHE
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+async fn fetch_json(url: &str) -> Result<Value, ApError> {
+    let parsed = Url::parse(url).map_err(|e| ApError(format!("Invalid URL {url}: {e}")))?;
+    http_client::check_public_url(&parsed).map_err(ApError)?;
+
+    http_client::public_only()
+        .get(url)
+        .header("Accept", ACTIVITY_JSON)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| ApError(format!("Failed to fetch {url}: {e}")))?
+        .json()
+        .await
+        .map_err(|e| ApError(format!("Invalid JSON from {url}: {e}")))
+}
+
+fn same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false,
+    }
+}
+
+/// Fetches the actor document at `url`. The document has to call itself by
+/// that URL and keep its key on the same origin, so one server cannot pass
+/// off another instance's actors as its own.
+pub async fn fetch_actor(url: &str) -> Result<RemoteActor, ApError> {
+    let document = fetch_json(url).await?;
+
+    let field = |value: &Value, name: &str| -> Result<String, ApError> {
+        value
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| ApError(format!("Actor document is missing {name}")))
+    };
+
+    let public_key = document
+        .get("publicKey")
+        .ok_or_else(|| ApError("Actor document is missing publicKey".to_string()))?;
+
+    let actor = RemoteActor {
+        id: field(&document, "id")?,
+        inbox: field(&document, "inbox")?,
+        shared_inbox: document
+            .pointer("/endpoints/sharedInbox")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        preferred_username: document
+            .get("preferredUsername")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        key_id: field(public_key, "id")?,
+        public_key_pem: field(public_key, "publicKeyPem")?,
+    };
+
+    if actor.id != url {
+        return Err(ApError(format!(
+            "Actor document at {url} claims to be {}",
+            actor.id
+        )));
+    }
+    if !same_origin(&actor.id, &actor.key_id) {
+        return Err(ApError(
+            "Actor key is hosted on a different origin".to_string(),
+        ));
+    }
+
+    actor_cache().insert(actor.id.clone(), (Instant::now(), actor.clone()));
+
+    Ok(actor)
+}
+
+/// The actor an activity claims to come from, provided `key_id` is its key.
+async fn signing_actor(
+    actor_id: &str,
+    key_id: &str,
+    refresh: bool,
+) -> Result<RemoteActor, ApError> {
+    let cached = if refresh {
+        None
+    } else {
+        let cache = actor_cache();
+        cache
+            .get(actor_id)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < ACTOR_CACHE_TTL)
+            .map(|(_, actor)| actor.clone())
+    };
+
+    let actor = match cached {
+        Some(actor) => actor,
+        None => fetch_actor(actor_id).await?,
+    };
+    if actor.key_id != key_id {
+        return Err(ApError(
+            "Signature key does not belong to actor".to_string(),
+        ));
+    }
+    Ok(actor)
+}
+
+fn verify_signature(public_key_pem: &str, signing_string: &str, signature: &[u8]) -> bool {
+    let Ok(public_key) = RsaPublicKey::from_public_key_pem(public_key_pem) else {
+        return false;
+    };
+    let Ok(signature) = Signature::try_from(signature) else {
+        return false;
+    };
+    VerifyingKey::<Sha256>::new(public_key)
+        .verify(signing_string.as_bytes(), &signature)
+        .is_ok()
+}
+
+/// Checks the HTTP signature of an inbox delivery from `actor_id`, the
+/// activity's `actor`. The key is always looked up through that actor, never
+/// through whatever `keyId` points at.
+pub async fn verify_request(
+    method: &str,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    actor_id: &str,
+) -> Result<RemoteActor, ApError> {
+    let header = |name: &str| -> Result<String, ApError> {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| ApError(format!("Missing {name} header")))
+    };
+
+    let params = parse_signature_header(&header("signature")?);
+    let key_id = params
+        .get("keyId")
+        .ok_or_else(|| ApError("Signature is missing keyId".to_string()))?;
+    let signature = params
+        .get("signature")
+        .and_then(|s| BASE64.decode(s).ok())
+        .ok_or_else(|| ApError("Signature is missing or malformed".to_string()))?;
+    let signed_headers: Vec<String> = params
+        .get("headers")
+        .map(|h| h.split_whitespace().map(str::to_lowercase).collect())
+        .unwrap_or_else(|| vec!["date".to_string()]);
+
+    for required in ["(request-target)", "host", "date", "digest"] {
+        if !signed_headers.iter().any(|h| h == required) {
+            return Err(ApError(format!("Signature must cover {required}")));
+        }
+    }
+
+    if header("digest")? != digest_header(body) {
+        return Err(ApError("Digest does not match body".to_string()));
+    }
+
+    let date = DateTime::parse_from_rfc2822(&header("date")?)
+        .map_err(|_| ApError("Invalid Date header".to_string()))?;
+    if (Utc::now() - date.with_timezone(&Utc)).num_seconds().abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(ApError(
+            "Date header is too far from current time".to_string(),
+        ));
+    }
+
+    let signing_string = signed_headers
+        .iter()
+        .map(|name| match name.as_str() {
+            "(request-target)" => Ok(format!(
+                "(request-target): {} {}",
+                method.to_lowercase(),
+                path_and_query
+            )),
+            name => Ok(format!("{name}: {}", header(name)?)),
+        })
+        .collect::<Result<Vec<_>, ApError>>()?
+        .join("\n");
+
+    // A cached actor may have rotated its key since; fetch it once more
+    // before giving up.
+    if let Ok(actor) = signing_actor(actor_id, key_id, false).await {
+        if verify_signature(&actor.public_key_pem, &signing_string, &signature) {
+            return Ok(actor);
+        }
+    }
+
+    let actor = signing_actor(actor_id, key_id, true).await?;
+    if verify_signature(&actor.public_key_pem, &signing_string, &signature) {
+        return Ok(actor);
+    }
+
+    Err(ApError("Signature verification failed".to_string()))
+}
+
+pub async fn deliver(
+    inbox: &str,
+    activity: &Value,
+    key_id: &str,
+    private_key_pem: &str,
+) -> Result<(), ApError> {
+    let body =
+        serde_json::to_vec(activity).map_err(|e| ApError(format!("Serialization error: {e}")))?;
+    let headers = sign_request("POST", inbox, &body, key_id, private_key_pem)?;
+
+    let parsed = Url::parse(inbox).map_err(|e| ApError(format!("Invalid inbox {inbox}: {e}")))?;
+    http_client::check_public_url(&parsed).map_err(ApError)?;
+
+    let mut request = http_client::public_only()
+        .post(inbox)
+        .header("Content-Type", ACTIVITY_JSON)
+        .header("Accept", ACTIVITY_JSON);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+
+    request
+        .body(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| ApError(format!("Delivery to {inbox} failed: {e}")))?;
+
+    Ok(())
+}
+
+async fn deliver_to_followers(pool: &DbPool, user_id: i32, username: &str, activity: Value) {
+    let inboxes: Vec<(String,)> = match sqlx::query_as(
+        "SELECT DISTINCT COALESCE(shared_inbox_url, inbox_url) FROM ap_followers WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    {
+        Ok(inboxes) => inboxes,
+        Err(e) => {
+            tracing::error!("Failed to load followers for user {}: {}", user_id, e);
+            return;
+        }
+    };
+
+    if inboxes.is_empty() {
+        return;
+    }
+
+    let keys = match actor_keys(pool, user_id).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            tracing::error!("Failed to load actor keys for user {}: {}", user_id, e);
+            return;
+        }
+    };
+
+    let key_id = format!("{}#main-key", actor_url(username));
+    for (inbox,) in inboxes {
+        if let Err(e) = deliver(&inbox, &activity, &key_id, &keys.private_key_pem).await {
+            tracing::warn!("{}", e);
+        }
+    }
+}
+
+type FederatedPostRow = (String, i32, String, String, DateTime<Utc>, DateTime<Utc>);
+
+pub fn spawn_publish(pool: DbPool, post_id: i32, kind: &'static str) {
+    tokio::spawn(async move {
+        let post: Option<FederatedPostRow> = match sqlx::query_as(
+            "SELECT u.username, p.user_id, p.title, p.content, p.created_at, p.updated_at
+             FROM posts p
+             JOIN users u ON p.user_id = u.id
+             WHERE p.id = ?",
+        )
+        .bind(post_id)
+        .fetch_optional(&pool)
+        .await
+        {
+            Ok(post) => post,
+            Err(e) => {
+                tracing::error!("Failed to load post {} for federation: {}", post_id, e);
+                return;
+            }
+        };
+
+        let Some((username, user_id, title, content, created_at, updated_at)) = post else {
+            return;
+        };
+
+        let object = article(post_id, &username, &title, &content, created_at, updated_at);
+        let activity = wrap_activity(kind, &actor_url(&username), object);
+        deliver_to_followers(&pool, user_id, &username, activity).await;
+    });
+}
+
+pub fn spawn_delete(pool: DbPool, user_id: i32, username: String, post_id: i32) {
+    tokio::spawn(async move {
+        let actor = actor_url(&username);
+        let tombstone = json!({
+            "id": article_url(&username, post_id),
+            "type": "Tombstone",
+            "to": [PUBLIC],
+            "cc": [format!("{actor}/followers")]
+        });
+        let activity = wrap_activity("Delete", &actor, tombstone);
+        deliver_to_followers(&pool, user_id, &username, activity).await;
+    });
+}
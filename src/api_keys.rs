@@ -0,0 +1,96 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::auth::{
+    access_token_ttl_secs, generate_opaque_token, hash_opaque_token, role_permissions, Claims,
+    Permission,
+};
+use crate::db::DbPool;
+
+/// Marks a bearer token as an API key rather than a JWT.
+pub const TOKEN_PREFIX: &str = "pat_";
+
+pub fn generate_token() -> String {
+    format!("{TOKEN_PREFIX}{}", generate_opaque_token())
+}
+
+#[derive(sqlx::FromRow)]
+struct ApiKeyOwner {
+    key_id: i64,
+    user_id: i32,
+    username: String,
+    role: String,
+    #[sqlx(json)]
+    scopes: Vec<Permission>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Deletes every API key the user holds, for when their credentials may have
+/// been compromised.
+pub async fn revoke_all(pool: &DbPool, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM api_keys WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Resolves an API key to claims for its owner. The key's scopes are
+/// intersected with the owner's current role, so a demotion also narrows
+/// existing keys.
+pub async fn authenticate(pool: &DbPool, token: &str) -> Result<Option<Claims>, sqlx::Error> {
+    let row: Option<ApiKeyOwner> = sqlx::query_as(
+        "SELECT k.id AS key_id, u.id AS user_id, u.username, u.role, k.scopes, k.expires_at
+         FROM api_keys k
+         JOIN users u ON u.id = k.user_id
+         WHERE k.token_hash = ?
+           AND (k.expires_at IS NULL OR k.expires_at > NOW())
+           AND u.deleted_at IS NULL",
+    )
+    .bind(hash_opaque_token(token))
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(ApiKeyOwner {
+        key_id,
+        user_id,
+        username,
+        role,
+        scopes,
+        expires_at,
+    }) = row
+    else {
+        return Ok(None);
+    };
+
+    // Coarse enough to keep authenticated requests from writing on every call.
+    sqlx::query(
+        "UPDATE api_keys SET last_used_at = NOW()
+         WHERE id = ? AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL 1 MINUTE)",
+    )
+    .bind(key_id)
+    .execute(pool)
+    .await?;
+
+    let granted = role_permissions(&role);
+    let now = Utc::now();
+    let exp = expires_at.unwrap_or(now + Duration::seconds(access_token_ttl_secs()));
+
+    Ok(Some(Claims {
+        sub: user_id,
+        username,
+        permissions: scopes
+            .into_iter()
+            .filter(|scope| granted.contains(scope))
+            .collect(),
+        role,
+        // Creating a key already required a session that passed any
+        // second factor the role demands.
+        mfa: true,
+        exp: exp.timestamp() as usize,
+        iat: now.timestamp_millis() as f64 / 1000.0,
+        jti: format!("api-key-{key_id}"),
+        api_key_id: Some(key_id),
+        sid: None,
+        act: None,
+    }))
+}
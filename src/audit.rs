@@ -0,0 +1,321 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+use crate::db::DbPool;
+use crate::models::{AuditEventResponse, AuditQuery, AuditVerification};
+
+/// `prev_hash` of the first event in the chain.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const VERIFY_BATCH_SIZE: i64 = 1000;
+
+/// Size of `audit_events.target`.
+const TARGET_MAX_CHARS: usize = 255;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Outcome {
+    #[default]
+    Success,
+    /// The request was refused for lack of credentials or permission.
+    Denied,
+    Failure,
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 3] = [Outcome::Success, Outcome::Denied, Outcome::Failure];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Denied => "denied",
+            Outcome::Failure => "failure",
+        }
+    }
+
+    /// The outcome of a handler result: 401 and 403 are denials, any other
+    /// error a failure.
+    pub fn of<T, E>(result: &Result<T, (StatusCode, E)>) -> Outcome {
+        match result {
+            Ok(_) => Outcome::Success,
+            Err((StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN, _)) => Outcome::Denied,
+            Err(_) => Outcome::Failure,
+        }
+    }
+}
+
+/// One entry for the audit log, e.g.
+/// `AuditEvent { actor_id: Some(claims.sub), action: "post.delete", target: format!("post:{id}"), ip, ..Default::default() }`.
+#[derive(Debug, Clone, Default)]
+pub struct AuditEvent<'a> {
+    pub actor_id: Option<i32>,
+    pub action: &'a str,
+    pub target: String,
+    pub ip: Option<IpAddr>,
+    pub outcome: Outcome,
+    /// From [`digest`], over the parts of the request worth proving later.
+    /// Never include passwords or other guessable secrets.
+    pub request_digest: Option<String>,
+}
+
+/// SHA-256 over the JSON form of a request's relevant fields.
+pub fn digest<T: Serialize + ?Sized>(value: &T) -> String {
+    hex::encode(Sha256::digest(
+        serde_json::to_vec(value).unwrap_or_default(),
+    ))
+}
+
+/// Audit target and request digest for a call the server signs with a
+/// caller-supplied private key: a transfer batch on `chain`, or a call to
+/// `contract` on it. `fields` is everything worth proving later except the
+/// key, which stays out of the log even hashed, since a digest would confirm
+/// any guess at it.
+pub fn signed_request(
+    chain: &str,
+    contract: Option<&str>,
+    fields: &serde_json::Value,
+) -> (String, String) {
+    debug_assert!(fields.get("private_key").is_none());
+    let target = match contract {
+        Some(address) => format!("contract:{chain}:{address}"),
+        None => format!("chain:{chain}"),
+    };
+    (target, digest(fields))
+}
+
+/// Each event's hash covers the previous event's hash, so editing or
+/// removing a row breaks every hash after it.
+#[allow(clippy::too_many_arguments)]
+fn chain_hash(
+    prev_hash: &str,
+    created_at: DateTime<Utc>,
+    actor_id: Option<i32>,
+    action: &str,
+    target: &str,
+    ip: Option<&str>,
+    outcome: &str,
+    request_digest: Option<&str>,
+) -> String {
+    digest(&json!([
+        prev_hash,
+        created_at.timestamp_millis(),
+        actor_id,
+        action,
+        target,
+        ip,
+        outcome,
+        request_digest,
+    ]))
+}
+
+/// Appends an event to `audit_events`, linked to the one before it.
+pub async fn record(pool: &DbPool, event: &AuditEvent<'_>) -> Result<(), sqlx::Error> {
+    // Stored with millisecond precision, so hash exactly what is stored.
+    let created_at = Utc::now()
+        .duration_trunc(TimeDelta::milliseconds(1))
+        .unwrap_or_else(|_| Utc::now());
+    let ip = event.ip.map(|ip| ip.to_string());
+    // Targets can carry caller input such as a username; cut them to the
+    // column size so an oversized one cannot keep the event out of the log.
+    let target: String = event.target.chars().take(TARGET_MAX_CHARS).collect();
+
+    let mut tx = pool.begin().await?;
+
+    // The single head row serialises writers so the chain never forks.
+    let prev_hash: String =
+        sqlx::query_scalar("SELECT hash FROM audit_chain_head WHERE id = 1 FOR UPDATE")
+            .fetch_optional(&mut *tx)
+            .await?
+            .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+    let hash = chain_hash(
+        &prev_hash,
+        created_at,
+        event.actor_id,
+        event.action,
+        &target,
+        ip.as_deref(),
+        event.outcome.as_str(),
+        event.request_digest.as_deref(),
+    );
+
+    sqlx::query(
+        "INSERT INTO audit_events
+             (actor_id, action, target, ip, outcome, request_digest, prev_hash, hash, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event.actor_id)
+    .bind(event.action)
+    .bind(&target)
+    .bind(&ip)
+    .bind(event.outcome.as_str())
+    .bind(&event.request_digest)
+    .bind(&prev_hash)
+    .bind(&hash)
+    .bind(created_at)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO audit_chain_head (id, hash) VALUES (1, ?)
+         ON DUPLICATE KEY UPDATE hash = VALUES(hash)",
+    )
+    .bind(&hash)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// Like [`record`], for handlers whose action already happened: a failure
+/// to write the log is reported but does not fail the request.
+pub async fn log(pool: &DbPool, event: &AuditEvent<'_>) {
+    if let Err(e) = record(pool, event).await {
+        tracing::error!(
+            "Failed to write audit event {} on {}: {}",
+            event.action,
+            event.target,
+            e
+        );
+    }
+}
+
+/// Events matching the query, newest first.
+pub async fn search(
+    pool: &DbPool,
+    query: &AuditQuery,
+) -> Result<Vec<AuditEventResponse>, sqlx::Error> {
+    let mut conditions = Vec::new();
+    if query.actor_id.is_some() {
+        conditions.push("actor_id = ?");
+    }
+    if query.action.is_some() {
+        conditions.push("action = ?");
+    }
+    if query.action_prefix.is_some() {
+        conditions.push("action LIKE CONCAT(?, '%')");
+    }
+    if query.target.is_some() {
+        conditions.push("target = ?");
+    }
+    if query.ip.is_some() {
+        conditions.push("ip = ?");
+    }
+    if query.outcome.is_some() {
+        conditions.push("outcome = ?");
+    }
+    if query.since.is_some() {
+        conditions.push("created_at >= ?");
+    }
+    if query.until.is_some() {
+        conditions.push("created_at < ?");
+    }
+    if query.before_id.is_some() {
+        conditions.push("id < ?");
+    }
+
+    let mut sql = "SELECT id, actor_id, action, target, ip, outcome, request_digest, prev_hash,
+                          hash, created_at
+                   FROM audit_events"
+        .to_string();
+    if !conditions.is_empty() {
+        sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+    }
+    sql.push_str(" ORDER BY id DESC LIMIT ?");
+
+    let mut query_builder = sqlx::query_as::<_, AuditEventResponse>(&sql);
+    if let Some(actor_id) = query.actor_id {
+        query_builder = query_builder.bind(actor_id);
+    }
+    if let Some(action) = &query.action {
+        query_builder = query_builder.bind(action);
+    }
+    if let Some(prefix) = &query.action_prefix {
+        let escaped = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        query_builder = query_builder.bind(escaped);
+    }
+    if let Some(target) = &query.target {
+        query_builder = query_builder.bind(target);
+    }
+    if let Some(ip) = &query.ip {
+        query_builder = query_builder.bind(ip);
+    }
+    if let Some(outcome) = &query.outcome {
+        query_builder = query_builder.bind(outcome);
+    }
+    if let Some(since) = query.since {
+        query_builder = query_builder.bind(since);
+    }
+    if let Some(until) = query.until {
+        query_builder = query_builder.bind(until);
+    }
+    if let Some(before_id) = query.before_id {
+        query_builder = query_builder.bind(before_id);
+    }
+
+    query_builder.bind(query.limit()).fetch_all(pool).await
+}
+
+/// Recomputes the whole chain and reports the first event that does not
+/// match, whether it was edited, removed, inserted or cut off the end.
+pub async fn verify_chain(pool: &DbPool) -> Result<AuditVerification, sqlx::Error> {
+    let mut expected_prev = GENESIS_HASH.to_string();
+    let mut checked = 0u64;
+    let mut last_id = 0i64;
+
+    loop {
+        let batch = sqlx::query_as::<_, AuditEventResponse>(
+            "SELECT id, actor_id, action, target, ip, outcome, request_digest, prev_hash,
+                    hash, created_at
+             FROM audit_events WHERE id > ? ORDER BY id LIMIT ?",
+        )
+        .bind(last_id)
+        .bind(VERIFY_BATCH_SIZE)
+        .fetch_all(pool)
+        .await?;
+
+        let Some(last) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        for event in &batch {
+            let hash = chain_hash(
+                &event.prev_hash,
+                event.created_at,
+                event.actor_id,
+                &event.action,
+                &event.target,
+                event.ip.as_deref(),
+                &event.outcome,
+                event.request_digest.as_deref(),
+            );
+            if event.prev_hash != expected_prev || event.hash != hash {
+                return Ok(AuditVerification {
+                    valid: false,
+                    checked,
+                    first_invalid_id: Some(event.id),
+                });
+            }
+            expected_prev = hash;
+            checked += 1;
+        }
+    }
+
+    let head: Option<String> = sqlx::query_scalar("SELECT hash FROM audit_chain_head WHERE id = 1")
+        .fetch_optional(pool)
+        .await?;
+    let head = head.unwrap_or_else(|| GENESIS_HASH.to_string());
+
+    Ok(AuditVerification {
+        valid: head == expected_prev,
+        checked,
+        first_invalid_id: None,
+    })
+}
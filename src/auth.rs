@@ -1,56 +1,308 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
 use axum::{
     extract::{Request, State},
     http::StatusCode,
     middleware::Next,
     response::Response,
 };
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Validation};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::env;
+use std::net::IpAddr;
+use std::sync::OnceLock;
 
+use crate::api_keys;
+use crate::audit::{self, AuditEvent, Outcome};
+use crate::client_ip::ClientIp;
 use crate::db::DbPool;
+use crate::jwt_keys;
+use crate::moderation;
+use crate::sessions;
+use crate::tokens;
+use crate::two_factor;
+
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_EDITOR: &str = "editor";
+pub const ROLE_AUTHOR: &str = "author";
+
+pub const ROLES: [&str; 3] = [ROLE_ADMIN, ROLE_EDITOR, ROLE_AUTHOR];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Permission {
+    /// Publish posts and edit or delete one's own.
+    #[serde(rename = "posts:write")]
+    PostsWrite,
+    /// Edit or delete posts written by anyone.
+    #[serde(rename = "posts:edit_any")]
+    PostsEditAny,
+    /// Manage the site-wide featured slots.
+    #[serde(rename = "posts:feature")]
+    PostsFeature,
+    /// Change roles, suspend, ban and impersonate users.
+    #[serde(rename = "users:manage")]
+    UsersManage,
+    /// Read and verify the security audit log.
+    #[serde(rename = "audit:read")]
+    AuditRead,
+    /// Generate new wallet key pairs.
+    #[serde(rename = "wallets:generate")]
+    WalletsGenerate,
+    /// Send batch transfers over a configured chain RPC.
+    #[serde(rename = "transfer:send")]
+    TransferSend,
+    /// Call or send transactions to smart contracts.
+    #[serde(rename = "contract:call")]
+    ContractCall,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 8] = [
+        Permission::PostsWrite,
+        Permission::PostsEditAny,
+        Permission::PostsFeature,
+        Permission::UsersManage,
+        Permission::AuditRead,
+        Permission::WalletsGenerate,
+        Permission::TransferSend,
+        Permission::ContractCall,
+    ];
+
+    /// Permissions an API key may be scoped to.
+    pub const API_KEY_SCOPES: [Permission; 4] = [
+        Permission::PostsWrite,
+        Permission::WalletsGenerate,
+        Permission::TransferSend,
+        Permission::ContractCall,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::PostsWrite => "posts:write",
+            Permission::PostsEditAny => "posts:edit_any",
+            Permission::PostsFeature => "posts:feature",
+            Permission::UsersManage => "users:manage",
+            Permission::AuditRead => "audit:read",
+            Permission::WalletsGenerate => "wallets:generate",
+            Permission::TransferSend => "transfer:send",
+            Permission::ContractCall => "contract:call",
+        }
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Permissions granted by a role; unknown roles get none.
+pub fn role_permissions(role: &str) -> &'static [Permission] {
+    match role {
+        ROLE_ADMIN => &Permission::ALL,
+        ROLE_EDITOR => &[
+            Permission::PostsWrite,
+            Permission::PostsEditAny,
+            Permission::PostsFeature,
+        ],
+        ROLE_AUTHOR => &[Permission::PostsWrite],
+        _ => &[],
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: i32,
     pub username: String,
+    pub role: String,
+    pub permissions: Vec<Permission>,
+    /// Whether the session passed a second factor.
+    pub mfa: bool,
     pub exp: usize,
+    /// Issue time in seconds with millisecond precision, so a revocation
+    /// cutoff can fall between two tokens issued within the same second.
+    pub iat: f64,
+    /// Unique token id, the handle used to revoke a single access token.
+    pub jti: String,
+    /// Set when the request authenticated with an API key instead of a JWT.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<i64>,
+    /// The sign-in session the access token was issued for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i64>,
+    /// The admin acting as this user, set on impersonation tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<i32>,
 }
 
-pub fn create_token(user_id: i32, username: &str) -> Result<String, jsonwebtoken::errors::Error> {
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::hours(24))
+impl Claims {
+    pub fn issued_at_millis(&self) -> i64 {
+        (self.iat * 1000.0).round() as i64
+    }
+
+    /// Roles that must use two-factor authentication get no permissions
+    /// until the session has passed it.
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        if !self.mfa && two_factor::required_for_role(&self.role) {
+            return false;
+        }
+        self.permissions.contains(&permission)
+    }
+}
+
+pub fn access_token_ttl_secs() -> i64 {
+    env::var("ACCESS_TOKEN_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(900)
+}
+
+pub fn create_token(
+    user_id: i32,
+    username: &str,
+    role: &str,
+    mfa: bool,
+    session_id: i64,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = chrono::Utc::now();
+    let expiration = now
+        .checked_add_signed(chrono::Duration::seconds(access_token_ttl_secs()))
         .expect("valid timestamp")
         .timestamp() as usize;
 
     let claims = Claims {
         sub: user_id,
         username: username.to_owned(),
+        role: role.to_owned(),
+        permissions: role_permissions(role).to_vec(),
+        mfa,
         exp: expiration,
+        iat: now.timestamp_millis() as f64 / 1000.0,
+        jti: generate_opaque_token(),
+        api_key_id: None,
+        sid: Some(session_id),
+        act: None,
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
+    let keys = jwt_keys::keys();
+    encode(&keys.header(), &claims, keys.encoding_key())
+}
+
+pub fn impersonation_ttl_secs() -> i64 {
+    env::var("IMPERSONATION_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(900)
+}
+
+/// Signs an access token that lets `impersonator_id` act as the user. It is
+/// tied to no session, so it cannot be refreshed and lapses after
+/// `IMPERSONATION_TTL_SECS`.
+pub fn create_impersonation_token(
+    user_id: i32,
+    username: &str,
+    role: &str,
+    impersonator_id: i32,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = chrono::Utc::now();
+    let expiration = now
+        .checked_add_signed(chrono::Duration::seconds(impersonation_ttl_secs()))
+        .expect("valid timestamp")
+        .timestamp() as usize;
+
+    let claims = Claims {
+        sub: user_id,
+        username: username.to_owned(),
+        role: role.to_owned(),
+        permissions: role_permissions(role).to_vec(),
+        // The admin behind the token already passed their own second factor.
+        mfa: true,
+        exp: expiration,
+        iat: now.timestamp_millis() as f64 / 1000.0,
+        jti: generate_opaque_token(),
+        api_key_id: None,
+        sid: None,
+        act: Some(impersonator_id),
+    };
+
+    let keys = jwt_keys::keys();
+    encode(&keys.header(), &claims, keys.encoding_key())
 }
 
 pub fn verify_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let header = decode_header(token)?;
+    let (key, algorithm) = jwt_keys::keys()
+        .decoding_key(header.kid.as_deref())
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidSignature)?;
 
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )?;
+    let token_data = decode::<Claims>(token, key, &Validation::new(algorithm))?;
 
     Ok(token_data.claims)
 }
 
+/// Checks that apply to every authenticated request once the credential itself
+/// is known to be valid: the account must not be suspended or banned, and an
+/// impersonation token only works while the admin behind it may still manage
+/// users. Impersonated requests are audited under `operation`, e.g.
+/// `"POST /posts"`.
+pub async fn check_account_access(
+    pool: &DbPool,
+    claims: &Claims,
+    ip: Option<IpAddr>,
+    operation: &str,
+) -> Result<(), StatusCode> {
+    // Suspended and banned accounts lose access even with unexpired tokens.
+    if moderation::restriction(pool, claims.sub)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .is_some_and(|restriction| restriction.blocks_requests())
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let Some(impersonator_id) = claims.act else {
+        return Ok(());
+    };
+
+    let impersonator_role: Option<String> =
+        sqlx::query_scalar("SELECT role FROM users WHERE id = ? AND deleted_at IS NULL")
+            .bind(impersonator_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let may_manage_users = impersonator_role
+        .is_some_and(|role| role_permissions(&role).contains(&Permission::UsersManage));
+    if !may_manage_users
+        || moderation::restriction(pool, impersonator_id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .is_some()
+    {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    audit::log(
+        pool,
+        &AuditEvent {
+            actor_id: Some(impersonator_id),
+            action: "admin.impersonation_request",
+            target: format!("user:{} {operation}", claims.sub),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(())
+}
+
 pub async fn auth_middleware(
     State(pool): State<DbPool>,
+    ClientIp(ip): ClientIp,
     mut request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
@@ -66,27 +318,198 @@ pub async fn auth_middleware(
         }
     };
 
-    let claims = verify_token(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let claims = if token.starts_with(api_keys::TOKEN_PREFIX) {
+        api_keys::authenticate(&pool, token)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::UNAUTHORIZED)?
+    } else {
+        let claims = verify_token(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
 
-    let user_exists: Option<(i32,)> = sqlx::query_as("SELECT id FROM users WHERE id = ?")
-        .bind(claims.sub)
-        .fetch_optional(&pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let active = tokens::is_access_token_active(&pool, &claims)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    if user_exists.is_none() {
-        return Err(StatusCode::UNAUTHORIZED);
-    }
+        if !active {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        if let Some(sid) = claims.sid {
+            sessions::touch(&pool, sid)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        }
+        claims
+    };
+
+    let operation = format!("{} {}", request.method(), request.uri().path());
+    check_account_access(&pool, &claims, ip, &operation).await?;
 
     request.extensions_mut().insert(claims);
 
     Ok(next.run(request).await)
 }
 
-pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
-    bcrypt::hash(password, bcrypt::DEFAULT_COST)
+/// Rejects callers whose claims lack the permission given as the layer state,
+/// e.g. `middleware::from_fn_with_state((pool.clone(), Permission::TransferSend), auth::require_permission)`.
+/// Refusals are written to the audit log, since they never reach the
+/// handler's own audit event. Must be layered inside `auth_middleware`,
+/// which provides the `Claims`.
+pub async fn require_permission(
+    State((pool, permission)): State<(DbPool, Permission)>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let claims = request
+        .extensions()
+        .get::<Claims>()
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if !claims.has_permission(permission) {
+        audit::log(
+            &pool,
+            &AuditEvent {
+                actor_id: Some(claims.sub),
+                action: "auth.permission_denied",
+                target: format!(
+                    "{} {} {}",
+                    permission.as_str(),
+                    request.method(),
+                    request.uri().path()
+                ),
+                ip,
+                outcome: Outcome::Denied,
+                ..Default::default()
+            },
+        )
+        .await;
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Rejects API keys and impersonation tokens on routes that manage the account
+/// or its sessions, which need the signed-in user themselves. Must be layered
+/// inside `auth_middleware`.
+pub async fn require_session(request: Request, next: Next) -> Result<Response, StatusCode> {
+    let claims = request
+        .extensions()
+        .get::<Claims>()
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if claims.api_key_id.is_some() || claims.act.is_some() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[derive(Debug)]
+pub struct PasswordHashError(pub String);
+
+impl std::fmt::Display for PasswordHashError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PasswordHashError {}
+
+impl From<bcrypt::BcryptError> for PasswordHashError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        PasswordHashError(e.to_string())
+    }
+}
+
+impl From<argon2::password_hash::Error> for PasswordHashError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        PasswordHashError(e.to_string())
+    }
+}
+
+fn argon2_setting(name: &str, default: u32) -> u32 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(default)
+}
+
+/// Argon2id cost for new hashes. The defaults are the OWASP minimum of
+/// 19 MiB, two passes and one lane.
+fn argon2_params() -> Result<Params, PasswordHashError> {
+    Params::new(
+        argon2_setting("ARGON2_MEMORY_KIB", 19 * 1024),
+        argon2_setting("ARGON2_ITERATIONS", 2),
+        argon2_setting("ARGON2_PARALLELISM", 1),
+        None,
+    )
+    .map_err(|e| PasswordHashError(format!("Invalid Argon2 parameters: {e}")))
+}
+
+fn argon2() -> Result<Argon2<'static>, PasswordHashError> {
+    Ok(Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        argon2_params()?,
+    ))
+}
+
+pub fn hash_password(password: &str) -> Result<String, PasswordHashError> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    Ok(argon2()?
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Checks a password against an Argon2 hash or a legacy bcrypt one.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+    if hash.starts_with("$argon2") {
+        let parsed = PasswordHash::new(hash)?;
+        // The algorithm and cost come from the hash itself.
+        return match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(e.into()),
+        };
+    }
+    Ok(bcrypt::verify(password, hash)?)
+}
+
+/// Whether a hash was made with another algorithm or with different Argon2id
+/// parameters than are now configured.
+pub fn password_needs_rehash(hash: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        return true;
+    };
+    let Ok(current) = argon2_params() else {
+        return false;
+    };
+    parsed.algorithm != Algorithm::Argon2id.ident()
+        || parsed.version != Some(Version::V0x13.into())
+        || Params::try_from(&parsed).map_or(true, |params| {
+            params.m_cost() != current.m_cost()
+                || params.t_cost() != current.t_cost()
+                || params.p_cost() != current.p_cost()
+        })
+}
+
+/// A hash of a random password, checked against when the username does not
+/// exist so that lookups for unknown and known users take the same time.
+pub fn dummy_password_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| {
+        hash_password(&generate_opaque_token()).expect("hashing a random password cannot fail")
+    })
+}
+
+pub fn generate_opaque_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::BcryptError> {
-    bcrypt::verify(password, hash)
+pub fn hash_opaque_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
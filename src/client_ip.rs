@@ -0,0 +1,73 @@
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+
+/// The caller's IP address. Behind a reverse proxy set `TRUST_PROXY_HEADERS`
+/// so the `X-Forwarded-For` entry appended by the outermost trusted proxy (or
+/// `X-Real-IP`) is used instead of the peer address. Entries to its left come
+/// from the client and are ignored. `None` when neither is available.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+fn trust_proxy_headers() -> bool {
+    env::var("TRUST_PROXY_HEADERS")
+        .map(|value| value == "true")
+        .unwrap_or(false)
+}
+
+/// Proxies in front of the server that each append to `X-Forwarded-For`.
+fn trusted_proxy_hops() -> usize {
+    env::var("TRUSTED_PROXY_HOPS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|hops| *hops > 0)
+        .unwrap_or(1)
+}
+
+/// The entry written by the outermost of `hops` trusted proxies, counting
+/// from the right.
+fn forwarded_for(value: &str, hops: usize) -> Option<&str> {
+    let entries: Vec<&str> = value.split(',').map(str::trim).collect();
+    entries
+        .get(entries.len().saturating_sub(hops))
+        .copied()
+        .filter(|entry| !entry.is_empty())
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if trust_proxy_headers() {
+            let forwarded = parts
+                .headers
+                .get("X-Forwarded-For")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| forwarded_for(value, trusted_proxy_hops()))
+                .or_else(|| {
+                    parts
+                        .headers
+                        .get("X-Real-IP")
+                        .and_then(|value| value.to_str().ok())
+                })
+                .and_then(|value| value.trim().parse().ok());
+            if forwarded.is_some() {
+                return Ok(ClientIp(forwarded));
+            }
+        }
+
+        Ok(ClientIp(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        ))
+    }
+}
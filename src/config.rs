@@ -0,0 +1,12 @@
+use std::env;
+
+pub fn base_url() -> String {
+    env::var("PUBLIC_BASE_URL")
+        .unwrap_or_else(|_| "http://localhost:3000".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+pub fn site_name() -> String {
+    env::var("SITE_NAME").unwrap_or_else(|_| "Blog".to_string())
+}
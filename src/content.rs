@@ -0,0 +1,210 @@
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use std::collections::HashMap;
+use std::env;
+
+use crate::db::DbPool;
+use crate::models::TocEntry;
+use crate::recommend::is_cjk;
+
+pub const MAX_EXCERPT_CHARS: usize = 500;
+
+pub struct PostMeta {
+    pub excerpt: String,
+    pub reading_time_minutes: i32,
+    pub toc: Vec<TocEntry>,
+}
+
+fn env_or(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(default)
+}
+
+fn excerpt_length() -> usize {
+    env_or("EXCERPT_LENGTH", 200).min(MAX_EXCERPT_CHARS - 1)
+}
+
+fn words_per_minute() -> usize {
+    env_or("READING_WORDS_PER_MINUTE", 200)
+}
+
+fn cjk_chars_per_minute() -> usize {
+    env_or("READING_CJK_CHARS_PER_MINUTE", 400)
+}
+
+fn parser(content: &str) -> Parser<'_> {
+    Parser::new_ext(content, Options::ENABLE_HEADING_ATTRIBUTES)
+}
+
+/// Plain text of the body paragraphs, skipping headings and code blocks.
+fn body_text(content: &str) -> String {
+    let mut text = String::new();
+    let mut skip = 0usize;
+
+    for event in parser(content) {
+        match event {
+            Event::Start(Tag::Heading { .. } | Tag::CodeBlock(_)) => skip += 1,
+            Event::End(TagEnd::Heading(_) | TagEnd::CodeBlock) => skip = skip.saturating_sub(1),
+            Event::Text(value) | Event::Code(value) if skip == 0 => text.push_str(&value),
+            Event::SoftBreak | Event::HardBreak | Event::End(TagEnd::Paragraph | TagEnd::Item) => {
+                text.push(' ')
+            }
+            _ => {}
+        }
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn excerpt(content: &str) -> String {
+    let text = body_text(content);
+    let max = excerpt_length();
+    if text.chars().count() <= max {
+        return text;
+    }
+
+    let mut cut: String = text.chars().take(max).collect();
+    let next = text.chars().nth(max);
+    let mid_word = next.is_some_and(|c| !c.is_whitespace() && !is_cjk(c))
+        && cut.chars().last().is_some_and(|c| !is_cjk(c));
+    if mid_word {
+        if let Some(space) = cut.rfind(' ').filter(|space| *space > cut.len() / 2) {
+            cut.truncate(space);
+        }
+    }
+
+    let cut = cut.trim_end_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation());
+    format!("{cut}…")
+}
+
+/// Latin-script text is counted in words, CJK text in characters.
+pub fn reading_time_minutes(content: &str) -> i32 {
+    let mut words = 0usize;
+    let mut cjk_chars = 0usize;
+
+    for event in parser(content) {
+        if let Event::Text(value) | Event::Code(value) = event {
+            let mut in_word = false;
+            for c in value.chars() {
+                if is_cjk(c) {
+                    cjk_chars += 1;
+                    in_word = false;
+                } else if c.is_alphanumeric() {
+                    if !in_word {
+                        words += 1;
+                    }
+                    in_word = true;
+                } else if c.is_whitespace() {
+                    in_word = false;
+                }
+            }
+        }
+    }
+
+    let minutes =
+        words as f64 / words_per_minute() as f64 + cjk_chars as f64 / cjk_chars_per_minute() as f64;
+    (minutes.ceil() as i32).max(1)
+}
+
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() || c == '_' {
+            slug.extend(c.to_lowercase());
+        } else if c.is_whitespace() || c == '-' {
+            slug.push('-');
+        }
+    }
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+pub fn table_of_contents(content: &str) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    let mut used: HashMap<String, usize> = HashMap::new();
+    let mut current: Option<(u8, Option<String>, String)> = None;
+
+    for event in parser(content) {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some((level as u8, id.map(|id| id.to_string()), String::new()));
+            }
+            Event::Text(value) | Event::Code(value) => {
+                if let Some((_, _, text)) = current.as_mut() {
+                    text.push_str(&value);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let Some((level, id, text)) = current.take() else {
+                    continue;
+                };
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if text.is_empty() {
+                    continue;
+                }
+
+                let base = id.unwrap_or_else(|| slugify(&text));
+                let count = used.entry(base.clone()).or_insert(0);
+                let anchor = if *count == 0 {
+                    base
+                } else {
+                    format!("{base}-{count}")
+                };
+                *count += 1;
+
+                entries.push(TocEntry {
+                    level,
+                    text,
+                    anchor,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    entries
+}
+
+pub fn derive(content: &str, explicit_excerpt: Option<&str>) -> PostMeta {
+    let excerpt = match explicit_excerpt.map(str::trim) {
+        Some(custom) if !custom.is_empty() => custom.to_string(),
+        _ => excerpt(content),
+    };
+
+    PostMeta {
+        excerpt,
+        reading_time_minutes: reading_time_minutes(content),
+        toc: table_of_contents(content),
+    }
+}
+
+/// Fills in the derived fields of posts written before they existed, which the
+/// migration leaves with a reading time of 0. Returns how many were updated.
+pub async fn backfill_post_meta(pool: &DbPool) -> Result<usize, sqlx::Error> {
+    let posts: Vec<(i32, String)> =
+        sqlx::query_as("SELECT id, content FROM posts WHERE reading_time_minutes = 0")
+            .fetch_all(pool)
+            .await?;
+
+    for (id, content) in &posts {
+        let meta = derive(content, None);
+        let toc = serde_json::to_string(&meta.toc).unwrap_or_else(|_| "[]".to_string());
+        sqlx::query(
+            "UPDATE posts SET excerpt = ?, reading_time_minutes = ?, toc = ?, updated_at = updated_at
+             WHERE id = ?",
+        )
+        .bind(&meta.excerpt)
+        .bind(meta.reading_time_minutes)
+        .bind(&toc)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(posts.len())
+}
@@ -0,0 +1,175 @@
+use minijinja::{context, Environment};
+use std::env;
+
+use crate::auth::{generate_opaque_token, hash_opaque_token};
+use crate::config::{base_url, site_name};
+use crate::db::DbPool;
+use crate::mailer::{self, Email};
+
+const VERIFICATION_TEMPLATE: &str = include_str!("../templates/email_verification.txt");
+
+pub fn verification_token_ttl_hours() -> i64 {
+    env::var("EMAIL_VERIFICATION_TTL_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|hours| *hours > 0)
+        .unwrap_or(48)
+}
+
+/// Minimum time between two verification emails for the same user.
+pub fn resend_interval_secs() -> i64 {
+    env::var("EMAIL_VERIFICATION_RESEND_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(60)
+}
+
+/// Whether authors need a verified email address before they can publish.
+pub fn required_for_posting() -> bool {
+    env::var("REQUIRE_VERIFIED_EMAIL")
+        .map(|value| {
+            matches!(
+                value.trim().to_ascii_lowercase().as_str(),
+                "1" | "true" | "yes" | "on"
+            )
+        })
+        .unwrap_or(false)
+}
+
+pub fn render_verification_email(
+    username: &str,
+    email: &str,
+    verify_token: &str,
+) -> Result<String, minijinja::Error> {
+    let env = Environment::new();
+    env.render_str(
+        VERIFICATION_TEMPLATE,
+        context! {
+            username,
+            email,
+            site_name => site_name(),
+            ttl_hours => verification_token_ttl_hours(),
+            verify_url => format!("{}/verify-email?token={}", base_url(), verify_token),
+        },
+    )
+}
+
+/// Seconds until the user may request another verification email, if any.
+pub async fn resend_wait_secs(pool: &DbPool, user_id: i32) -> Result<Option<i64>, sqlx::Error> {
+    let elapsed: Option<(Option<i64>,)> = sqlx::query_as(
+        "SELECT TIMESTAMPDIFF(SECOND, MAX(created_at), NOW())
+         FROM email_verification_tokens
+         WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let interval = resend_interval_secs();
+    Ok(elapsed
+        .and_then(|(elapsed,)| elapsed)
+        .filter(|elapsed| *elapsed < interval)
+        .map(|elapsed| interval - elapsed))
+}
+
+/// Issues a token for `email`, replacing any outstanding one, and mails the
+/// link in the background. Failures to send are logged, not returned, so a
+/// mail outage does not block registration.
+pub async fn send_verification(
+    pool: &DbPool,
+    user_id: i32,
+    username: &str,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    let token = generate_opaque_token();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM email_verification_tokens WHERE user_id = ? AND used_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO email_verification_tokens (user_id, email, token_hash, expires_at)
+         VALUES (?, ?, ?, NOW() + INTERVAL ? HOUR)",
+    )
+    .bind(user_id)
+    .bind(email)
+    .bind(hash_opaque_token(&token))
+    .bind(verification_token_ttl_hours())
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let body = match render_verification_email(username, email, &token) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to render verification email: {}", e);
+            return Ok(());
+        }
+    };
+    let email = Email::new(
+        email,
+        format!("Verify your email address for {}", site_name()),
+        body,
+    );
+    tokio::spawn(async move {
+        let result = match mailer::from_env() {
+            Ok(mailer) => mailer.send(&email).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to send verification email: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+/// Spends a verification token. The address is only marked verified if it is
+/// still the one on the account, so a link for a replaced address does nothing.
+/// Returns `false` if the token is unknown, used, expired or stale.
+pub async fn verify_token(pool: &DbPool, token: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let row: Option<(i64, i32, String)> = sqlx::query_as(
+        "SELECT id, user_id, email FROM email_verification_tokens
+         WHERE token_hash = ? AND used_at IS NULL AND expires_at > NOW()
+         FOR UPDATE",
+    )
+    .bind(hash_opaque_token(token))
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((id, user_id, email)) = row else {
+        return Ok(false);
+    };
+
+    sqlx::query("UPDATE email_verification_tokens SET used_at = NOW() WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    let updated = sqlx::query(
+        "UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW())
+         WHERE id = ? AND email = ?",
+    )
+    .bind(user_id)
+    .bind(&email)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(updated.rows_affected() > 0)
+}
+
+pub async fn is_verified(pool: &DbPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    let verified: Option<(bool,)> =
+        sqlx::query_as("SELECT email_verified_at IS NOT NULL FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(verified.is_some_and(|(verified,)| verified))
+}
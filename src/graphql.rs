@@ -0,0 +1,691 @@
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, ErrorExtensions, InputObject, Object, Schema,
+    SimpleObject,
+};
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+use crate::auth::{self, Claims, Permission};
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::handlers::post_handler;
+use crate::models::{
+    CreatePostRequest, ErrorResponse, PostResponse, TocEntry, UpdatePostRequest,
+    POST_RESPONSE_SELECT,
+};
+use crate::tokens;
+
+pub type BlogSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+const MAX_PAGE_SIZE: i32 = 100;
+
+pub fn schema() -> &'static BlogSchema {
+    static SCHEMA: OnceLock<BlogSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+            .limit_depth(10)
+            .limit_complexity(500)
+            .finish()
+    })
+}
+
+/// Attaches the pool, the caller's claims and fresh per-request loaders.
+pub fn prepare_request(
+    request: async_graphql::Request,
+    pool: DbPool,
+    claims: Option<Claims>,
+    client_ip: ClientIp,
+) -> async_graphql::Request {
+    let mut request = request
+        .data(DataLoader::new(BlogLoader(pool.clone()), tokio::spawn))
+        .data(pool)
+        .data(client_ip);
+    if let Some(claims) = claims {
+        request = request.data(claims);
+    }
+    request
+}
+
+fn client_ip(ctx: &Context<'_>) -> ClientIp {
+    ctx.data_opt::<ClientIp>()
+        .copied()
+        .unwrap_or(ClientIp(None))
+}
+
+fn to_graphql_error(
+    (status, Json(body)): (StatusCode, Json<ErrorResponse>),
+) -> async_graphql::Error {
+    async_graphql::Error::new(body.error).extend_with(|_, e| e.set("status", status.as_u16()))
+}
+
+fn database_error(e: Arc<sqlx::Error>) -> async_graphql::Error {
+    async_graphql::Error::new(format!("Database error: {e}"))
+}
+
+/// Fields that need a signed-in caller run the token revocation check and
+/// `auth::check_account_access`, like routes behind `auth::auth_middleware`.
+async fn require_claims(ctx: &Context<'_>) -> async_graphql::Result<Claims> {
+    let claims = ctx.data_opt::<Claims>().cloned().ok_or_else(|| {
+        async_graphql::Error::new("Authentication required")
+            .extend_with(|_, e| e.set("status", StatusCode::UNAUTHORIZED.as_u16()))
+    })?;
+
+    let pool = ctx.data::<DbPool>()?;
+    let active = tokens::is_access_token_active(pool, &claims)
+        .await
+        .map_err(|e| database_error(Arc::new(e)))?;
+
+    if !active {
+        return Err(async_graphql::Error::new("Authentication required")
+            .extend_with(|_, e| e.set("status", StatusCode::UNAUTHORIZED.as_u16())));
+    }
+
+    let operation = format!("GraphQL {}", ctx.field().name());
+    auth::check_account_access(pool, &claims, client_ip(ctx).0, &operation)
+        .await
+        .map_err(|status| {
+            let message = match status {
+                StatusCode::UNAUTHORIZED => "Authentication required",
+                StatusCode::FORBIDDEN => "This account is suspended or banned",
+                _ => "Internal server error",
+            };
+            async_graphql::Error::new(message).extend_with(|_, e| e.set("status", status.as_u16()))
+        })?;
+
+    Ok(claims)
+}
+
+/// `require_claims` plus the permission check `auth::require_permission` applies to routes.
+async fn require_permission(
+    ctx: &Context<'_>,
+    permission: Permission,
+) -> async_graphql::Result<Claims> {
+    let claims = require_claims(ctx).await?;
+    if !claims.has_permission(permission) {
+        return Err(
+            async_graphql::Error::new(format!("Missing permission: {permission}"))
+                .extend_with(|_, e| e.set("status", StatusCode::FORBIDDEN.as_u16())),
+        );
+    }
+    Ok(claims)
+}
+
+pub fn claims_from_header(value: Option<&str>) -> Result<Option<Claims>, StatusCode> {
+    match value {
+        Some(header) if header.starts_with("Bearer ") => auth::verify_token(&header[7..])
+            .map(Some)
+            .map_err(|_| StatusCode::UNAUTHORIZED),
+        Some(_) => Err(StatusCode::UNAUTHORIZED),
+        None => Ok(None),
+    }
+}
+
+#[derive(Clone, SimpleObject)]
+#[graphql(complex)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl User {
+    async fn posts(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Post>> {
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        let posts = loader
+            .load_one(AuthorPostsKey(self.id))
+            .await
+            .map_err(database_error)?
+            .unwrap_or_default();
+        Ok(posts.into_iter().map(Post).collect())
+    }
+}
+
+#[derive(Clone, SimpleObject)]
+pub struct Comment {
+    pub id: i64,
+    pub author_name: String,
+    pub actor_uri: Option<String>,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, SimpleObject)]
+pub struct Heading {
+    pub level: i32,
+    pub text: String,
+    pub anchor: String,
+}
+
+impl From<&TocEntry> for Heading {
+    fn from(entry: &TocEntry) -> Self {
+        Self {
+            level: entry.level as i32,
+            text: entry.text.clone(),
+            anchor: entry.anchor.clone(),
+        }
+    }
+}
+
+#[derive(Clone, SimpleObject)]
+pub struct Translation {
+    pub id: i32,
+    pub language: String,
+    pub title: String,
+}
+
+#[derive(Clone, SimpleObject)]
+pub struct Webmention {
+    pub id: i64,
+    pub source_url: String,
+    pub title: Option<String>,
+    pub verified_at: DateTime<Utc>,
+}
+
+pub struct Post(pub PostResponse);
+
+#[Object]
+impl Post {
+    async fn id(&self) -> i32 {
+        self.0.id
+    }
+
+    async fn title(&self) -> &str {
+        &self.0.title
+    }
+
+    async fn content(&self) -> &str {
+        &self.0.content
+    }
+
+    async fn excerpt(&self) -> &str {
+        &self.0.excerpt
+    }
+
+    async fn reading_time_minutes(&self) -> i32 {
+        self.0.reading_time_minutes
+    }
+
+    async fn toc(&self) -> Vec<Heading> {
+        self.0.toc.iter().map(Heading::from).collect()
+    }
+
+    async fn language(&self) -> &str {
+        &self.0.language
+    }
+
+    async fn pinned(&self) -> bool {
+        self.0.pinned
+    }
+
+    async fn featured_slot(&self) -> Option<i32> {
+        self.0.featured_slot
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.0.updated_at
+    }
+
+    async fn author(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<User>> {
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        loader
+            .load_one(UserKey(self.0.user_id))
+            .await
+            .map_err(database_error)
+    }
+
+    async fn comments(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Comment>> {
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        Ok(loader
+            .load_one(CommentsKey(self.0.id))
+            .await
+            .map_err(database_error)?
+            .unwrap_or_default())
+    }
+
+    async fn like_count(&self, ctx: &Context<'_>) -> async_graphql::Result<i64> {
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        Ok(loader
+            .load_one(LikeCountKey(self.0.id))
+            .await
+            .map_err(database_error)?
+            .unwrap_or(0))
+    }
+
+    async fn webmentions(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Webmention>> {
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        Ok(loader
+            .load_one(WebmentionsKey(self.0.id))
+            .await
+            .map_err(database_error)?
+            .unwrap_or_default())
+    }
+
+    async fn translations(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Translation>> {
+        let Some(group) = self.0.translation_group else {
+            return Ok(vec![Translation {
+                id: self.0.id,
+                language: self.0.language.clone(),
+                title: self.0.title.clone(),
+            }]);
+        };
+
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        Ok(loader
+            .load_one(TranslationsKey(group))
+            .await
+            .map_err(database_error)?
+            .unwrap_or_default())
+    }
+}
+
+#[derive(InputObject)]
+pub struct CreatePostInput {
+    pub title: String,
+    pub content: String,
+    pub excerpt: Option<String>,
+    pub language: Option<String>,
+    pub translation_of: Option<i32>,
+}
+
+#[derive(InputObject)]
+pub struct UpdatePostInput {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub excerpt: Option<String>,
+    pub language: Option<String>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn post(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Option<Post>> {
+        let pool = ctx.data::<DbPool>()?;
+        let post =
+            sqlx::query_as::<_, PostResponse>(&format!("{POST_RESPONSE_SELECT} WHERE p.id = ?"))
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+                .map_err(|e| database_error(Arc::new(e)))?;
+        Ok(post.map(Post))
+    }
+
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        author_id: Option<i32>,
+        language: Option<String>,
+        #[graphql(default = 20)] limit: i32,
+        #[graphql(default = 0)] offset: i32,
+    ) -> async_graphql::Result<Vec<Post>> {
+        let pool = ctx.data::<DbPool>()?;
+
+        let mut conditions = Vec::new();
+        if author_id.is_some() {
+            conditions.push("p.user_id = ?");
+        }
+        if language.is_some() {
+            conditions.push("p.language = ?");
+        }
+
+        let mut sql = POST_RESPONSE_SELECT.to_string();
+        if !conditions.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        sql.push_str(" ORDER BY p.created_at DESC LIMIT ? OFFSET ?");
+
+        let mut query = sqlx::query_as::<_, PostResponse>(&sql);
+        if let Some(author_id) = author_id {
+            query = query.bind(author_id);
+        }
+        if let Some(language) = language {
+            query = query.bind(language);
+        }
+
+        let posts = query
+            .bind(limit.clamp(1, MAX_PAGE_SIZE))
+            .bind(offset.max(0))
+            .fetch_all(pool)
+            .await
+            .map_err(|e| database_error(Arc::new(e)))?;
+
+        Ok(posts.into_iter().map(Post).collect())
+    }
+
+    async fn user(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Option<User>> {
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        loader.load_one(UserKey(id)).await.map_err(database_error)
+    }
+
+    async fn user_by_username(
+        &self,
+        ctx: &Context<'_>,
+        username: String,
+    ) -> async_graphql::Result<Option<User>> {
+        let pool = ctx.data::<DbPool>()?;
+        let user: Option<(i32, String, DateTime<Utc>)> =
+            sqlx::query_as("SELECT id, username, created_at FROM users WHERE username = ?")
+                .bind(username)
+                .fetch_optional(pool)
+                .await
+                .map_err(|e| database_error(Arc::new(e)))?;
+
+        Ok(user.map(|(id, username, created_at)| User {
+            id,
+            username,
+            created_at,
+        }))
+    }
+
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<User>> {
+        let claims = require_claims(ctx).await?;
+        let loader = ctx.data::<DataLoader<BlogLoader>>()?;
+        loader
+            .load_one(UserKey(claims.sub))
+            .await
+            .map_err(database_error)
+    }
+}
+
+pub struct MutationRoot;
+
+/// Mutations delegate to the REST handlers so validation and ownership rules stay in one place.
+#[Object]
+impl MutationRoot {
+    async fn create_post(
+        &self,
+        ctx: &Context<'_>,
+        input: CreatePostInput,
+    ) -> async_graphql::Result<Post> {
+        let claims = require_permission(ctx, Permission::PostsWrite).await?;
+        let pool = ctx.data::<DbPool>()?.clone();
+
+        let Json(post) = post_handler::create_post(
+            State(pool),
+            Extension(claims),
+            client_ip(ctx),
+            Json(CreatePostRequest {
+                title: input.title,
+                content: input.content,
+                excerpt: input.excerpt,
+                language: input.language,
+                translation_of: input.translation_of,
+            }),
+        )
+        .await
+        .map_err(to_graphql_error)?;
+
+        Ok(Post(post))
+    }
+
+    async fn update_post(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        input: UpdatePostInput,
+    ) -> async_graphql::Result<Post> {
+        let claims = require_permission(ctx, Permission::PostsWrite).await?;
+        let pool = ctx.data::<DbPool>()?.clone();
+
+        let Json(post) = post_handler::update_post(
+            State(pool),
+            Extension(claims),
+            client_ip(ctx),
+            axum::extract::Path(id),
+            Json(UpdatePostRequest {
+                title: input.title,
+                content: input.content,
+                excerpt: input.excerpt,
+                language: input.language,
+            }),
+        )
+        .await
+        .map_err(to_graphql_error)?;
+
+        Ok(Post(post))
+    }
+
+    async fn delete_post(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<bool> {
+        let claims = require_permission(ctx, Permission::PostsWrite).await?;
+        let pool = ctx.data::<DbPool>()?.clone();
+
+        post_handler::delete_post(
+            State(pool),
+            Extension(claims),
+            client_ip(ctx),
+            axum::extract::Path(id),
+        )
+        .await
+        .map_err(to_graphql_error)?;
+
+        Ok(true)
+    }
+}
+
+pub struct BlogLoader(DbPool);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UserKey(pub i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AuthorPostsKey(pub i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CommentsKey(pub i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LikeCountKey(pub i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WebmentionsKey(pub i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TranslationsKey(pub i32);
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+impl Loader<UserKey> for BlogLoader {
+    type Value = User;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[UserKey]) -> Result<HashMap<UserKey, User>, Self::Error> {
+        let sql = format!(
+            "SELECT id, username, created_at FROM users WHERE id IN ({})",
+            placeholders(keys.len())
+        );
+        let mut query = sqlx::query_as::<_, (i32, String, DateTime<Utc>)>(&sql);
+        for key in keys {
+            query = query.bind(key.0);
+        }
+
+        Ok(query
+            .fetch_all(&self.0)
+            .await?
+            .into_iter()
+            .map(|(id, username, created_at)| {
+                (
+                    UserKey(id),
+                    User {
+                        id,
+                        username,
+                        created_at,
+                    },
+                )
+            })
+            .collect())
+    }
+}
+
+impl Loader<AuthorPostsKey> for BlogLoader {
+    type Value = Vec<PostResponse>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(
+        &self,
+        keys: &[AuthorPostsKey],
+    ) -> Result<HashMap<AuthorPostsKey, Vec<PostResponse>>, Self::Error> {
+        let sql = format!(
+            "{POST_RESPONSE_SELECT} WHERE p.user_id IN ({}) ORDER BY p.created_at DESC",
+            placeholders(keys.len())
+        );
+        let mut query = sqlx::query_as::<_, PostResponse>(&sql);
+        for key in keys {
+            query = query.bind(key.0);
+        }
+
+        let mut grouped: HashMap<AuthorPostsKey, Vec<PostResponse>> = HashMap::new();
+        for post in query.fetch_all(&self.0).await? {
+            grouped
+                .entry(AuthorPostsKey(post.user_id))
+                .or_default()
+                .push(post);
+        }
+        Ok(grouped)
+    }
+}
+
+impl Loader<CommentsKey> for BlogLoader {
+    type Value = Vec<Comment>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(
+        &self,
+        keys: &[CommentsKey],
+    ) -> Result<HashMap<CommentsKey, Vec<Comment>>, Self::Error> {
+        let sql = format!(
+            "SELECT id, post_id, author_name, actor_uri, content, created_at
+             FROM post_comments
+             WHERE post_id IN ({})
+             ORDER BY created_at",
+            placeholders(keys.len())
+        );
+        let mut query =
+            sqlx::query_as::<_, (i64, i32, String, Option<String>, String, DateTime<Utc>)>(&sql);
+        for key in keys {
+            query = query.bind(key.0);
+        }
+
+        let mut grouped: HashMap<CommentsKey, Vec<Comment>> = HashMap::new();
+        for (id, post_id, author_name, actor_uri, content, created_at) in
+            query.fetch_all(&self.0).await?
+        {
+            grouped
+                .entry(CommentsKey(post_id))
+                .or_default()
+                .push(Comment {
+                    id,
+                    author_name,
+                    actor_uri,
+                    content,
+                    created_at,
+                });
+        }
+        Ok(grouped)
+    }
+}
+
+impl Loader<LikeCountKey> for BlogLoader {
+    type Value = i64;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[LikeCountKey]) -> Result<HashMap<LikeCountKey, i64>, Self::Error> {
+        let sql = format!(
+            "SELECT post_id, COUNT(*) FROM post_reactions
+             WHERE kind = 'like' AND post_id IN ({})
+             GROUP BY post_id",
+            placeholders(keys.len())
+        );
+        let mut query = sqlx::query_as::<_, (i32, i64)>(&sql);
+        for key in keys {
+            query = query.bind(key.0);
+        }
+
+        Ok(query
+            .fetch_all(&self.0)
+            .await?
+            .into_iter()
+            .map(|(post_id, count)| (LikeCountKey(post_id), count))
+            .collect())
+    }
+}
+
+impl Loader<WebmentionsKey> for BlogLoader {
+    type Value = Vec<Webmention>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(
+        &self,
+        keys: &[WebmentionsKey],
+    ) -> Result<HashMap<WebmentionsKey, Vec<Webmention>>, Self::Error> {
+        let sql = format!(
+            "SELECT post_id, id, source_url, title, verified_at
+             FROM webmentions
+             WHERE status = 'verified' AND post_id IN ({})
+             ORDER BY verified_at DESC",
+            placeholders(keys.len())
+        );
+        let mut query =
+            sqlx::query_as::<_, (i32, i64, String, Option<String>, DateTime<Utc>)>(&sql);
+        for key in keys {
+            query = query.bind(key.0);
+        }
+
+        let mut grouped: HashMap<WebmentionsKey, Vec<Webmention>> = HashMap::new();
+        for (post_id, id, source_url, title, verified_at) in query.fetch_all(&self.0).await? {
+            grouped
+                .entry(WebmentionsKey(post_id))
+                .or_default()
+                .push(Webmention {
+                    id,
+                    source_url,
+                    title,
+                    verified_at,
+                });
+        }
+        Ok(grouped)
+    }
+}
+
+impl Loader<TranslationsKey> for BlogLoader {
+    type Value = Vec<Translation>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(
+        &self,
+        keys: &[TranslationsKey],
+    ) -> Result<HashMap<TranslationsKey, Vec<Translation>>, Self::Error> {
+        let sql = format!(
+            "SELECT translation_group, id, language, title FROM posts
+             WHERE translation_group IN ({})
+             ORDER BY id",
+            placeholders(keys.len())
+        );
+        let mut query = sqlx::query_as::<_, (i32, i32, String, String)>(&sql);
+        for key in keys {
+            query = query.bind(key.0);
+        }
+
+        let mut grouped: HashMap<TranslationsKey, Vec<Translation>> = HashMap::new();
+        for (group, id, language, title) in query.fetch_all(&self.0).await? {
+            grouped
+                .entry(TranslationsKey(group))
+                .or_default()
+                .push(Translation {
+                    id,
+                    language,
+                    title,
+                });
+        }
+        Ok(grouped)
+    }
+}
@@ -0,0 +1,306 @@
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use validator::Validate;
+
+use crate::accounts;
+use crate::api_keys;
+use crate::audit::{self, AuditEvent};
+use crate::auth::{hash_password, verify_password, Claims};
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::email_verification;
+use crate::handlers::user_handler::issue_tokens;
+use crate::models::{
+    AccountDeletionResponse, AccountResponse, AuthResponse, ChangeEmailRequest,
+    ChangePasswordRequest, DeleteAccountRequest, ErrorResponse, User, UserResponse, USER_SELECT,
+};
+use crate::password_policy;
+use crate::sessions::Device;
+use crate::tokens;
+
+pub(crate) async fn fetch_user(
+    pool: &DbPool,
+    user_id: i32,
+) -> Result<User, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as::<_, User>(&format!("{USER_SELECT} WHERE id = ?"))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new("User not found")),
+            )
+        })
+}
+
+/// Sensitive account changes need the current password on top of the token.
+pub(crate) async fn fetch_user_with_password(
+    pool: &DbPool,
+    user_id: i32,
+    password: &str,
+) -> Result<User, (StatusCode, Json<ErrorResponse>)> {
+    let user = fetch_user(pool, user_id).await?;
+
+    let valid = verify_password(password, &user.password_hash).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!(
+                "Password verification error: {e}"
+            ))),
+        )
+    })?;
+
+    if !valid {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("Current password is incorrect")),
+        ));
+    }
+
+    Ok(user)
+}
+
+pub async fn get_me(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<AccountResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = fetch_user(&pool, claims.sub).await?;
+
+    let deletion_scheduled_at: Option<chrono::DateTime<chrono::Utc>> =
+        sqlx::query_scalar("SELECT deletion_scheduled_at FROM users WHERE id = ?")
+            .bind(claims.sub)
+            .fetch_one(&pool)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(format!("Database error: {e}"))),
+                )
+            })?;
+
+    Ok(Json(AccountResponse {
+        created_at: user.created_at,
+        deletion_scheduled_at,
+        user: user.into(),
+    }))
+}
+
+/// Signs out every other session, revokes the user's API keys and hands the
+/// caller a fresh token pair.
+pub async fn change_password(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    device: Device,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<AuthResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+
+    let user = fetch_user_with_password(&pool, claims.sub, &payload.current_password).await?;
+
+    password_policy::check(&payload.new_password, &user.username, &user.email)
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(ErrorResponse::new(message))))?;
+
+    let password_hash = hash_password(&payload.new_password).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Password hashing error: {e}"))),
+        )
+    })?;
+
+    sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+        .bind(&password_hash)
+        .bind(user.id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    tokens::revoke_all_sessions(&pool, user.id)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    api_keys::revoke_all(&pool, user.id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let user_id = user.id;
+    let response = issue_tokens(&pool, user.into(), &device, claims.mfa).await?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(user_id),
+            action: "auth.password_change",
+            target: format!("user:{user_id}"),
+            ip: device.ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(response))
+}
+
+/// The new address starts out unverified and gets a fresh verification link.
+pub async fn change_email(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ChangeEmailRequest>,
+) -> Result<Json<UserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+
+    let user = fetch_user_with_password(&pool, claims.sub, &payload.current_password).await?;
+
+    if user.email == payload.email {
+        return Ok(Json(user.into()));
+    }
+
+    let taken: Option<(i32,)> = sqlx::query_as("SELECT id FROM users WHERE email = ?")
+        .bind(&payload.email)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if taken.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new("Email already exists")),
+        ));
+    }
+
+    sqlx::query("UPDATE users SET email = ?, email_verified_at = NULL WHERE id = ?")
+        .bind(&payload.email)
+        .bind(user.id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    email_verification::send_verification(&pool, user.id, &user.username, &payload.email)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(user.id),
+            action: "account.email_change",
+            target: format!("user:{}", user.id),
+            ip,
+            request_digest: Some(audit::digest(&serde_json::json!({
+                "old_email": user.email,
+                "new_email": payload.email,
+            }))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let user = fetch_user(&pool, user.id).await?;
+
+    Ok(Json(user.into()))
+}
+
+/// Schedules the account for deletion, signs it out everywhere and revokes its
+/// API keys; signing in again before the grace period ends cancels the
+/// request.
+pub async fn delete_me(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> Result<(StatusCode, Json<AccountDeletionResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let user = fetch_user_with_password(&pool, claims.sub, &payload.current_password).await?;
+
+    let delete_after = accounts::schedule_deletion(&pool, user.id, payload.posts)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    tokens::revoke_all_sessions(&pool, user.id)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    api_keys::revoke_all(&pool, user.id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(user.id),
+            action: "auth.account_delete",
+            target: format!("user:{}", user.id),
+            ip,
+            request_digest: Some(audit::digest(&serde_json::json!({
+                "posts": payload.posts,
+                "delete_after": delete_after,
+            }))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(AccountDeletionResponse {
+            delete_after,
+            posts: payload.posts,
+        }),
+    ))
+}
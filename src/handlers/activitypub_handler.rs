@@ -0,0 +1,459 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, Method, StatusCode, Uri},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use crate::activitypub::{self, ACTIVITY_JSON};
+use crate::auth::generate_opaque_token;
+use crate::config::base_url;
+use crate::db::DbPool;
+use crate::models::{ErrorResponse, WebFingerQuery};
+
+const OUTBOX_PAGE_SIZE: i64 = 50;
+
+type ApResponse = ([(axum::http::HeaderName, &'static str); 1], Json<Value>);
+
+type OutboxRow = (i32, String, String, DateTime<Utc>, DateTime<Utc>);
+
+fn activity_json(value: Value) -> ApResponse {
+    ([(CONTENT_TYPE, ACTIVITY_JSON)], Json(value))
+}
+
+async fn find_user(
+    pool: &DbPool,
+    username: &str,
+) -> Result<(i32, DateTime<Utc>), (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as("SELECT id, created_at FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new("User not found")),
+            )
+        })
+}
+
+pub async fn webfinger(
+    State(pool): State<DbPool>,
+    Query(query): Query<WebFingerQuery>,
+) -> Result<ApResponse, (StatusCode, Json<ErrorResponse>)> {
+    let domain = activitypub::domain();
+    let username = query
+        .resource
+        .strip_prefix("acct:")
+        .and_then(|acct| acct.strip_suffix(&format!("@{domain}")))
+        .or_else(|| {
+            query
+                .resource
+                .strip_prefix(&format!("{}/users/", base_url()))
+        })
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new("Unknown resource")),
+            )
+        })?
+        .to_string();
+
+    find_user(&pool, &username).await?;
+
+    let actor = activitypub::actor_url(&username);
+    Ok((
+        [(CONTENT_TYPE, "application/jrd+json")],
+        Json(json!({
+            "subject": format!("acct:{username}@{domain}"),
+            "aliases": [actor],
+            "links": [
+                {
+                    "rel": "self",
+                    "type": ACTIVITY_JSON,
+                    "href": actor
+                },
+                {
+                    "rel": "http://webfinger.net/rel/profile-page",
+                    "type": "text/html",
+                    "href": actor
+                }
+            ]
+        })),
+    ))
+}
+
+pub async fn get_actor(
+    State(pool): State<DbPool>,
+    Path(username): Path<String>,
+) -> Result<ApResponse, (StatusCode, Json<ErrorResponse>)> {
+    let (user_id, created_at) = find_user(&pool, &username).await?;
+
+    let keys = activitypub::actor_keys(&pool, user_id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(e.to_string())),
+        )
+    })?;
+
+    Ok(activity_json(activitypub::person(
+        &username,
+        created_at,
+        &keys.public_key_pem,
+    )))
+}
+
+pub async fn get_outbox(
+    State(pool): State<DbPool>,
+    Path(username): Path<String>,
+) -> Result<ApResponse, (StatusCode, Json<ErrorResponse>)> {
+    let (user_id, _) = find_user(&pool, &username).await?;
+
+    let (total,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM posts WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    let posts: Vec<OutboxRow> = sqlx::query_as(
+        "SELECT id, title, content, created_at, updated_at
+         FROM posts
+         WHERE user_id = ?
+         ORDER BY created_at DESC
+         LIMIT ?",
+    )
+    .bind(user_id)
+    .bind(OUTBOX_PAGE_SIZE)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let actor = activitypub::actor_url(&username);
+    let items: Vec<Value> = posts
+        .into_iter()
+        .map(|(id, title, content, created_at, updated_at)| {
+            let object =
+                activitypub::article(id, &username, &title, &content, created_at, updated_at);
+            let mut activity = activitypub::wrap_activity("Create", &actor, object);
+            activity["id"] = json!(format!(
+                "{}#create",
+                activitypub::article_url(&username, id)
+            ));
+            activity["published"] = json!(created_at.to_rfc3339());
+            activity
+        })
+        .collect();
+
+    Ok(activity_json(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{actor}/outbox"),
+        "type": "OrderedCollection",
+        "totalItems": total,
+        "orderedItems": items
+    })))
+}
+
+pub async fn get_followers(
+    State(pool): State<DbPool>,
+    Path(username): Path<String>,
+) -> Result<ApResponse, (StatusCode, Json<ErrorResponse>)> {
+    let (user_id, _) = find_user(&pool, &username).await?;
+
+    let (total,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM ap_followers WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    Ok(activity_json(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/followers", activitypub::actor_url(&username)),
+        "type": "OrderedCollection",
+        "totalItems": total
+    })))
+}
+
+pub async fn get_article(
+    State(pool): State<DbPool>,
+    Path((username, post_id)): Path<(String, i32)>,
+) -> Result<ApResponse, (StatusCode, Json<ErrorResponse>)> {
+    let (user_id, _) = find_user(&pool, &username).await?;
+
+    let (title, content, created_at, updated_at): (String, String, DateTime<Utc>, DateTime<Utc>) =
+        sqlx::query_as(
+            "SELECT title, content, created_at, updated_at FROM posts WHERE id = ? AND user_id = ?",
+        )
+        .bind(post_id)
+        .bind(user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new("Post not found")),
+            )
+        })?;
+
+    let mut object =
+        activitypub::article(post_id, &username, &title, &content, created_at, updated_at);
+    object["@context"] = json!("https://www.w3.org/ns/activitystreams");
+
+    Ok(activity_json(object))
+}
+
+fn object_id(object: &Value) -> Option<&str> {
+    object
+        .as_str()
+        .or_else(|| object.get("id").and_then(Value::as_str))
+}
+
+pub async fn inbox(
+    State(pool): State<DbPool>,
+    Path(username): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let (user_id, _) = find_user(&pool, &username).await?;
+
+    let path_and_query = uri
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| uri.path().to_string());
+
+    let activity: Value = serde_json::from_slice(&body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Invalid activity: {e}"))),
+        )
+    })?;
+    let actor_id = activity
+        .get("actor")
+        .and_then(Value::as_str)
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new("Activity is missing actor")),
+            )
+        })?;
+
+    let remote =
+        activitypub::verify_request(method.as_str(), &path_and_query, &headers, &body, actor_id)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(ErrorResponse::new(format!("Invalid signature: {e}"))),
+                )
+            })?;
+
+    let activity_id = activity
+        .get("id")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let object = activity.get("object").cloned().unwrap_or(Value::Null);
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    };
+
+    match activity.get("type").and_then(Value::as_str) {
+        Some("Follow") => {
+            if object_id(&object) != Some(activitypub::actor_url(&username).as_str()) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new(
+                        "Follow target does not match inbox owner",
+                    )),
+                ));
+            }
+
+            sqlx::query(
+                "INSERT INTO ap_followers (user_id, actor_uri, inbox_url, shared_inbox_url, follow_activity_id)
+                 VALUES (?, ?, ?, ?, ?)
+                 ON DUPLICATE KEY UPDATE inbox_url = VALUES(inbox_url),
+                     shared_inbox_url = VALUES(shared_inbox_url),
+                     follow_activity_id = VALUES(follow_activity_id)",
+            )
+            .bind(user_id)
+            .bind(&remote.id)
+            .bind(&remote.inbox)
+            .bind(&remote.shared_inbox)
+            .bind(&activity_id)
+            .execute(&pool)
+            .await
+            .map_err(db_error)?;
+
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let actor = activitypub::actor_url(&username);
+                let accept = json!({
+                    "@context": "https://www.w3.org/ns/activitystreams",
+                    "id": format!("{actor}#accepts/{}", generate_opaque_token()),
+                    "type": "Accept",
+                    "actor": actor,
+                    "object": activity
+                });
+                let result = match activitypub::actor_keys(&pool, user_id).await {
+                    Ok(keys) => {
+                        activitypub::deliver(
+                            &remote.inbox,
+                            &accept,
+                            &format!("{actor}#main-key"),
+                            &keys.private_key_pem,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    tracing::warn!("Failed to accept follow from {}: {}", remote.id, e);
+                }
+            });
+        }
+        Some("Undo") => {
+            let undone = object_id(&object).unwrap_or_default();
+            match object.get("type").and_then(Value::as_str) {
+                Some("Like") => {
+                    sqlx::query(
+                        "DELETE FROM post_reactions WHERE actor_uri = ? AND activity_id = ?",
+                    )
+                    .bind(&remote.id)
+                    .bind(undone)
+                    .execute(&pool)
+                    .await
+                    .map_err(db_error)?;
+                }
+                Some("Follow") => {
+                    sqlx::query("DELETE FROM ap_followers WHERE user_id = ? AND actor_uri = ?")
+                        .bind(user_id)
+                        .bind(&remote.id)
+                        .execute(&pool)
+                        .await
+                        .map_err(db_error)?;
+                }
+                _ => {
+                    sqlx::query(
+                        "DELETE FROM ap_followers
+                         WHERE user_id = ? AND actor_uri = ? AND follow_activity_id = ?",
+                    )
+                    .bind(user_id)
+                    .bind(&remote.id)
+                    .bind(undone)
+                    .execute(&pool)
+                    .await
+                    .map_err(db_error)?;
+                    sqlx::query(
+                        "DELETE FROM post_reactions WHERE actor_uri = ? AND activity_id = ?",
+                    )
+                    .bind(&remote.id)
+                    .bind(undone)
+                    .execute(&pool)
+                    .await
+                    .map_err(db_error)?;
+                }
+            }
+        }
+        Some("Like") => {
+            if let Some(post_id) = object_id(&object).and_then(activitypub::post_id_from_object) {
+                sqlx::query(
+                    "INSERT IGNORE INTO post_reactions (post_id, actor_uri, activity_id, kind)
+                     SELECT id, ?, ?, 'like' FROM posts WHERE id = ?",
+                )
+                .bind(&remote.id)
+                .bind(&activity_id)
+                .bind(post_id)
+                .execute(&pool)
+                .await
+                .map_err(db_error)?;
+            }
+        }
+        // Followers-only replies and direct messages are not published as
+        // comments.
+        Some("Create")
+            if object.get("type").and_then(Value::as_str) == Some("Note")
+                && activitypub::is_public(&object) =>
+        {
+            let post_id = object
+                .get("inReplyTo")
+                .and_then(Value::as_str)
+                .and_then(activitypub::post_id_from_object);
+            let content = object
+                .get("content")
+                .and_then(Value::as_str)
+                .map(activitypub::strip_html)
+                .unwrap_or_default();
+
+            if let (Some(post_id), false) = (post_id, content.is_empty()) {
+                let author_name = remote
+                    .preferred_username
+                    .clone()
+                    .unwrap_or_else(|| remote.id.clone());
+                sqlx::query(
+                    "INSERT IGNORE INTO post_comments (post_id, author_name, actor_uri, activity_id, content)
+                     SELECT id, ?, ?, ?, ? FROM posts WHERE id = ?",
+                )
+                .bind(&author_name)
+                .bind(&remote.id)
+                .bind(object_id(&object).unwrap_or(&activity_id))
+                .bind(&content)
+                .bind(post_id)
+                .execute(&pool)
+                .await
+                .map_err(db_error)?;
+            }
+        }
+        Some("Delete") => {
+            if let Some(deleted) = object_id(&object) {
+                sqlx::query("DELETE FROM post_comments WHERE actor_uri = ? AND activity_id = ?")
+                    .bind(&remote.id)
+                    .bind(deleted)
+                    .execute(&pool)
+                    .await
+                    .map_err(db_error)?;
+            }
+        }
+        _ => {
+            tracing::debug!(
+                "Ignoring unsupported activity {} from {}",
+                activity_id,
+                remote.id
+            );
+        }
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,404 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::Utc;
+use validator::Validate;
+
+use crate::api_keys;
+use crate::audit::{self, AuditEvent};
+use crate::auth::{
+    create_impersonation_token, impersonation_ttl_secs, role_permissions, Claims, Permission, ROLES,
+};
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::handlers::password_handler::send_reset_email;
+use crate::models::{
+    AdminUserResponse, AssignRoleRequest, BanUserRequest, ErrorResponse, ImpersonationResponse,
+    ModeratedUser, RoleResponse, SuspendUserRequest, User, UserResponse, UserSearchQuery,
+    USER_SELECT,
+};
+use crate::moderation;
+use crate::tokens;
+
+pub async fn list_roles() -> Json<Vec<RoleResponse>> {
+    Json(
+        ROLES
+            .iter()
+            .map(|role| RoleResponse {
+                role,
+                permissions: role_permissions(role),
+            })
+            .collect(),
+    )
+}
+
+pub async fn assign_role(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(user_id): Path<i32>,
+    Json(payload): Json<AssignRoleRequest>,
+) -> Result<Json<UserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !ROLES.contains(&payload.role.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!(
+                "Unknown role: {} (expected one of {})",
+                payload.role,
+                ROLES.join(", ")
+            ))),
+        ));
+    }
+
+    // An admin demoting themselves could leave the site without one.
+    if user_id == claims.sub {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("You cannot change your own role")),
+        ));
+    }
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let old_role: String = sqlx::query_scalar("SELECT role FROM users WHERE id = ? FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(user_not_found)?;
+
+    sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+        .bind(&payload.role)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "admin.role_assign",
+            target: format!("user:{user_id}"),
+            ip,
+            request_digest: Some(audit::digest(&serde_json::json!({
+                "old_role": old_role,
+                "new_role": payload.role,
+            }))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // Tokens carry the role, so outstanding ones must be re-issued.
+    tokens::expire_access_tokens(&pool, user_id)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    let user: User = sqlx::query_as::<_, User>(&format!("{USER_SELECT} WHERE id = ?"))
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    Ok(Json(user.into()))
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse::new(format!("Database error: {e}"))),
+    )
+}
+
+fn user_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new("User not found")),
+    )
+}
+
+/// Keeps an admin from locking themselves out.
+fn reject_self(
+    claims: &Claims,
+    user_id: i32,
+    message: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if user_id == claims.sub {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse::new(message))));
+    }
+    Ok(())
+}
+
+async fn moderated_user(
+    pool: &DbPool,
+    user_id: i32,
+) -> Result<ModeratedUser, (StatusCode, Json<ErrorResponse>)> {
+    moderation::find(pool, user_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(user_not_found)
+}
+
+pub async fn list_users(
+    State(pool): State<DbPool>,
+    Query(query): Query<UserSearchQuery>,
+) -> Result<Json<Vec<AdminUserResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(status) = &query.status {
+        if !moderation::STATUSES.contains(&status.as_str()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(format!(
+                    "Unknown status: {status} (expected one of {})",
+                    moderation::STATUSES.join(", ")
+                ))),
+            ));
+        }
+    }
+
+    let users = moderation::search(&pool, &query).await.map_err(db_error)?;
+
+    Ok(Json(users.into_iter().map(Into::into).collect()))
+}
+
+/// Blocks the user until `until` and signs them out everywhere.
+pub async fn suspend_user(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(user_id): Path<i32>,
+    Json(payload): Json<SuspendUserRequest>,
+) -> Result<Json<AdminUserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+    if payload.until <= Utc::now() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Suspension must end in the future")),
+        ));
+    }
+    reject_self(&claims, user_id, "You cannot suspend yourself")?;
+
+    if !moderation::suspend(&pool, user_id, payload.until, payload.reason.as_deref())
+        .await
+        .map_err(db_error)?
+    {
+        return Err(user_not_found());
+    }
+    tokens::revoke_all_sessions(&pool, user_id)
+        .await
+        .map_err(db_error)?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "admin.user_suspend",
+            target: format!("user:{user_id}"),
+            ip,
+            request_digest: Some(audit::digest(&serde_json::json!({
+                "until": payload.until,
+                "reason": payload.reason,
+            }))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(moderated_user(&pool, user_id).await?.into()))
+}
+
+/// Blocks the user until reinstated and signs them out everywhere.
+pub async fn ban_user(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(user_id): Path<i32>,
+    payload: Option<Json<BanUserRequest>>,
+) -> Result<Json<AdminUserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let payload = payload.map(|Json(payload)| payload).unwrap_or_default();
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+    reject_self(&claims, user_id, "You cannot ban yourself")?;
+
+    if !moderation::ban(&pool, user_id, payload.reason.as_deref())
+        .await
+        .map_err(db_error)?
+    {
+        return Err(user_not_found());
+    }
+    tokens::revoke_all_sessions(&pool, user_id)
+        .await
+        .map_err(db_error)?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "admin.user_ban",
+            target: format!("user:{user_id}"),
+            ip,
+            request_digest: Some(audit::digest(&serde_json::json!({
+                "reason": payload.reason,
+            }))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(moderated_user(&pool, user_id).await?.into()))
+}
+
+/// Lifts a suspension or ban; the user signs in again as usual.
+pub async fn reinstate_user(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(user_id): Path<i32>,
+) -> Result<Json<AdminUserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !moderation::reinstate(&pool, user_id)
+        .await
+        .map_err(db_error)?
+    {
+        return Err(user_not_found());
+    }
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "admin.user_reinstate",
+            target: format!("user:{user_id}"),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(moderated_user(&pool, user_id).await?.into()))
+}
+
+/// Signs the user out everywhere, deletes their API keys, refuses sign-in
+/// until they set a new password and emails them a reset link.
+pub async fn force_password_reset(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(user_id): Path<i32>,
+) -> Result<Json<AdminUserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    reject_self(
+        &claims,
+        user_id,
+        "Use the password change endpoint for your own account",
+    )?;
+
+    if !moderation::require_password_reset(&pool, user_id)
+        .await
+        .map_err(db_error)?
+    {
+        return Err(user_not_found());
+    }
+    tokens::revoke_all_sessions(&pool, user_id)
+        .await
+        .map_err(db_error)?;
+    api_keys::revoke_all(&pool, user_id)
+        .await
+        .map_err(db_error)?;
+
+    let user = moderated_user(&pool, user_id).await?;
+    send_reset_email(&pool, user_id, &user.user.username, user.user.email.clone()).await?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "admin.password_reset",
+            target: format!("user:{user_id}"),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(user.into()))
+}
+
+/// Issues a short-lived access token to act as the user for support. Every
+/// request made with it is written to the audit log under the admin's id.
+pub async fn impersonate_user(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(user_id): Path<i32>,
+) -> Result<Json<ImpersonationResponse>, (StatusCode, Json<ErrorResponse>)> {
+    reject_self(&claims, user_id, "You cannot impersonate yourself")?;
+
+    let user = moderated_user(&pool, user_id).await?.user;
+
+    // Acting as another admin would hand out their powers without a trace of
+    // who holds them.
+    if role_permissions(&user.role).contains(&Permission::UsersManage) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("Administrators cannot be impersonated")),
+        ));
+    }
+    if let Some(restriction) = moderation::restriction(&pool, user_id)
+        .await
+        .map_err(db_error)?
+        .filter(|restriction| restriction.blocks_requests())
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(restriction.to_string())),
+        ));
+    }
+
+    let token = create_impersonation_token(user.id, &user.username, &user.role, claims.sub)
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Token creation error: {e}"))),
+            )
+        })?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "admin.impersonate",
+            target: format!("user:{user_id}"),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(ImpersonationResponse {
+        token,
+        expires_in: impersonation_ttl_secs(),
+        user: user.into(),
+    }))
+}
@@ -0,0 +1,164 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{Duration, Utc};
+use sqlx::types::Json as SqlJson;
+use validator::Validate;
+
+use crate::api_keys;
+use crate::audit::{self, AuditEvent};
+use crate::auth::{hash_opaque_token, Claims, Permission};
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::models::{ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse, ErrorResponse};
+
+const API_KEY_SELECT: &str =
+    "SELECT id, name, scopes, created_at, expires_at, last_used_at FROM api_keys";
+
+pub async fn create_api_key(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreatedApiKeyResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+
+    let mut scopes: Vec<Permission> = Vec::new();
+    for scope in payload.scopes {
+        if !Permission::API_KEY_SCOPES.contains(&scope) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(format!(
+                    "API keys cannot be scoped to {scope}"
+                ))),
+            ));
+        }
+        // A key never grants more than its creator holds.
+        if !claims.has_permission(scope) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse::new(format!(
+                    "You do not have the {scope} permission"
+                ))),
+            ));
+        }
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let token = api_keys::generate_token();
+    let expires_at = payload
+        .expires_in_days
+        .map(|days| Utc::now() + Duration::days(days));
+
+    let result = sqlx::query(
+        "INSERT INTO api_keys (user_id, name, token_hash, scopes, expires_at)
+         VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(claims.sub)
+    .bind(&payload.name)
+    .bind(hash_opaque_token(&token))
+    .bind(SqlJson(&scopes))
+    .bind(expires_at)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let key = sqlx::query_as::<_, ApiKeyResponse>(&format!("{API_KEY_SELECT} WHERE id = ?"))
+        .bind(result.last_insert_id() as i64)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "auth.api_key_create",
+            target: format!("api_key:{}", key.id),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(CreatedApiKeyResponse { key, token }))
+}
+
+pub async fn list_api_keys(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<ApiKeyResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let keys = sqlx::query_as::<_, ApiKeyResponse>(&format!(
+        "{API_KEY_SELECT} WHERE user_id = ? ORDER BY created_at DESC, id DESC"
+    ))
+    .bind(claims.sub)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(keys))
+}
+
+pub async fn delete_api_key(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let result = sqlx::query("DELETE FROM api_keys WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(claims.sub)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("API key not found")),
+        ));
+    }
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "auth.api_key_delete",
+            target: format!("api_key:{id}"),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
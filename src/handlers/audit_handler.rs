@@ -0,0 +1,49 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::audit::{self, Outcome};
+use crate::db::DbPool;
+use crate::models::{AuditEventResponse, AuditQuery, AuditVerification, ErrorResponse};
+
+pub async fn list_audit_events(
+    State(pool): State<DbPool>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEventResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(outcome) = &query.outcome {
+        if !Outcome::ALL.iter().any(|known| known.as_str() == outcome) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(format!(
+                    "Unknown outcome: {outcome} (expected one of success, denied, failure)"
+                ))),
+            ));
+        }
+    }
+
+    let events = audit::search(&pool, &query).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(events))
+}
+
+/// Recomputes the hash chain; `valid: false` means rows were altered,
+/// removed or inserted out of band.
+pub async fn verify_audit_chain(
+    State(pool): State<DbPool>,
+) -> Result<Json<AuditVerification>, (StatusCode, Json<ErrorResponse>)> {
+    let verification = audit::verify_chain(&pool).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(verification))
+}
@@ -1,4 +1,4 @@
-use axum::{http::StatusCode, Json};
+use axum::{extract::State, http::StatusCode, Extension, Json};
 use ethers::{
     abi::{Abi, Token},
     prelude::*,
@@ -6,10 +6,15 @@ use ethers::{
     signers::{LocalWallet, Signer},
     types::{transaction::eip2718::TypedTransaction, TransactionRequest},
 };
+use serde_json::json;
 use std::env;
 use std::sync::Arc;
 use validator::Validate;
 
+use crate::audit::{self, AuditEvent, Outcome};
+use crate::auth::Claims;
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
 use crate::models::{ContractCallRequest, ContractCallResponse, ErrorResponse};
 
 fn get_rpc_url(chain: &str) -> Result<String, String> {
@@ -71,7 +76,43 @@ fn convert_json_to_token(
 }
 
 pub async fn call_contract(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<ContractCallRequest>,
+) -> Result<Json<ContractCallResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (target, request_digest) = audit::signed_request(
+        &payload.chain,
+        Some(&payload.contract_address),
+        &json!({
+            "chain": payload.chain,
+            "contract_address": payload.contract_address,
+            "abi": payload.abi,
+            "function_name": payload.function_name,
+            "params": payload.params,
+            "value": payload.value,
+        }),
+    );
+    let result = execute_call(payload).await;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "contract.call",
+            target,
+            ip,
+            outcome: Outcome::of(&result),
+            request_digest: Some(request_digest),
+        },
+    )
+    .await;
+
+    result
+}
+
+async fn execute_call(
+    payload: ContractCallRequest,
 ) -> Result<Json<ContractCallResponse>, (StatusCode, Json<ErrorResponse>)> {
     if let Err(errors) = payload.validate() {
         return Err((
@@ -0,0 +1,256 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use std::env;
+use validator::Validate;
+
+use crate::auth::Claims;
+use crate::db::DbPool;
+use crate::handlers::post_handler::fetch_post;
+use crate::models::{
+    ErrorResponse, FeaturedSlotRequest, FeaturedSlotResponse, PinPostRequest, PostResponse,
+};
+use crate::recommend;
+
+const MAX_PINNED_PER_AUTHOR: i64 = 5;
+
+fn featured_slot_count() -> i32 {
+    env::var("FEATURED_SLOT_COUNT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|count| *count > 0)
+        .unwrap_or(5)
+}
+
+fn check_expiry(
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    match expires_at {
+        Some(expires_at) if expires_at <= Utc::now() => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("expires_at must be in the future")),
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub async fn pin_post(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i32>,
+    Json(payload): Json<PinPostRequest>,
+) -> Result<Json<PostResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+    check_expiry(payload.expires_at)?;
+
+    let post = fetch_post(&pool, id).await?;
+    if post.user_id != claims.sub {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("You can only pin your own posts")),
+        ));
+    }
+
+    let (pinned,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM pinned_posts
+         WHERE user_id = ? AND post_id <> ? AND (expires_at IS NULL OR expires_at > NOW())",
+    )
+    .bind(claims.sub)
+    .bind(id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    if pinned >= MAX_PINNED_PER_AUTHOR {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(format!(
+                "At most {MAX_PINNED_PER_AUTHOR} posts can be pinned"
+            ))),
+        ));
+    }
+
+    sqlx::query(
+        "INSERT INTO pinned_posts (post_id, user_id, position, expires_at) VALUES (?, ?, ?, ?)
+         ON DUPLICATE KEY UPDATE position = VALUES(position), expires_at = VALUES(expires_at)",
+    )
+    .bind(id)
+    .bind(claims.sub)
+    .bind(payload.position.unwrap_or(0))
+    .bind(payload.expires_at)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    recommend::invalidate();
+
+    Ok(Json(fetch_post(&pool, id).await?))
+}
+
+pub async fn unpin_post(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let post = fetch_post(&pool, id).await?;
+    if post.user_id != claims.sub {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("You can only unpin your own posts")),
+        ));
+    }
+
+    sqlx::query("DELETE FROM pinned_posts WHERE post_id = ?")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    recommend::invalidate();
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_featured_slots(
+    State(pool): State<DbPool>,
+) -> Result<Json<Vec<FeaturedSlotResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let slots = sqlx::query_as::<_, FeaturedSlotResponse>(
+        "SELECT fs.slot, fs.post_id, p.title, fs.expires_at, fs.assigned_by
+         FROM featured_slots fs
+         JOIN posts p ON fs.post_id = p.id
+         ORDER BY fs.slot",
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(slots))
+}
+
+pub async fn set_featured_slot(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    Path(slot): Path<i32>,
+    Json(payload): Json<FeaturedSlotRequest>,
+) -> Result<Json<FeaturedSlotResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let slots = featured_slot_count();
+    if !(1..=slots).contains(&slot) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!(
+                "Slot must be between 1 and {slots}"
+            ))),
+        ));
+    }
+    check_expiry(payload.expires_at)?;
+
+    let post = fetch_post(&pool, payload.post_id).await?;
+
+    let mut tx = pool.begin().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    // A post occupies at most one slot, so featuring it elsewhere moves it.
+    sqlx::query("DELETE FROM featured_slots WHERE post_id = ? OR slot = ?")
+        .bind(post.id)
+        .bind(slot)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    sqlx::query(
+        "INSERT INTO featured_slots (slot, post_id, expires_at, assigned_by) VALUES (?, ?, ?, ?)",
+    )
+    .bind(slot)
+    .bind(post.id)
+    .bind(payload.expires_at)
+    .bind(claims.sub)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    tx.commit().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    recommend::invalidate();
+
+    Ok(Json(FeaturedSlotResponse {
+        slot,
+        post_id: post.id,
+        title: post.title,
+        expires_at: payload.expires_at,
+        assigned_by: Some(claims.sub),
+    }))
+}
+
+pub async fn clear_featured_slot(
+    State(pool): State<DbPool>,
+    Path(slot): Path<i32>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let result = sqlx::query("DELETE FROM featured_slots WHERE slot = ?")
+        .bind(slot)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("Featured slot is empty")),
+        ));
+    }
+
+    recommend::invalidate();
+
+    Ok(StatusCode::NO_CONTENT)
+}
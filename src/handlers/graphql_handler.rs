@@ -0,0 +1,33 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::Html,
+    Json,
+};
+
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::graphql;
+
+pub async fn graphql(
+    State(pool): State<DbPool>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    Json(request): Json<async_graphql::Request>,
+) -> Result<Json<async_graphql::Response>, StatusCode> {
+    let claims = graphql::claims_from_header(
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok()),
+    )?;
+
+    let request = graphql::prepare_request(request, pool, claims, client_ip);
+
+    Ok(Json(graphql::schema().execute(request).await))
+}
+
+pub async fn graphql_playground() -> Html<String> {
+    Html(async_graphql::http::playground_source(
+        async_graphql::http::GraphQLPlaygroundConfig::new("/graphql"),
+    ))
+}
@@ -0,0 +1,12 @@
+use axum::{http::header, response::IntoResponse, Json};
+
+use crate::jwt_keys;
+
+/// Public keys access tokens can be verified with. Retired keys stay listed
+/// while their tokens may still be in use.
+pub async fn jwks() -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(jwt_keys::keys().jwks().clone()),
+    )
+}
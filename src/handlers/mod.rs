@@ -1,5 +1,21 @@
+pub mod account_handler;
+pub mod activitypub_handler;
+pub mod admin_handler;
+pub mod api_key_handler;
+pub mod audit_handler;
 pub mod contract_handler;
+pub mod curation_handler;
+pub mod graphql_handler;
+pub mod jwks_handler;
+pub mod newsletter_handler;
+pub mod oidc_handler;
+pub mod password_handler;
 pub mod post_handler;
+pub mod recommend_handler;
+pub mod session_handler;
+pub mod siwe_handler;
 pub mod transfer_handler;
+pub mod two_factor_handler;
 pub mod user_handler;
 pub mod wallet_handler;
+pub mod webmention_handler;
@@ -0,0 +1,235 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use validator::Validate;
+
+use crate::auth::{generate_opaque_token, hash_opaque_token};
+use crate::config;
+use crate::db::DbPool;
+use crate::mailer::{self, Email};
+use crate::models::{
+    ErrorResponse, MessageResponse, SubscribeRequest, TokenQuery, UnsubscribeQuery,
+};
+use crate::newsletter;
+
+const CONFIRM_TOKEN_TTL_HOURS: i64 = 48;
+/// Minimum gap between confirmation emails to one address, so the public
+/// endpoint cannot be used to flood an inbox.
+const CONFIRM_RESEND_INTERVAL_SECS: i64 = 300;
+
+pub async fn subscribe(
+    State(pool): State<DbPool>,
+    Json(payload): Json<SubscribeRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+
+    let author = match payload.author_id {
+        Some(author_id) => {
+            let author: Option<(String,)> =
+                sqlx::query_as("SELECT username FROM users WHERE id = ?")
+                    .bind(author_id)
+                    .fetch_optional(&pool)
+                    .await
+                    .map_err(|e| {
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(ErrorResponse::new(format!("Database error: {e}"))),
+                        )
+                    })?;
+            Some(
+                author
+                    .ok_or_else(|| {
+                        (
+                            StatusCode::NOT_FOUND,
+                            Json(ErrorResponse::new("Author not found")),
+                        )
+                    })?
+                    .0,
+            )
+        }
+        None => None,
+    };
+
+    let accepted = (
+        StatusCode::ACCEPTED,
+        Json(MessageResponse::new(
+            "Check your inbox to confirm the subscription",
+        )),
+    );
+
+    let existing: Option<(i32, bool)> = sqlx::query_as(
+        "SELECT id, confirmed_at IS NOT NULL AND unsubscribed_at IS NULL
+         FROM newsletter_subscriptions
+         WHERE email = ? AND author_id <=> ?",
+    )
+    .bind(&payload.email)
+    .bind(payload.author_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    if let Some((_, true)) = existing {
+        return Ok(accepted);
+    }
+
+    // Counted across authors, since each one would otherwise get its own
+    // pending subscription and email.
+    let wait_secs: Option<i64> = sqlx::query_scalar(
+        "SELECT TIMESTAMPDIFF(SECOND, NOW(), MAX(confirm_sent_at) + INTERVAL ? SECOND)
+         FROM newsletter_subscriptions
+         WHERE email = ? AND confirmed_at IS NULL",
+    )
+    .bind(CONFIRM_RESEND_INTERVAL_SECS)
+    .bind(&payload.email)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    if let Some(secs) = wait_secs.filter(|secs| *secs > 0) {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(format!(
+                "A confirmation email was sent recently; try again in {secs} seconds"
+            ))),
+        ));
+    }
+
+    let confirm_token = generate_opaque_token();
+    let token_hash = hash_opaque_token(&confirm_token);
+
+    let result = match existing {
+        Some((id, _)) => sqlx::query(
+            "UPDATE newsletter_subscriptions
+             SET confirm_token_hash = ?, confirm_sent_at = NOW(), confirmed_at = NULL,
+                 unsubscribed_at = NULL, last_sent_at = NULL
+             WHERE id = ?",
+        )
+        .bind(&token_hash)
+        .bind(id)
+        .execute(&pool)
+        .await,
+        None => sqlx::query(
+            "INSERT INTO newsletter_subscriptions (email, author_id, confirm_token_hash, confirm_sent_at)
+             VALUES (?, ?, ?, NOW())",
+        )
+        .bind(&payload.email)
+        .bind(payload.author_id)
+        .bind(&token_hash)
+        .execute(&pool)
+        .await,
+    };
+
+    result.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let body = newsletter::render_confirmation(&payload.email, author.as_deref(), &confirm_token)
+        .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Template error: {e}"))),
+        )
+    })?;
+
+    let mailer = mailer::from_env().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Mailer error: {e}"))),
+        )
+    })?;
+
+    mailer
+        .send(&Email::new(
+            &payload.email,
+            format!("Confirm your subscription to {}", config::site_name()),
+            body,
+        ))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponse::new(format!("Failed to send email: {e}"))),
+            )
+        })?;
+
+    Ok(accepted)
+}
+
+pub async fn confirm_subscription(
+    State(pool): State<DbPool>,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<MessageResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let result = sqlx::query(
+        "UPDATE newsletter_subscriptions
+         SET confirmed_at = NOW(), confirm_token_hash = NULL
+         WHERE confirm_token_hash = ? AND confirmed_at IS NULL
+           AND confirm_sent_at >= NOW() - INTERVAL ? HOUR",
+    )
+    .bind(hash_opaque_token(&query.token))
+    .bind(CONFIRM_TOKEN_TTL_HOURS)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Invalid or expired confirmation token")),
+        ));
+    }
+
+    Ok(Json(MessageResponse::new("Subscription confirmed")))
+}
+
+pub async fn unsubscribe(
+    State(pool): State<DbPool>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<Json<MessageResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !newsletter::verify_unsubscribe_token(query.id, &query.token) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Invalid unsubscribe token")),
+        ));
+    }
+
+    sqlx::query(
+        "UPDATE newsletter_subscriptions SET unsubscribed_at = NOW()
+         WHERE id = ? AND unsubscribed_at IS NULL",
+    )
+    .bind(query.id)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(MessageResponse::new("You have been unsubscribed")))
+}
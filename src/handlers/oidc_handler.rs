@@ -0,0 +1,171 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Redirect,
+    Extension, Json,
+};
+
+use crate::auth::Claims;
+use crate::db::DbPool;
+use crate::handlers::user_handler::start_session;
+use crate::models::{
+    ErrorResponse, IdentityResponse, LoginResponse, OidcAuthorizationResponse, OidcCallbackQuery,
+    User, USER_SELECT,
+};
+use crate::oidc::{self, OidcError, ProviderConfig};
+use crate::sessions::Device;
+
+fn oidc_error(e: OidcError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match &e {
+        OidcError::UnknownProvider => StatusCode::NOT_FOUND,
+        OidcError::InvalidState => StatusCode::BAD_REQUEST,
+        OidcError::Provider(_) => StatusCode::BAD_GATEWAY,
+        OidcError::Conflict(_) => StatusCode::CONFLICT,
+        OidcError::RegistrationClosed => StatusCode::FORBIDDEN,
+        OidcError::Internal(_) | OidcError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(ErrorResponse::new(e.to_string())))
+}
+
+fn provider_config(name: &str) -> Result<ProviderConfig, (StatusCode, Json<ErrorResponse>)> {
+    oidc::provider(name).ok_or_else(|| oidc_error(OidcError::UnknownProvider))
+}
+
+pub async fn list_providers() -> Json<Vec<String>> {
+    Json(oidc::provider_names())
+}
+
+/// Sends the browser to the provider's login page.
+pub async fn authorize(
+    State(pool): State<DbPool>,
+    Path(provider): Path<String>,
+) -> Result<Redirect, (StatusCode, Json<ErrorResponse>)> {
+    let config = provider_config(&provider)?;
+    let url = oidc::begin_login(&pool, &config, None)
+        .await
+        .map_err(oidc_error)?;
+
+    Ok(Redirect::to(&url))
+}
+
+/// Starts linking a provider identity to the signed-in account. The client
+/// opens the returned URL; the callback then attaches the identity.
+pub async fn link(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    Path(provider): Path<String>,
+) -> Result<Json<OidcAuthorizationResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let config = provider_config(&provider)?;
+    let authorization_url = oidc::begin_login(&pool, &config, Some(claims.sub))
+        .await
+        .map_err(oidc_error)?;
+
+    Ok(Json(OidcAuthorizationResponse { authorization_url }))
+}
+
+pub async fn callback(
+    State(pool): State<DbPool>,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+    device: Device,
+) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let config = provider_config(&provider)?;
+
+    if let Some(error) = query.error {
+        let reason = match query.error_description {
+            Some(description) => format!("{error}: {description}"),
+            None => error,
+        };
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse::new(format!(
+                "Login was not completed ({reason})"
+            ))),
+        ));
+    }
+
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Missing code or state")),
+        ));
+    };
+
+    let (identity, link_user_id) = oidc::complete_login(&pool, &config, &code, &state)
+        .await
+        .map_err(oidc_error)?;
+    let user_id = oidc::resolve_user(&pool, &config, &identity, link_user_id)
+        .await
+        .map_err(oidc_error)?;
+
+    let user = sqlx::query_as::<_, User>(&format!(
+        "{USER_SELECT} WHERE id = ? AND deleted_at IS NULL"
+    ))
+    .bind(user_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?
+    .ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("User not found")),
+        )
+    })?;
+
+    Ok(Json(start_session(&pool, user, &device).await?))
+}
+
+pub async fn list_identities(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<IdentityResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let identities = sqlx::query_as::<_, IdentityResponse>(
+        "SELECT provider, subject, email, created_at FROM user_identities
+         WHERE user_id = ? ORDER BY created_at",
+    )
+    .bind(claims.sub)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(identities))
+}
+
+pub async fn unlink(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    Path(provider): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let result = sqlx::query("DELETE FROM user_identities WHERE user_id = ? AND provider = ?")
+        .bind(claims.sub)
+        .bind(&provider)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "No identity from this provider is linked",
+            )),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
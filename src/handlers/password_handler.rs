@@ -0,0 +1,203 @@
+use axum::{extract::State, http::StatusCode, Json};
+use validator::Validate;
+
+use crate::api_keys;
+use crate::audit::{self, AuditEvent};
+use crate::auth::hash_password;
+use crate::client_ip::ClientIp;
+use crate::config;
+use crate::db::DbPool;
+use crate::login_throttle;
+use crate::mailer::{self, Email};
+use crate::models::{ErrorResponse, ForgotPasswordRequest, MessageResponse, ResetPasswordRequest};
+use crate::moderation;
+use crate::password_policy;
+use crate::password_reset;
+use crate::tokens;
+
+pub async fn forgot_password(
+    State(pool): State<DbPool>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+
+    // The same answer for known and unknown addresses, so the endpoint cannot
+    // be used to find out who has an account.
+    let accepted = (
+        StatusCode::ACCEPTED,
+        Json(MessageResponse::new(
+            "If the email is registered, a password reset link has been sent",
+        )),
+    );
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    };
+
+    if let Some(secs) = login_throttle::reset_locked_for(&pool, &payload.email, ip)
+        .await
+        .map_err(db_error)?
+    {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(format!(
+                "Too many password reset requests; try again in {secs} seconds"
+            ))),
+        ));
+    }
+
+    // Counted for unknown addresses too, so the limit says nothing about
+    // whether an account exists.
+    login_throttle::record_reset_request(&pool, &payload.email, ip)
+        .await
+        .map_err(db_error)?;
+
+    let user: Option<(i32, String)> =
+        sqlx::query_as("SELECT id, username FROM users WHERE email = ?")
+            .bind(&payload.email)
+            .fetch_optional(&pool)
+            .await
+            .map_err(db_error)?;
+
+    let Some((user_id, username)) = user else {
+        return Ok(accepted);
+    };
+
+    send_reset_email(&pool, user_id, &username, payload.email).await?;
+
+    Ok(accepted)
+}
+
+/// Issues a reset token and emails the link to the user in the background.
+pub(crate) async fn send_reset_email(
+    pool: &DbPool,
+    user_id: i32,
+    username: &str,
+    email: String,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let reset_token = password_reset::issue_reset_token(pool, user_id)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    let body = password_reset::render_reset_email(username, &reset_token).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Template error: {e}"))),
+        )
+    })?;
+
+    // Sent in the background so response times do not reveal whether the
+    // address belongs to an account.
+    let message = Email::new(
+        email,
+        format!("Reset your {} password", config::site_name()),
+        body,
+    );
+    tokio::spawn(async move {
+        let result = match mailer::from_env() {
+            Ok(mailer) => mailer.send(&message).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to send password reset email: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+pub async fn reset_password(
+    State(pool): State<DbPool>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<MessageResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if let Err(errors) = payload.validate() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Validation error: {errors}"))),
+        ));
+    }
+
+    let invalid_token = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Invalid or expired reset token")),
+        )
+    };
+
+    let (username, email) = password_reset::reset_token_owner(&pool, &payload.token)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?
+        .ok_or_else(invalid_token)?;
+
+    password_policy::check(&payload.password, &username, &email)
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(ErrorResponse::new(message))))?;
+
+    let password_hash = hash_password(&payload.password).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Password hashing error: {e}"))),
+        )
+    })?;
+
+    let user_id = password_reset::consume_reset_token(&pool, &payload.token, &password_hash)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?
+        .ok_or_else(invalid_token)?;
+    moderation::forget(user_id);
+
+    tokens::revoke_all_sessions(&pool, user_id)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    // Keys minted by whoever knew the old password go too.
+    api_keys::revoke_all(&pool, user_id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(user_id),
+            action: "auth.password_reset",
+            target: format!("user:{user_id}"),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(MessageResponse::new("Password has been reset")))
+}
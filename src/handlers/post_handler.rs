@@ -1,18 +1,59 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     Extension, Json,
 };
 use validator::Validate;
 
-use crate::auth::Claims;
+use crate::activitypub;
+use crate::audit::{self, AuditEvent, Outcome};
+use crate::auth::{self, Claims, Permission};
+use crate::client_ip::ClientIp;
+use crate::content;
 use crate::db::DbPool;
-use crate::models::{CreatePostRequest, ErrorResponse, PostResponse, UpdatePostRequest};
+use crate::email_verification;
+use crate::i18n;
+use crate::models::{
+    CreatePostRequest, ErrorResponse, PostListQuery, PostResponse, TranslationResponse,
+    UpdatePostRequest, POST_RESPONSE_SELECT,
+};
+use crate::recommend;
+use crate::webmention;
 
 pub async fn create_post(
     State(pool): State<DbPool>,
     Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<CreatePostRequest>,
+) -> Result<Json<PostResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let actor_id = claims.sub;
+    let request_digest = audit::digest(&payload);
+    let result = insert_post(pool.clone(), claims, payload).await;
+
+    let target = match &result {
+        Ok(Json(post)) => format!("post:{}", post.id),
+        Err(_) => "post:new".to_string(),
+    };
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(actor_id),
+            action: "post.create",
+            target,
+            ip,
+            outcome: Outcome::of(&result),
+            request_digest: Some(request_digest),
+        },
+    )
+    .await;
+
+    result
+}
+
+async fn insert_post(
+    pool: DbPool,
+    claims: Claims,
+    payload: CreatePostRequest,
 ) -> Result<Json<PostResponse>, (StatusCode, Json<ErrorResponse>)> {
     if let Err(errors) = payload.validate() {
         return Err((
@@ -21,100 +62,176 @@ pub async fn create_post(
         ));
     }
 
-    let result = sqlx::query("INSERT INTO posts (title, content, user_id) VALUES (?, ?, ?)")
-        .bind(&payload.title)
-        .bind(&payload.content)
-        .bind(claims.sub)
-        .execute(&pool)
-        .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::new(format!("Database error: {e}"))),
-            )
-        })?;
+    if email_verification::required_for_posting() {
+        let verified = email_verification::is_verified(&pool, claims.sub)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(format!("Database error: {e}"))),
+                )
+            })?;
+        if !verified {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse::new(
+                    "Verify your email address before publishing posts",
+                )),
+            ));
+        }
+    }
 
-    let post_id = result.last_insert_id() as i32;
+    let language = match payload.language.as_deref() {
+        Some(tag) => parse_language(tag)?,
+        None => i18n::default_language(),
+    };
+
+    let translation_group = match payload.translation_of {
+        Some(source_id) => {
+            let source: Option<(i32, Option<i32>)> =
+                sqlx::query_as("SELECT user_id, translation_group FROM posts WHERE id = ?")
+                    .bind(source_id)
+                    .fetch_optional(&pool)
+                    .await
+                    .map_err(|e| {
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(ErrorResponse::new(format!("Database error: {e}"))),
+                        )
+                    })?;
+
+            let (source_user_id, source_group) = source.ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse::new("Translation source not found")),
+                )
+            })?;
+
+            if source_user_id != claims.sub {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Json(ErrorResponse::new("You can only translate your own posts")),
+                ));
+            }
 
-    let post: PostResponse = sqlx::query_as::<
-        _,
+            let group = source_group.unwrap_or(source_id);
+            ensure_language_available(&pool, group, &language, None).await?;
+            Some(group)
+        }
+        None => None,
+    };
+
+    let meta = content::derive(&payload.content, payload.excerpt.as_deref());
+    let excerpt_is_custom = payload
+        .excerpt
+        .as_deref()
+        .is_some_and(|excerpt| !excerpt.trim().is_empty());
+    let toc = serde_json::to_string(&meta.toc).unwrap_or_else(|_| "[]".to_string());
+
+    let mut tx = pool.begin().await.map_err(|e| {
         (
-            i32,
-            String,
-            String,
-            i32,
-            String,
-            chrono::DateTime<chrono::Utc>,
-            chrono::DateTime<chrono::Utc>,
-        ),
-    >(
-        "SELECT p.id, p.title, p.content, p.user_id, u.username, p.created_at, p.updated_at
-         FROM posts p
-         JOIN users u ON p.user_id = u.id
-         WHERE p.id = ?",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    if let Some(group) = translation_group {
+        // The first translation turns the source post into the group's anchor.
+        sqlx::query(
+            "UPDATE posts SET translation_group = ?, updated_at = updated_at
+             WHERE id = ? AND translation_group IS NULL",
+        )
+        .bind(group)
+        .bind(group)
+        .execute(&mut *tx)
+        .await
+        .map_err(write_error)?;
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO posts (title, content, excerpt, excerpt_is_custom, reading_time_minutes, toc,
+                            language, translation_group, user_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
     )
-    .bind(post_id)
-    .fetch_one(&pool)
+    .bind(&payload.title)
+    .bind(&payload.content)
+    .bind(&meta.excerpt)
+    .bind(excerpt_is_custom)
+    .bind(meta.reading_time_minutes)
+    .bind(&toc)
+    .bind(&language)
+    .bind(translation_group)
+    .bind(claims.sub)
+    .execute(&mut *tx)
     .await
-    .map(
-        |(id, title, content, user_id, username, created_at, updated_at)| PostResponse {
-            id,
-            title,
-            content,
-            user_id,
-            username,
-            created_at,
-            updated_at,
-        },
-    )
-    .map_err(|e| {
+    .map_err(write_error)?;
+
+    tx.commit().await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::new(format!("Database error: {e}"))),
         )
     })?;
 
+    let post_id = result.last_insert_id() as i32;
+
+    let post: PostResponse =
+        sqlx::query_as::<_, PostResponse>(&format!("{POST_RESPONSE_SELECT} WHERE p.id = ?"))
+            .bind(post_id)
+            .fetch_one(&pool)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(format!("Database error: {e}"))),
+                )
+            })?;
+
+    recommend::invalidate();
+    activitypub::spawn_publish(pool.clone(), post.id, "Create");
+    webmention::spawn_send_for_post(pool.clone(), post.id, post.content.clone());
+
     Ok(Json(post))
 }
 
 pub async fn get_posts(
     State(pool): State<DbPool>,
+    Query(query): Query<PostListQuery>,
 ) -> Result<Json<Vec<PostResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    let posts: Vec<PostResponse> = sqlx::query_as::<
-        _,
-        (
-            i32,
-            String,
-            String,
-            i32,
-            String,
-            chrono::DateTime<chrono::Utc>,
-            chrono::DateTime<chrono::Utc>,
-        ),
-    >(
-        "SELECT p.id, p.title, p.content, p.user_id, u.username, p.created_at, p.updated_at
-         FROM posts p
-         JOIN users u ON p.user_id = u.id
-         ORDER BY p.created_at DESC",
-    )
-    .fetch_all(&pool)
-    .await
-    .map(|rows| {
-        rows.into_iter()
-            .map(
-                |(id, title, content, user_id, username, created_at, updated_at)| PostResponse {
-                    id,
-                    title,
-                    content,
-                    user_id,
-                    username,
-                    created_at,
-                    updated_at,
-                },
-            )
-            .collect()
-    })
-    .map_err(|e| {
+    let language = query.language.as_deref().map(parse_language).transpose()?;
+
+    let mut conditions = Vec::new();
+    if query.author_id.is_some() {
+        conditions.push("p.user_id = ?");
+    }
+    if language.is_some() {
+        conditions.push("p.language = ?");
+    }
+
+    let mut sql = POST_RESPONSE_SELECT.to_string();
+    if !conditions.is_empty() {
+        sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+    }
+
+    let mut order = Vec::new();
+    if query.featured {
+        order.push("fs.slot IS NULL, fs.slot");
+    }
+    if query.pinned {
+        order.push("pp.post_id IS NULL, pp.position, pp.created_at DESC");
+    }
+    order.push("p.created_at DESC");
+    sql.push_str(&format!(" ORDER BY {}", order.join(", ")));
+
+    let mut query_builder = sqlx::query_as::<_, PostResponse>(&sql);
+    if let Some(author_id) = query.author_id {
+        query_builder = query_builder.bind(author_id);
+    }
+    if let Some(language) = &language {
+        query_builder = query_builder.bind(language);
+    }
+
+    let posts: Vec<PostResponse> = query_builder.fetch_all(&pool).await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::new(format!("Database error: {e}"))),
@@ -127,59 +244,142 @@ pub async fn get_posts(
 pub async fn get_post(
     State(pool): State<DbPool>,
     Path(id): Path<i32>,
-) -> Result<Json<PostResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let post: PostResponse = sqlx::query_as::<
-        _,
-        (
-            i32,
-            String,
-            String,
-            i32,
-            String,
-            chrono::DateTime<chrono::Utc>,
-            chrono::DateTime<chrono::Utc>,
-        ),
-    >(
-        "SELECT p.id, p.title, p.content, p.user_id, u.username, p.created_at, p.updated_at
-         FROM posts p
-         JOIN users u ON p.user_id = u.id
-         WHERE p.id = ?",
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+) -> Result<(HeaderMap, Json<PostResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let mut post = fetch_post(&pool, id).await?;
+
+    let preferences = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .map(i18n::parse_accept_language)
+        .unwrap_or_default();
+
+    let translations = match post.translation_group {
+        Some(group) => fetch_translations(&pool, group).await?,
+        None => Vec::new(),
+    };
+
+    if !preferences.is_empty() && !translations.is_empty() {
+        let languages: Vec<String> = translations
+            .iter()
+            .map(|translation| translation.language.clone())
+            .collect();
+        if let Some(index) = i18n::best_match(&languages, &preferences) {
+            if translations[index].id != post.id {
+                post = fetch_post(&pool, translations[index].id).await?;
+            }
+        }
+    }
+
+    // Only used to recognise repeat views, so a bad token falls back to the IP.
+    let viewer_id = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| auth::verify_token(token).ok())
+        .map(|claims| claims.sub);
+    let viewer = recommend::viewer_key(viewer_id, ip);
+
+    if let Err(e) = sqlx::query(
+        "INSERT INTO post_views (post_id, viewer)
+         SELECT ?, ? FROM DUAL
+         WHERE ? IS NULL OR NOT EXISTS (
+             SELECT 1 FROM post_views
+             WHERE post_id = ? AND viewer = ? AND viewed_at > NOW() - INTERVAL ? MINUTE
+         )",
     )
-    .bind(id)
-    .fetch_optional(&pool)
+    .bind(post.id)
+    .bind(&viewer)
+    .bind(&viewer)
+    .bind(post.id)
+    .bind(&viewer)
+    .bind(recommend::view_dedup_window_minutes())
+    .execute(&pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new(format!("Database error: {e}"))),
-        )
-    })?
-    .map(
-        |(id, title, content, user_id, username, created_at, updated_at)| PostResponse {
-            id,
-            title,
-            content,
-            user_id,
-            username,
-            created_at,
-            updated_at,
-        },
-    )
-    .ok_or_else(|| {
-        (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse::new("Post not found")),
-        )
-    })?;
+    {
+        tracing::warn!("Failed to record view for post {}: {}", post.id, e);
+    }
 
-    Ok(Json(post))
+    let mut links = vec![format!(
+        "<{}>; rel=\"webmention\"",
+        webmention::endpoint_url()
+    )];
+    links.extend(
+        translations
+            .iter()
+            .filter(|translation| translation.id != post.id)
+            .map(|translation| {
+                format!(
+                    "<{}>; rel=\"alternate\"; hreflang=\"{}\"",
+                    webmention::post_url(translation.id),
+                    translation.language
+                )
+            }),
+    );
+
+    let mut response_headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&links.join(", ")) {
+        response_headers.insert(header::LINK, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&post.language) {
+        response_headers.insert(header::CONTENT_LANGUAGE, value);
+    }
+    response_headers.insert(header::VARY, HeaderValue::from_static("Accept-Language"));
+
+    Ok((response_headers, Json(post)))
+}
+
+pub async fn get_post_translations(
+    State(pool): State<DbPool>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<TranslationResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let post = fetch_post(&pool, id).await?;
+
+    let translations = match post.translation_group {
+        Some(group) => fetch_translations(&pool, group).await?,
+        None => vec![TranslationResponse {
+            id: post.id,
+            language: post.language,
+            title: post.title,
+        }],
+    };
+
+    Ok(Json(translations))
 }
 
 pub async fn update_post(
     State(pool): State<DbPool>,
     Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
     Path(id): Path<i32>,
     Json(payload): Json<UpdatePostRequest>,
+) -> Result<Json<PostResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let actor_id = claims.sub;
+    let request_digest = audit::digest(&payload);
+    let result = apply_post_update(pool.clone(), claims, id, payload).await;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(actor_id),
+            action: "post.update",
+            target: format!("post:{id}"),
+            ip,
+            outcome: Outcome::of(&result),
+            request_digest: Some(request_digest),
+        },
+    )
+    .await;
+
+    result
+}
+
+async fn apply_post_update(
+    pool: DbPool,
+    claims: Claims,
+    id: i32,
+    payload: UpdatePostRequest,
 ) -> Result<Json<PostResponse>, (StatusCode, Json<ErrorResponse>)> {
     if let Err(errors) = payload.validate() {
         return Err((
@@ -188,38 +388,67 @@ pub async fn update_post(
         ));
     }
 
-    let post: Option<(i32, i32)> = sqlx::query_as("SELECT id, user_id FROM posts WHERE id = ?")
-        .bind(id)
-        .fetch_optional(&pool)
-        .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::new(format!("Database error: {e}"))),
-            )
-        })?;
-
-    let (_, user_id) = post.ok_or_else(|| {
+    let post: Option<(i32, i32, String, String, bool, Option<i32>)> = sqlx::query_as(
+        "SELECT id, user_id, content, excerpt, excerpt_is_custom, translation_group
+         FROM posts WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
         (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse::new("Post not found")),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
         )
     })?;
 
-    if user_id != claims.sub {
+    let (_, user_id, current_content, current_excerpt, excerpt_is_custom, translation_group) = post
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new("Post not found")),
+            )
+        })?;
+
+    if user_id != claims.sub && !claims.has_permission(Permission::PostsEditAny) {
         return Err((
             StatusCode::FORBIDDEN,
             Json(ErrorResponse::new("You can only update your own posts")),
         ));
     }
 
-    if payload.title.is_none() && payload.content.is_none() {
+    if payload.title.is_none()
+        && payload.content.is_none()
+        && payload.excerpt.is_none()
+        && payload.language.is_none()
+    {
         return Err((
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse::new("At least one field must be provided")),
         ));
     }
 
+    let language = payload
+        .language
+        .as_deref()
+        .map(parse_language)
+        .transpose()?;
+    if let (Some(language), Some(group)) = (&language, translation_group) {
+        ensure_language_available(&pool, group, language, Some(id)).await?;
+    }
+
+    // A custom excerpt survives content edits until it is explicitly cleared.
+    let explicit_excerpt = match payload.excerpt.as_deref() {
+        Some(excerpt) => Some(excerpt),
+        None if excerpt_is_custom => Some(current_excerpt.as_str()),
+        None => None,
+    };
+    let new_content = payload.content.as_deref().unwrap_or(&current_content);
+    let content_changed = new_content != current_content;
+    let meta = content::derive(new_content, explicit_excerpt);
+    let excerpt_is_custom = explicit_excerpt.is_some_and(|excerpt| !excerpt.trim().is_empty());
+    let toc = serde_json::to_string(&meta.toc).unwrap_or_else(|_| "[]".to_string());
+
     let mut query_parts = Vec::new();
     let mut has_title = false;
     let mut has_content = false;
@@ -232,6 +461,13 @@ pub async fn update_post(
         query_parts.push("content = ?");
         has_content = true;
     }
+    if language.is_some() {
+        query_parts.push("language = ?");
+    }
+    query_parts.push("excerpt = ?");
+    query_parts.push("excerpt_is_custom = ?");
+    query_parts.push("reading_time_minutes = ?");
+    query_parts.push("toc = ?");
 
     let query = format!("UPDATE posts SET {} WHERE id = ?", query_parts.join(", "));
 
@@ -243,47 +479,77 @@ pub async fn update_post(
     if has_content {
         query_builder = query_builder.bind(payload.content.as_ref().unwrap());
     }
+    if let Some(language) = &language {
+        query_builder = query_builder.bind(language);
+    }
 
-    query_builder = query_builder.bind(id);
+    query_builder = query_builder
+        .bind(&meta.excerpt)
+        .bind(excerpt_is_custom)
+        .bind(meta.reading_time_minutes)
+        .bind(&toc)
+        .bind(id);
 
-    query_builder.execute(&pool).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new(format!("Database error: {e}"))),
-        )
-    })?;
+    query_builder.execute(&pool).await.map_err(write_error)?;
 
-    let post: PostResponse = sqlx::query_as::<
-        _,
-        (
-            i32,
-            String,
-            String,
-            i32,
-            String,
-            chrono::DateTime<chrono::Utc>,
-            chrono::DateTime<chrono::Utc>,
-        ),
-    >(
-        "SELECT p.id, p.title, p.content, p.user_id, u.username, p.created_at, p.updated_at
-         FROM posts p
-         JOIN users u ON p.user_id = u.id
-         WHERE p.id = ?",
+    let post: PostResponse =
+        sqlx::query_as::<_, PostResponse>(&format!("{POST_RESPONSE_SELECT} WHERE p.id = ?"))
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(format!("Database error: {e}"))),
+                )
+            })?;
+
+    recommend::invalidate();
+    activitypub::spawn_publish(pool.clone(), post.id, "Update");
+    // Linked sites are only pinged again when there is new content to see.
+    if content_changed {
+        webmention::spawn_send_for_post(pool.clone(), post.id, post.content.clone());
+    }
+
+    Ok(Json(post))
+}
+
+pub async fn delete_post(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let actor_id = claims.sub;
+    let result = remove_post(pool.clone(), claims, id).await;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(actor_id),
+            action: "post.delete",
+            target: format!("post:{id}"),
+            ip,
+            outcome: Outcome::of(&result),
+            request_digest: None,
+        },
+    )
+    .await;
+
+    result
+}
+
+async fn remove_post(
+    pool: DbPool,
+    claims: Claims,
+    id: i32,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let post: Option<(i32, String)> = sqlx::query_as(
+        "SELECT p.user_id, u.username FROM posts p JOIN users u ON p.user_id = u.id WHERE p.id = ?",
     )
     .bind(id)
-    .fetch_one(&pool)
+    .fetch_optional(&pool)
     .await
-    .map(
-        |(id, title, content, user_id, username, created_at, updated_at)| PostResponse {
-            id,
-            title,
-            content,
-            user_id,
-            username,
-            created_at,
-            updated_at,
-        },
-    )
     .map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -291,17 +557,23 @@ pub async fn update_post(
         )
     })?;
 
-    Ok(Json(post))
-}
+    let (user_id, username) = post.ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("Post not found")),
+        )
+    })?;
 
-pub async fn delete_post(
-    State(pool): State<DbPool>,
-    Extension(claims): Extension<Claims>,
-    Path(id): Path<i32>,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    let post: Option<(i32, i32)> = sqlx::query_as("SELECT id, user_id FROM posts WHERE id = ?")
+    if user_id != claims.sub && !claims.has_permission(Permission::PostsEditAny) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("You can only delete your own posts")),
+        ));
+    }
+
+    sqlx::query("DELETE FROM posts WHERE id = ?")
         .bind(id)
-        .fetch_optional(&pool)
+        .execute(&pool)
         .await
         .map_err(|e| {
             (
@@ -310,30 +582,114 @@ pub async fn delete_post(
             )
         })?;
 
-    let (_, user_id) = post.ok_or_else(|| {
+    recommend::invalidate();
+    activitypub::spawn_delete(pool.clone(), user_id, username, id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// A database error from writing a post's language; losing a race with
+/// another translation into the same language is a conflict.
+fn write_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let duplicate_language = e.as_database_error().is_some_and(|e| {
+        e.is_unique_violation() && e.message().contains("idx_translation_language")
+    });
+    if duplicate_language {
+        return (
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(
+                "A translation in this language already exists",
+            )),
+        );
+    }
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse::new(format!("Database error: {e}"))),
+    )
+}
+
+fn parse_language(tag: &str) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    i18n::normalize_language(tag).ok_or_else(|| {
         (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse::new("Post not found")),
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(format!("Invalid language tag: {tag}"))),
+        )
+    })
+}
+
+async fn ensure_language_available(
+    pool: &DbPool,
+    group: i32,
+    language: &str,
+    exclude_id: Option<i32>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let existing: Option<(i32,)> = sqlx::query_as(
+        "SELECT id FROM posts
+         WHERE (translation_group = ? OR id = ?) AND language = ? AND id <> ?",
+    )
+    .bind(group)
+    .bind(group)
+    .bind(language)
+    .bind(exclude_id.unwrap_or(0))
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
         )
     })?;
 
-    if user_id != claims.sub {
+    if existing.is_some() {
         return Err((
-            StatusCode::FORBIDDEN,
-            Json(ErrorResponse::new("You can only delete your own posts")),
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(format!(
+                "A translation in {language} already exists"
+            ))),
         ));
     }
 
-    sqlx::query("DELETE FROM posts WHERE id = ?")
+    Ok(())
+}
+
+pub(crate) async fn fetch_post(
+    pool: &DbPool,
+    id: i32,
+) -> Result<PostResponse, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as::<_, PostResponse>(&format!("{POST_RESPONSE_SELECT} WHERE p.id = ?"))
         .bind(id)
-        .execute(&pool)
+        .fetch_optional(pool)
         .await
         .map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse::new(format!("Database error: {e}"))),
             )
-        })?;
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new("Post not found")),
+            )
+        })
+}
 
-    Ok(StatusCode::NO_CONTENT)
+async fn fetch_translations(
+    pool: &DbPool,
+    group: i32,
+) -> Result<Vec<TranslationResponse>, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as::<_, TranslationResponse>(
+        "SELECT id, language, title FROM posts
+         WHERE translation_group = ?
+         ORDER BY id",
+    )
+    .bind(group)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })
 }
@@ -0,0 +1,153 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use crate::db::DbPool;
+use crate::models::{ErrorResponse, PostResponse, RecommendQuery, POST_RESPONSE_SELECT};
+use crate::recommend::{self, Document};
+
+const MAX_CANDIDATES: i64 = 500;
+
+pub async fn get_related_posts(
+    State(pool): State<DbPool>,
+    Path(id): Path<i32>,
+    Query(query): Query<RecommendQuery>,
+) -> Result<Json<Vec<PostResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let limit = query.limit();
+    let cache_key = format!("related:{id}:{limit}");
+    if let Some(posts) = recommend::cached(&cache_key) {
+        return Ok(Json(posts));
+    }
+
+    let target: (i32, String, String) =
+        sqlx::query_as("SELECT id, title, content FROM posts WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(format!("Database error: {e}"))),
+                )
+            })?
+            .ok_or_else(|| {
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(ErrorResponse::new("Post not found")),
+                )
+            })?;
+
+    let candidates: Vec<(i32, String, String)> = sqlx::query_as(
+        "SELECT id, title, content FROM posts WHERE id <> ? ORDER BY created_at DESC LIMIT ?",
+    )
+    .bind(id)
+    .bind(MAX_CANDIDATES)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let documents: Vec<Document> = candidates
+        .iter()
+        .map(|(id, title, content)| Document {
+            id: *id,
+            title,
+            content,
+        })
+        .collect();
+
+    let ranked = recommend::rank_related(
+        &Document {
+            id: target.0,
+            title: &target.1,
+            content: &target.2,
+        },
+        &documents,
+    );
+    let ids: Vec<i32> = ranked.into_iter().take(limit).map(|(id, _)| id).collect();
+
+    let mut posts = fetch_posts_by_ids(&pool, &ids).await?;
+    posts.sort_by_key(|post| ids.iter().position(|id| *id == post.id));
+
+    recommend::store(cache_key, posts.clone());
+
+    Ok(Json(posts))
+}
+
+pub async fn get_trending_posts(
+    State(pool): State<DbPool>,
+    Query(query): Query<RecommendQuery>,
+) -> Result<Json<Vec<PostResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let limit = query.limit();
+    let cache_key = format!("trending:{limit}");
+    if let Some(posts) = recommend::cached(&cache_key) {
+        return Ok(Json(posts));
+    }
+
+    let posts: Vec<PostResponse> = sqlx::query_as::<_, PostResponse>(&format!(
+        "{POST_RESPONSE_SELECT}
+         JOIN (
+             SELECT post_id,
+                    SUM(weight * POW(0.5, TIMESTAMPDIFF(SECOND, at, NOW()) / 3600 / ?)) AS score
+             FROM (
+                 SELECT post_id, viewed_at AS at, 1.0 AS weight
+                 FROM post_views
+                 WHERE viewed_at >= NOW() - INTERVAL ? HOUR
+                 UNION ALL
+                 SELECT post_id, created_at, ?
+                 FROM post_reactions
+                 WHERE created_at >= NOW() - INTERVAL ? HOUR
+             ) activity
+             GROUP BY post_id
+         ) t ON t.post_id = p.id
+         ORDER BY t.score DESC
+         LIMIT ?"
+    ))
+    .bind(recommend::trending_half_life_hours())
+    .bind(recommend::trending_window_hours())
+    .bind(recommend::trending_reaction_weight())
+    .bind(recommend::trending_window_hours())
+    .bind(limit as i64)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    recommend::store(cache_key, posts.clone());
+
+    Ok(Json(posts))
+}
+
+async fn fetch_posts_by_ids(
+    pool: &DbPool,
+    ids: &[i32],
+) -> Result<Vec<PostResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let query = format!("{POST_RESPONSE_SELECT} WHERE p.id IN ({placeholders})");
+
+    let mut query_builder = sqlx::query_as::<_, PostResponse>(&query);
+    for id in ids {
+        query_builder = query_builder.bind(id);
+    }
+
+    query_builder.fetch_all(pool).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })
+}
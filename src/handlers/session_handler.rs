@@ -0,0 +1,67 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+
+use crate::audit::{self, AuditEvent};
+use crate::auth::Claims;
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::models::{ErrorResponse, SessionResponse};
+use crate::sessions;
+
+pub async fn list_sessions(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<Vec<SessionResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mut sessions = sessions::list(&pool, claims.sub).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    for session in &mut sessions {
+        session.current = claims.sid == Some(session.id);
+    }
+
+    Ok(Json(sessions))
+}
+
+/// Signs a session out; its refresh token stops working and its access
+/// tokens are rejected.
+pub async fn delete_session(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let revoked = sessions::revoke(&pool, claims.sub, id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    if !revoked {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("Session not found")),
+        ));
+    }
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "auth.session_revoke",
+            target: format!("session:{id}"),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
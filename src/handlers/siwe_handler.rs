@@ -0,0 +1,174 @@
+use axum::{extract::State, http::StatusCode, Extension, Json};
+
+use crate::auth::{generate_opaque_token, hash_password, Claims};
+use crate::db::DbPool;
+use crate::handlers::user_handler::start_session;
+use crate::models::{
+    ErrorResponse, LoginResponse, SiweNonceResponse, SiweRequest, User, UserResponse, USER_SELECT,
+};
+use crate::sessions::Device;
+use crate::siwe::{self, SiweError};
+
+fn siwe_error(e: SiweError) -> (StatusCode, Json<ErrorResponse>) {
+    match e {
+        SiweError::Database(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        ),
+        e => (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse::new(e.to_string())),
+        ),
+    }
+}
+
+async fn fetch_user_by_address(
+    pool: &DbPool,
+    address: &str,
+) -> Result<Option<User>, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as::<_, User>(&format!(
+        "{USER_SELECT} WHERE ethereum_address = ? AND deleted_at IS NULL"
+    ))
+    .bind(address)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })
+}
+
+pub async fn get_nonce(
+    State(pool): State<DbPool>,
+) -> Result<Json<SiweNonceResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let nonce = siwe::issue_nonce(&pool).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(SiweNonceResponse {
+        nonce,
+        expires_in: siwe::nonce_ttl_secs(),
+    }))
+}
+
+/// Signs in the account linked to the signing address. Unknown addresses get
+/// a new account named after the address, unless `SIWE_ALLOW_REGISTRATION`
+/// is `false`.
+pub async fn siwe_login(
+    State(pool): State<DbPool>,
+    device: Device,
+    Json(payload): Json<SiweRequest>,
+) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let address = siwe::verify(&pool, &payload.message, &payload.signature)
+        .await
+        .map_err(siwe_error)?;
+    let address = siwe::address_key(&address);
+
+    if let Some(user) = fetch_user_by_address(&pool, &address).await? {
+        return Ok(Json(start_session(&pool, user, &device).await?));
+    }
+
+    if !siwe::registration_allowed() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("No account is linked to this address")),
+        ));
+    }
+
+    // The account signs in with its wallet; nobody knows this password.
+    let password_hash = hash_password(&generate_opaque_token()).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Password hashing error: {e}"))),
+        )
+    })?;
+
+    let inserted = sqlx::query(
+        "INSERT INTO users (username, email, password_hash, ethereum_address) VALUES (?, ?, ?, ?)",
+    )
+    .bind(&address)
+    .bind(format!("{address}@ethereum.invalid"))
+    .bind(&password_hash)
+    .bind(&address)
+    .execute(&pool)
+    .await;
+
+    match inserted {
+        Ok(_) => tracing::info!("Created account for Ethereum address {}", address),
+        // A concurrent sign-in with the same address got there first.
+        Err(e)
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation()) => {}
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            ))
+        }
+    }
+
+    let user = fetch_user_by_address(&pool, &address)
+        .await?
+        .ok_or_else(|| {
+            (
+                StatusCode::CONFLICT,
+                Json(ErrorResponse::new("Username already exists")),
+            )
+        })?;
+
+    Ok(Json(start_session(&pool, user, &device).await?))
+}
+
+/// Links the signing address to the signed-in account, replacing any
+/// previously linked address.
+pub async fn link_address(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<SiweRequest>,
+) -> Result<Json<UserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let address = siwe::verify(&pool, &payload.message, &payload.signature)
+        .await
+        .map_err(siwe_error)?;
+    let address = siwe::address_key(&address);
+
+    if let Some(owner) = fetch_user_by_address(&pool, &address).await? {
+        if owner.id != claims.sub {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ErrorResponse::new(
+                    "Address is already linked to another account",
+                )),
+            ));
+        }
+    }
+
+    sqlx::query("UPDATE users SET ethereum_address = ? WHERE id = ?")
+        .bind(&address)
+        .bind(claims.sub)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    let user = sqlx::query_as::<_, User>(&format!("{USER_SELECT} WHERE id = ?"))
+        .bind(claims.sub)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    Ok(Json(user.into()))
+}
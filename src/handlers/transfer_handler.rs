@@ -1,4 +1,4 @@
-use axum::{http::StatusCode, Json};
+use axum::{extract::State, http::StatusCode, Extension, Json};
 use ethers::{
     prelude::*,
     providers::{Http, Provider},
@@ -6,10 +6,15 @@ use ethers::{
     types::{TransactionRequest, U256},
     utils::parse_ether,
 };
+use serde_json::json;
 use std::env;
 use std::sync::Arc;
 use validator::Validate;
 
+use crate::audit::{self, AuditEvent, Outcome};
+use crate::auth::Claims;
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
 use crate::models::{ErrorResponse, TransferRequest, TransferResponse, TransferResult};
 
 fn get_rpc_url(chain: &str) -> Result<String, String> {
@@ -18,7 +23,43 @@ fn get_rpc_url(chain: &str) -> Result<String, String> {
 }
 
 pub async fn batch_transfer(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<TransferRequest>,
+) -> Result<Json<TransferResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let (target, request_digest) = audit::signed_request(
+        &payload.chain,
+        None,
+        &json!({
+            "chain": payload.chain,
+            "transfers": payload.transfers,
+        }),
+    );
+    let result = send_batch(payload).await;
+
+    let outcome = match &result {
+        Ok(Json(response)) if response.failed > 0 => Outcome::Failure,
+        result => Outcome::of(result),
+    };
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "transfer.batch",
+            target,
+            ip,
+            outcome,
+            request_digest: Some(request_digest),
+        },
+    )
+    .await;
+
+    result
+}
+
+async fn send_batch(
+    payload: TransferRequest,
 ) -> Result<Json<TransferResponse>, (StatusCode, Json<ErrorResponse>)> {
     if let Err(errors) = payload.validate() {
         return Err((
@@ -0,0 +1,332 @@
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use std::net::IpAddr;
+
+use crate::audit::{self, AuditEvent};
+use crate::auth::Claims;
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::handlers::account_handler::{fetch_user, fetch_user_with_password};
+use crate::login_throttle;
+use crate::models::{
+    DisableTwoFactorRequest, ErrorResponse, MessageResponse, RecoveryCodesResponse,
+    TwoFactorCodeRequest, TwoFactorEnrollmentResponse, User,
+};
+use crate::tokens;
+use crate::two_factor;
+
+fn not_enabled() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::CONFLICT,
+        Json(ErrorResponse::new(
+            "Two-factor authentication is not enabled",
+        )),
+    )
+}
+
+fn invalid_code() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse::new("Invalid authentication code")),
+    )
+}
+
+/// Checks the second factor of a signed-in user. Wrong codes count against
+/// the username like failed logins, so a session cannot be used to guess
+/// codes without limit.
+async fn verify_signed_in_code(
+    pool: &DbPool,
+    tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+    user: &User,
+    code: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    };
+
+    if let Some(secs) = login_throttle::locked_for(pool, &user.username, ip)
+        .await
+        .map_err(db_error)?
+    {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(format!(
+                "Too many failed attempts; try again in {secs} seconds"
+            ))),
+        ));
+    }
+
+    if !two_factor::verify_second_factor(tx, user.id, code)
+        .await
+        .map_err(db_error)?
+    {
+        login_throttle::record_failure(pool, &user.username, ip)
+            .await
+            .map_err(db_error)?;
+        return Err(invalid_code());
+    }
+
+    login_throttle::record_success(pool, &user.username)
+        .await
+        .map_err(db_error)
+}
+
+/// Starts enrollment with a fresh secret. It only takes effect once a code
+/// generated from it is confirmed, so an abandoned enrollment changes nothing.
+pub async fn enroll(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<TwoFactorEnrollmentResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = fetch_user(&pool, claims.sub).await?;
+
+    if user.two_factor_enabled {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(
+                "Two-factor authentication is already enabled",
+            )),
+        ));
+    }
+
+    let secret = two_factor::generate_secret();
+    let otpauth_uri = two_factor::otpauth_uri(&secret, &user.username).ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("Failed to build TOTP secret")),
+        )
+    })?;
+    let qr_code_png = two_factor::qr_code_png(&otpauth_uri).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("QR code error: {e}"))),
+        )
+    })?;
+
+    sqlx::query("UPDATE users SET totp_pending_secret = ? WHERE id = ?")
+        .bind(&secret)
+        .bind(user.id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    Ok(Json(TwoFactorEnrollmentResponse {
+        secret,
+        otpauth_uri,
+        qr_code_png,
+    }))
+}
+
+/// Enables two-factor authentication and hands out the recovery codes.
+/// Every session is signed out, since none of them passed the second factor;
+/// only sign-ins that do get access tokens carrying `mfa`.
+pub async fn confirm(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<TwoFactorCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let row: Option<(String, Option<String>, bool)> = sqlx::query_as(
+        "SELECT username, totp_pending_secret, totp_enabled_at IS NOT NULL FROM users WHERE id = ?",
+    )
+    .bind(claims.sub)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let Some((username, pending_secret, enabled)) = row else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("User not found")),
+        ));
+    };
+
+    if enabled {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(
+                "Two-factor authentication is already enabled",
+            )),
+        ));
+    }
+
+    let Some(secret) = pending_secret else {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new("Start enrollment first")),
+        ));
+    };
+
+    let step = two_factor::verify_code(&secret, &username, &payload.code, None)
+        .ok_or_else(invalid_code)?;
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    };
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    sqlx::query(
+        "UPDATE users
+         SET totp_secret = totp_pending_secret, totp_pending_secret = NULL,
+             totp_enabled_at = NOW(), totp_last_step = ?
+         WHERE id = ? AND totp_pending_secret = ?",
+    )
+    .bind(step)
+    .bind(claims.sub)
+    .bind(&secret)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    let recovery_codes = two_factor::replace_recovery_codes(&mut tx, claims.sub)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    tokens::revoke_all_sessions(&pool, claims.sub)
+        .await
+        .map_err(db_error)?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "auth.2fa_enable",
+            target: format!("user:{}", claims.sub),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Replaces every recovery code, used or not.
+pub async fn regenerate_recovery_codes(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<TwoFactorCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = fetch_user(&pool, claims.sub).await?;
+    if !user.two_factor_enabled {
+        return Err(not_enabled());
+    }
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    };
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    verify_signed_in_code(&pool, &mut tx, &user, &payload.code, ip).await?;
+
+    let recovery_codes = two_factor::replace_recovery_codes(&mut tx, user.id)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(user.id),
+            action: "auth.2fa_recovery_codes",
+            target: format!("user:{}", user.id),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+pub async fn disable(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<DisableTwoFactorRequest>,
+) -> Result<Json<MessageResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = fetch_user_with_password(&pool, claims.sub, &payload.current_password).await?;
+    if !user.two_factor_enabled {
+        return Err(not_enabled());
+    }
+
+    if two_factor::required_for_role(&user.role) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new(format!(
+                "Two-factor authentication is required for the {} role",
+                user.role
+            ))),
+        ));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    };
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    verify_signed_in_code(&pool, &mut tx, &user, &payload.code, ip).await?;
+
+    sqlx::query(
+        "UPDATE users
+         SET totp_secret = NULL, totp_pending_secret = NULL,
+             totp_enabled_at = NULL, totp_last_step = NULL
+         WHERE id = ?",
+    )
+    .bind(user.id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(user.id),
+            action: "auth.2fa_disable",
+            target: format!("user:{}", user.id),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(MessageResponse::new(
+        "Two-factor authentication disabled",
+    )))
+}
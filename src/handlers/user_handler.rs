@@ -1,14 +1,34 @@
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
 use validator::Validate;
 
-use crate::auth::{create_token, hash_password, verify_password};
+use crate::accounts;
+use crate::audit::{self, AuditEvent, Outcome};
+use crate::auth::{
+    access_token_ttl_secs, create_token, dummy_password_hash, hash_password, password_needs_rehash,
+    role_permissions, verify_password, Claims, ROLE_AUTHOR,
+};
+use crate::client_ip::ClientIp;
 use crate::db::DbPool;
+use crate::email_verification;
+use crate::login_throttle;
 use crate::models::{
-    AuthResponse, ErrorResponse, LoginRequest, RegisterRequest, User, UserResponse,
+    AuthResponse, ErrorResponse, LoginRequest, LoginResponse, LogoutRequest, MessageResponse,
+    RefreshTokenRequest, RegisterRequest, TokenQuery, TwoFactorLoginRequest, User, UserResponse,
+    USER_SELECT,
 };
+use crate::moderation;
+use crate::password_policy;
+use crate::sessions::Device;
+use crate::tokens::{self, RefreshError};
+use crate::two_factor::{self, ChallengeOutcome};
 
 pub async fn register(
     State(pool): State<DbPool>,
+    device: Device,
     Json(payload): Json<RegisterRequest>,
 ) -> Result<Json<AuthResponse>, (StatusCode, Json<ErrorResponse>)> {
     if let Err(errors) = payload.validate() {
@@ -18,6 +38,9 @@ pub async fn register(
         ));
     }
 
+    password_policy::check(&payload.password, &payload.username, &payload.email)
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(ErrorResponse::new(message))))?;
+
     let existing_user: Option<(i32,)> =
         sqlx::query_as("SELECT id FROM users WHERE username = ? OR email = ?")
             .bind(&payload.username)
@@ -60,27 +83,52 @@ pub async fn register(
 
     let user_id = result.last_insert_id() as i32;
 
-    let token = create_token(user_id, &payload.username).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse::new(format!("Token creation error: {e}"))),
-        )
-    })?;
+    email_verification::send_verification(&pool, user_id, &payload.username, &payload.email)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
 
-    Ok(Json(AuthResponse {
-        token,
-        user: UserResponse {
+    let response = issue_tokens(
+        &pool,
+        UserResponse {
             id: user_id,
             username: payload.username,
             email: payload.email,
+            role: ROLE_AUTHOR.to_string(),
+            permissions: role_permissions(ROLE_AUTHOR).to_vec(),
+            email_verified: false,
+            two_factor_enabled: false,
+            ethereum_address: None,
         },
-    }))
+        &device,
+        false,
+    )
+    .await?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(user_id),
+            action: "auth.register",
+            target: format!("user:{user_id}"),
+            ip: device.ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(response))
 }
 
 pub async fn login(
     State(pool): State<DbPool>,
+    device: Device,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<AuthResponse>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
     if let Err(errors) = payload.validate() {
         return Err((
             StatusCode::BAD_REQUEST,
@@ -88,27 +136,38 @@ pub async fn login(
         ));
     }
 
-    let user: Option<User> = sqlx::query_as::<_, User>(
-        "SELECT id, username, email, password_hash, created_at, updated_at FROM users WHERE username = ?"
-    )
-    .bind(&payload.username)
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| {
+    let db_error = |e: sqlx::Error| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::new(format!("Database error: {e}"))),
         )
-    })?;
+    };
 
-    let user = user.ok_or_else(|| {
-        (
-            StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse::new("Invalid username or password")),
-        )
-    })?;
+    if let Some(secs) = login_throttle::locked_for(&pool, &payload.username, device.ip)
+        .await
+        .map_err(db_error)?
+    {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(format!(
+                "Too many failed login attempts; try again in {secs} seconds"
+            ))),
+        ));
+    }
+
+    let user: Option<User> =
+        sqlx::query_as::<_, User>(&format!("{USER_SELECT} WHERE username = ?"))
+            .bind(&payload.username)
+            .fetch_optional(&pool)
+            .await
+            .map_err(db_error)?;
 
-    let valid = verify_password(&payload.password, &user.password_hash).map_err(|e| {
+    // Unknown usernames still pay for a hash check so response times do not
+    // reveal which accounts exist.
+    let password_hash = user
+        .as_ref()
+        .map_or(dummy_password_hash(), |user| user.password_hash.as_str());
+    let valid = verify_password(&payload.password, password_hash).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::new(format!(
@@ -117,14 +176,300 @@ pub async fn login(
         )
     })?;
 
-    if !valid {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse::new("Invalid username or password")),
-        ));
+    let user = match user {
+        Some(user) if valid => user,
+        _ => {
+            login_throttle::record_failure(&pool, &payload.username, device.ip)
+                .await
+                .map_err(db_error)?;
+            audit::log(
+                &pool,
+                &AuditEvent {
+                    actor_id: user.map(|user| user.id),
+                    action: "auth.login",
+                    target: format!("username:{}", payload.username),
+                    ip: device.ip,
+                    outcome: Outcome::Denied,
+                    ..Default::default()
+                },
+            )
+            .await;
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::new("Invalid username or password")),
+            ));
+        }
+    };
+
+    // With a second factor the failures are only cleared once it passes.
+    if !user.two_factor_enabled {
+        login_throttle::record_success(&pool, &payload.username)
+            .await
+            .map_err(db_error)?;
+    }
+
+    if password_needs_rehash(&user.password_hash) {
+        rehash_password(&pool, user.id, &payload.password).await;
+    }
+
+    let response = start_session(&pool, user, &device).await?;
+
+    Ok(Json(response))
+}
+
+/// Upgrades a stored hash to the current algorithm and cost. The password
+/// already checked out, so a failure here only logs and keeps the old hash.
+async fn rehash_password(pool: &DbPool, user_id: i32, password: &str) {
+    let password_hash = match hash_password(password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::warn!("Failed to rehash password for user {}: {}", user_id, e);
+            return;
+        }
+    };
+    if let Err(e) = sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+        .bind(&password_hash)
+        .bind(user_id)
+        .execute(pool)
+        .await
+    {
+        tracing::warn!(
+            "Failed to store rehashed password for user {}: {}",
+            user_id,
+            e
+        );
     }
+}
+
+/// Refuses sign-in to suspended or banned accounts and to those with a
+/// forced password reset pending.
+async fn check_restriction(
+    pool: &DbPool,
+    user_id: i32,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let restriction = moderation::restriction(pool, user_id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    match restriction {
+        Some(restriction) => Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new(restriction.to_string())),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Signs in a user whose first factor checked out, or asks for the second
+/// factor when they enabled one.
+pub(crate) async fn start_session(
+    pool: &DbPool,
+    user: User,
+    device: &Device,
+) -> Result<LoginResponse, (StatusCode, Json<ErrorResponse>)> {
+    check_restriction(pool, user.id).await?;
+
+    if user.two_factor_enabled {
+        let challenge_token = two_factor::create_challenge(pool, user.id)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(format!("Database error: {e}"))),
+                )
+            })?;
+
+        return Ok(LoginResponse::TwoFactorRequired {
+            two_factor_required: true,
+            challenge_token,
+            expires_in: two_factor::challenge_ttl_secs(),
+        });
+    }
+
+    Ok(LoginResponse::Tokens(
+        complete_login(pool, user, device).await?,
+    ))
+}
+
+/// Second login step for accounts with two-factor authentication: trades the
+/// challenge token from `/login` plus a TOTP or recovery code for tokens.
+pub async fn login_two_factor(
+    State(pool): State<DbPool>,
+    device: Device,
+    Json(payload): Json<TwoFactorLoginRequest>,
+) -> Result<Json<AuthResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    };
+
+    // Wrong codes count against the same username and IP limits as wrong
+    // passwords, so fresh challenges do not buy more guesses.
+    let username = two_factor::challenge_username(&pool, &payload.challenge_token)
+        .await
+        .map_err(db_error)?;
+
+    if let Some(username) = &username {
+        if let Some(secs) = login_throttle::locked_for(&pool, username, device.ip)
+            .await
+            .map_err(db_error)?
+        {
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ErrorResponse::new(format!(
+                    "Too many failed login attempts; try again in {secs} seconds"
+                ))),
+            ));
+        }
+    }
+
+    let outcome = two_factor::complete_challenge(&pool, &payload.challenge_token, &payload.code)
+        .await
+        .map_err(db_error)?;
+
+    let user_id = match outcome {
+        ChallengeOutcome::Passed(user_id) => user_id,
+        ChallengeOutcome::WrongCode => {
+            if let Some(username) = &username {
+                login_throttle::record_failure(&pool, username, device.ip)
+                    .await
+                    .map_err(db_error)?;
+            }
+            audit::log(
+                &pool,
+                &AuditEvent {
+                    action: "auth.login_2fa",
+                    target: "challenge".to_string(),
+                    ip: device.ip,
+                    outcome: Outcome::Denied,
+                    ..Default::default()
+                },
+            )
+            .await;
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::new("Invalid authentication code")),
+            ));
+        }
+        ChallengeOutcome::Invalid => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::new("Invalid or expired challenge token")),
+            ))
+        }
+    };
+
+    let user: User = sqlx::query_as::<_, User>(&format!("{USER_SELECT} WHERE id = ?"))
+        .bind(user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::new("Invalid or expired challenge token")),
+            )
+        })?;
+
+    login_throttle::record_success(&pool, &user.username)
+        .await
+        .map_err(db_error)?;
+
+    check_restriction(&pool, user.id).await?;
+
+    let response = complete_login(&pool, user, &device).await?;
+
+    Ok(Json(response))
+}
+
+async fn complete_login(
+    pool: &DbPool,
+    user: User,
+    device: &Device,
+) -> Result<AuthResponse, (StatusCode, Json<ErrorResponse>)> {
+    // Signing in during the grace period keeps the account.
+    if accounts::cancel_deletion(pool, user.id)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?
+    {
+        tracing::info!("Cancelled scheduled deletion of user {}", user.id);
+    }
+
+    // Accounts with two-factor authentication only get here after passing it.
+    let user_id = user.id;
+    let mfa = user.two_factor_enabled;
+    let response = issue_tokens(pool, user.into(), device, mfa).await?;
+
+    audit::log(
+        pool,
+        &AuditEvent {
+            actor_id: Some(user_id),
+            action: "auth.login",
+            target: format!("user:{user_id}"),
+            ip: device.ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(response)
+}
+
+pub async fn refresh_token(
+    State(pool): State<DbPool>,
+    device: Device,
+    Json(payload): Json<RefreshTokenRequest>,
+) -> Result<Json<AuthResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let rotation = tokens::rotate_refresh_token(&pool, &payload.refresh_token, &device)
+        .await
+        .map_err(|e| match e {
+            RefreshError::Database(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            ),
+            e => (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::new(e.to_string())),
+            ),
+        })?;
 
-    let token = create_token(user.id, &user.username).map_err(|e| {
+    let user: User = sqlx::query_as::<_, User>(&format!("{USER_SELECT} WHERE id = ?"))
+        .bind(rotation.user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse::new("Invalid refresh token")),
+            )
+        })?;
+
+    check_restriction(&pool, user.id).await?;
+
+    let token = create_token(
+        user.id,
+        &user.username,
+        &user.role,
+        rotation.mfa,
+        rotation.session_id,
+    )
+    .map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse::new(format!("Token creation error: {e}"))),
@@ -133,6 +478,200 @@ pub async fn login(
 
     Ok(Json(AuthResponse {
         token,
+        refresh_token: rotation.refresh_token,
+        expires_in: access_token_ttl_secs(),
         user: user.into(),
     }))
 }
+
+pub async fn logout(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    payload: Option<Json<LogoutRequest>>,
+) -> Result<Json<MessageResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let Json(payload) = payload.unwrap_or_default();
+
+    tokens::revoke_access_token(&pool, &claims)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if let Some(refresh_token) = payload.refresh_token {
+        tokens::revoke_refresh_token(&pool, claims.sub, &refresh_token)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new(format!("Database error: {e}"))),
+                )
+            })?;
+    }
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "auth.logout",
+            target: format!("user:{}", claims.sub),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(MessageResponse::new("Logged out")))
+}
+
+pub async fn logout_all(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+) -> Result<Json<MessageResponse>, (StatusCode, Json<ErrorResponse>)> {
+    tokens::revoke_all_sessions(&pool, claims.sub)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "auth.logout_all",
+            target: format!("user:{}", claims.sub),
+            ip,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    Ok(Json(MessageResponse::new("Logged out of all sessions")))
+}
+
+pub async fn verify_email(
+    State(pool): State<DbPool>,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<MessageResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let verified = email_verification::verify_token(&pool, &query.token)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if !verified {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Invalid or expired verification token")),
+        ));
+    }
+
+    Ok(Json(MessageResponse::new("Email address verified")))
+}
+
+pub async fn resend_verification_email(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+) -> Result<(StatusCode, Json<MessageResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let user: Option<(String, String, bool)> = sqlx::query_as(
+        "SELECT username, email, email_verified_at IS NOT NULL FROM users WHERE id = ?",
+    )
+    .bind(claims.sub)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let (username, email, verified) = user.ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("User not found")),
+        )
+    })?;
+
+    if verified {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new("Email address is already verified")),
+        ));
+    }
+
+    let wait = email_verification::resend_wait_secs(&pool, claims.sub)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if let Some(wait) = wait {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorResponse::new(format!(
+                "Please wait {wait} seconds before requesting another verification email"
+            ))),
+        ));
+    }
+
+    email_verification::send_verification(&pool, claims.sub, &username, &email)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(MessageResponse::new("Verification email sent")),
+    ))
+}
+
+/// Starts a session for `user`; `mfa` says whether this sign-in passed a
+/// second factor and is carried over to every refreshed access token.
+pub(crate) async fn issue_tokens(
+    pool: &DbPool,
+    user: UserResponse,
+    device: &Device,
+    mfa: bool,
+) -> Result<AuthResponse, (StatusCode, Json<ErrorResponse>)> {
+    let (refresh_token, session_id) = tokens::issue_refresh_token(pool, user.id, device, mfa)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    let token =
+        create_token(user.id, &user.username, &user.role, mfa, session_id).map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Token creation error: {e}"))),
+            )
+        })?;
+
+    Ok(AuthResponse {
+        token,
+        refresh_token,
+        expires_in: access_token_ttl_secs(),
+        user,
+    })
+}
@@ -1,13 +1,42 @@
-use axum::{http::StatusCode, Json};
+use axum::{extract::State, http::StatusCode, Extension, Json};
 use ethers_core::k256::ecdsa::SigningKey;
 use ethers_core::utils::{hex, secret_key_to_address};
 use rand::thread_rng;
 use validator::Validate;
 
+use crate::audit::{self, AuditEvent, Outcome};
+use crate::auth::Claims;
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
 use crate::models::{ErrorResponse, GenerateWalletsRequest, GenerateWalletsResponse, WalletInfo};
 
 pub async fn generate_wallets(
+    State(pool): State<DbPool>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<GenerateWalletsRequest>,
+) -> Result<Json<GenerateWalletsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let target = format!("wallets:{}", payload.count);
+    let result = generate(payload);
+
+    audit::log(
+        &pool,
+        &AuditEvent {
+            actor_id: Some(claims.sub),
+            action: "wallet.generate",
+            target,
+            ip,
+            outcome: Outcome::of(&result),
+            request_digest: None,
+        },
+    )
+    .await;
+
+    result
+}
+
+fn generate(
+    payload: GenerateWalletsRequest,
 ) -> Result<Json<GenerateWalletsResponse>, (StatusCode, Json<ErrorResponse>)> {
     if let Err(errors) = payload.validate() {
         return Err((
@@ -0,0 +1,110 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Form, Json,
+};
+use url::Url;
+
+use crate::db::DbPool;
+use crate::models::{ErrorResponse, MessageResponse, WebmentionRequest, WebmentionResponse};
+use crate::webmention;
+
+pub async fn receive_webmention(
+    State(pool): State<DbPool>,
+    Form(payload): Form<WebmentionRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let is_http = |value: &str| {
+        Url::parse(value)
+            .map(|url| matches!(url.scheme(), "http" | "https"))
+            .unwrap_or(false)
+    };
+
+    if !is_http(&payload.source) || !is_http(&payload.target) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Source and target must be http(s) URLs")),
+        ));
+    }
+
+    if payload.source == payload.target {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Source and target must differ")),
+        ));
+    }
+
+    let post_id = webmention::post_id_from_target(&payload.target).ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Target does not accept webmentions")),
+        )
+    })?;
+
+    let post: Option<(i32,)> = sqlx::query_as("SELECT id FROM posts WHERE id = ?")
+        .bind(post_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(format!("Database error: {e}"))),
+            )
+        })?;
+
+    if post.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new("Target does not accept webmentions")),
+        ));
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO webmentions (post_id, source_url, target_url, status)
+         VALUES (?, ?, ?, 'pending')
+         ON DUPLICATE KEY UPDATE id = LAST_INSERT_ID(id), status = 'pending', error = NULL",
+    )
+    .bind(post_id)
+    .bind(&payload.source)
+    .bind(&payload.target)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    let mention_id = result.last_insert_id() as i64;
+    tokio::spawn(async move {
+        webmention::verify(&pool, mention_id, &payload.source, &payload.target).await;
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(MessageResponse::new("Webmention queued for verification")),
+    ))
+}
+
+pub async fn get_post_webmentions(
+    State(pool): State<DbPool>,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<WebmentionResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let mentions: Vec<WebmentionResponse> = sqlx::query_as::<_, WebmentionResponse>(
+        "SELECT id, source_url, title, verified_at
+         FROM webmentions
+         WHERE post_id = ? AND status = 'verified'
+         ORDER BY verified_at DESC",
+    )
+    .bind(id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new(format!("Database error: {e}"))),
+        )
+    })?;
+
+    Ok(Json(mentions))
+}
@@ -0,0 +1,143 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect;
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use url::Url;
+
+const MAX_REDIRECTS: usize = 10;
+
+pub fn shared() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(concat!("blog-api/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("HTTP client configuration is valid")
+    })
+}
+
+/// Client for URLs that come from untrusted input, such as webmention sources
+/// and remote ActivityPub actors. Hosts are only connected to if every address
+/// they resolve to is public, and each redirect is checked the same way. Pair
+/// it with `check_public_url`, since hosts given as IP literals skip DNS.
+pub fn public_only() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(concat!("blog-api/", env!("CARGO_PKG_VERSION")))
+            // A proxy would resolve hosts itself and bypass the address check.
+            .no_proxy()
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    return attempt.error("Too many redirects");
+                }
+                match check_public_url(attempt.url()) {
+                    Ok(()) => attempt.follow(),
+                    Err(e) => attempt.error(e),
+                }
+            }))
+            .build()
+            .expect("HTTP client configuration is valid")
+    })
+}
+
+/// Lets `public_only` reach private networks, for development setups where
+/// federated peers run on localhost or a LAN.
+fn private_networks_allowed() -> bool {
+    env::var("ALLOW_PRIVATE_NETWORK_FETCHES")
+        .map(|value| value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// Whether the address is globally routable, i.e. not loopback, private,
+/// link-local (which includes cloud metadata services), shared, reserved,
+/// documentation or multicast space.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_ipv4(mapped),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // 100.64.0.0/10, carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // 192.0.0.0/24, IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // 198.18.0.0/15, benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // 240.0.0.0/4, reserved
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // 2001:db8::/32, documentation
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // 64:ff9b:1::/48, local-use NAT64
+        || (first == 0x0064 && ip.segments()[1] == 0xff9b && ip.segments()[2] == 1))
+}
+
+/// Rejects URLs that are not http(s) or whose host is a non-public IP
+/// literal. Hostnames are checked by `public_only` when they resolve.
+pub fn check_public_url(url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Unsupported URL scheme: {}", url.scheme()));
+    }
+    let ip = match url.host() {
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(url::Host::Domain(_)) => return Ok(()),
+        None => return Err(format!("URL has no host: {url}")),
+    };
+    if is_public_ip(ip) || private_networks_allowed() {
+        Ok(())
+    } else {
+        Err(format!("Refusing to connect to non-public address {ip}"))
+    }
+}
+
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if !private_networks_allowed() {
+                if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+                    return Err(format!(
+                        "Refusing to connect to {host}: it resolves to non-public address {}",
+                        addr.ip()
+                    )
+                    .into());
+                }
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
@@ -0,0 +1,97 @@
+use std::env;
+
+/// Size of `posts.language`.
+const MAX_TAG_LEN: usize = 16;
+
+pub fn default_language() -> String {
+    env::var("DEFAULT_POST_LANGUAGE")
+        .ok()
+        .and_then(|tag| normalize_language(&tag))
+        .unwrap_or_else(|| "en".to_string())
+}
+
+/// Normalizes a BCP 47 style tag: `ZH-cn` becomes `zh-CN`, `en_us` becomes `en-US`.
+/// Tags longer than `posts.language` allows are rejected.
+pub fn normalize_language(tag: &str) -> Option<String> {
+    let mut parts = tag.trim().split(['-', '_']);
+    let primary = parts.next()?;
+    if !(2..=3).contains(&primary.len()) || !primary.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut normalized = primary.to_ascii_lowercase();
+    for part in parts {
+        if !(2..=8).contains(&part.len()) || !part.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        normalized.push('-');
+        match part.len() {
+            2 => normalized.push_str(&part.to_ascii_uppercase()),
+            4 => {
+                let mut chars = part.chars();
+                if let Some(first) = chars.next() {
+                    normalized.push(first.to_ascii_uppercase());
+                    normalized.push_str(&chars.as_str().to_ascii_lowercase());
+                }
+            }
+            _ => normalized.push_str(&part.to_ascii_lowercase()),
+        }
+    }
+
+    (normalized.len() <= MAX_TAG_LEN).then_some(normalized)
+}
+
+fn primary_subtag(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
+
+/// Parses an `Accept-Language` header into tags ordered by descending quality.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32, usize)> = header
+        .split(',')
+        .enumerate()
+        .filter_map(|(index, range)| {
+            let mut params = range.split(';');
+            let tag = params.next()?.trim();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                return None;
+            }
+            let tag = if tag == "*" {
+                "*".to_string()
+            } else {
+                normalize_language(tag)?
+            };
+            Some((tag, quality, index))
+        })
+        .collect();
+
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.2.cmp(&b.2)));
+    ranges.into_iter().map(|(tag, _, _)| tag).collect()
+}
+
+/// Picks the index of the best available language for the given preferences.
+/// An exact tag match wins over a match on the primary subtag alone.
+pub fn best_match(available: &[String], preferences: &[String]) -> Option<usize> {
+    for preferred in preferences {
+        if preferred == "*" {
+            return None;
+        }
+        if let Some(index) = available
+            .iter()
+            .position(|tag| tag.eq_ignore_ascii_case(preferred))
+        {
+            return Some(index);
+        }
+        if let Some(index) = available
+            .iter()
+            .position(|tag| primary_subtag(tag).eq_ignore_ascii_case(primary_subtag(preferred)))
+        {
+            return Some(index);
+        }
+    }
+    None
+}
@@ -0,0 +1,217 @@
+use base64::{
+    engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL},
+    Engine,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo; the raw key follows.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+#[derive(Debug)]
+pub struct JwtKeyError(pub String);
+
+impl std::fmt::Display for JwtKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for JwtKeyError {}
+
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// The key access tokens are signed with plus every key they are still
+/// accepted under, loaded once at startup.
+pub struct JwtKeys {
+    algorithm: Algorithm,
+    /// `None` for HS256, whose tokens carry no `kid`.
+    kid: Option<String>,
+    encoding_key: EncodingKey,
+    verification_keys: HashMap<String, VerificationKey>,
+    /// HS256 verification key, used for tokens without a `kid`.
+    secret_key: Option<DecodingKey>,
+    jwks: Value,
+}
+
+impl JwtKeys {
+    pub fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.kid.clone();
+        header
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding_key
+    }
+
+    /// The key and algorithm a token with this `kid` must verify under.
+    /// Pinning the algorithm to the key rules out algorithm confusion.
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<(&DecodingKey, Algorithm)> {
+        match kid {
+            Some(kid) => self
+                .verification_keys
+                .get(kid)
+                .map(|key| (&key.key, key.algorithm)),
+            None => self.secret_key.as_ref().map(|key| (key, Algorithm::HS256)),
+        }
+    }
+
+    /// Public verification keys as a JWK Set; empty for HS256.
+    pub fn jwks(&self) -> &Value {
+        &self.jwks
+    }
+}
+
+fn pem_der(pem: &str) -> Result<Vec<u8>, JwtKeyError> {
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    BASE64
+        .decode(body.trim())
+        .map_err(|e| JwtKeyError(format!("Invalid PEM: {e}")))
+}
+
+/// Builds the JWK for a public key PEM, with its RFC 7638 thumbprint as `kid`.
+fn public_jwk(pem: &str) -> Result<(Value, VerificationKey), JwtKeyError> {
+    let (thumbprint_input, mut jwk, verification) =
+        if let Ok(public_key) = RsaPublicKey::from_public_key_pem(pem) {
+            let n = BASE64_URL.encode(public_key.n().to_bytes_be());
+            let e = BASE64_URL.encode(public_key.e().to_bytes_be());
+            let key = DecodingKey::from_rsa_components(&n, &e)
+                .map_err(|e| JwtKeyError(format!("Invalid RSA key: {e}")))?;
+            (
+                format!(r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#),
+                json!({ "kty": "RSA", "alg": "RS256", "n": n, "e": e }),
+                VerificationKey {
+                    algorithm: Algorithm::RS256,
+                    key,
+                },
+            )
+        } else {
+            let der = pem_der(pem)?;
+            let raw = der
+                .strip_prefix(&ED25519_SPKI_PREFIX[..])
+                .filter(|raw| raw.len() == 32)
+                .ok_or_else(|| JwtKeyError("Public key is neither RSA nor Ed25519".to_string()))?;
+            let x = BASE64_URL.encode(raw);
+            (
+                format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{x}"}}"#),
+                json!({ "kty": "OKP", "crv": "Ed25519", "alg": "EdDSA", "x": x }),
+                VerificationKey {
+                    algorithm: Algorithm::EdDSA,
+                    key: DecodingKey::from_ed_der(raw),
+                },
+            )
+        };
+
+    let kid = BASE64_URL.encode(Sha256::digest(thumbprint_input.as_bytes()));
+    jwk["kid"] = json!(kid);
+    jwk["use"] = json!("sig");
+    Ok((jwk, verification))
+}
+
+fn read_key_file(var: &str) -> Result<String, JwtKeyError> {
+    let path = env::var(var).map_err(|_| JwtKeyError(format!("{var} must be set")))?;
+    fs::read_to_string(&path).map_err(|e| JwtKeyError(format!("Cannot read {path}: {e}")))
+}
+
+/// Reads the keys from the environment:
+///
+/// - `JWT_ALGORITHM`: `HS256` (default), `RS256` or `EdDSA`.
+/// - HS256 signs with `JWT_SECRET`.
+/// - RS256/EdDSA sign with the PKCS#8 PEM in `JWT_PRIVATE_KEY_FILE`, whose
+///   public half is in `JWT_PUBLIC_KEY_FILE`.
+/// - `JWT_PREVIOUS_PUBLIC_KEY_FILES` (comma separated) lists retired public
+///   keys whose tokens are still accepted during a rotation.
+pub fn load() -> Result<JwtKeys, JwtKeyError> {
+    let algorithm = match env::var("JWT_ALGORITHM").as_deref() {
+        Err(_) | Ok("HS256") => Algorithm::HS256,
+        Ok("RS256") => Algorithm::RS256,
+        Ok("EdDSA") => Algorithm::EdDSA,
+        Ok(other) => return Err(JwtKeyError(format!("Unsupported JWT_ALGORITHM {other}"))),
+    };
+
+    if algorithm == Algorithm::HS256 {
+        let secret = env::var("JWT_SECRET")
+            .map_err(|_| JwtKeyError("JWT_SECRET must be set".to_string()))?;
+        return Ok(JwtKeys {
+            algorithm,
+            kid: None,
+            encoding_key: EncodingKey::from_secret(secret.as_bytes()),
+            verification_keys: HashMap::new(),
+            secret_key: Some(DecodingKey::from_secret(secret.as_bytes())),
+            jwks: json!({ "keys": [] }),
+        });
+    }
+
+    let private_pem = read_key_file("JWT_PRIVATE_KEY_FILE")?;
+    let encoding_key = match algorithm {
+        Algorithm::RS256 => EncodingKey::from_rsa_pem(private_pem.as_bytes()),
+        _ => EncodingKey::from_ed_pem(private_pem.as_bytes()),
+    }
+    .map_err(|e| JwtKeyError(format!("Invalid JWT_PRIVATE_KEY_FILE: {e}")))?;
+
+    let (current, current_key) = public_jwk(&read_key_file("JWT_PUBLIC_KEY_FILE")?)?;
+    if current_key.algorithm != algorithm {
+        return Err(JwtKeyError(
+            "JWT_PUBLIC_KEY_FILE does not match JWT_ALGORITHM".to_string(),
+        ));
+    }
+    let kid = current["kid"].as_str().unwrap_or_default().to_string();
+
+    let mut jwks = vec![current];
+    let mut verification_keys = HashMap::from([(kid.clone(), current_key)]);
+
+    for path in env::var("JWT_PREVIOUS_PUBLIC_KEY_FILES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+    {
+        let pem = fs::read_to_string(path)
+            .map_err(|e| JwtKeyError(format!("Cannot read {path}: {e}")))?;
+        let (jwk, key) = public_jwk(&pem)?;
+        let previous_kid = jwk["kid"].as_str().unwrap_or_default().to_string();
+        if verification_keys.insert(previous_kid, key).is_none() {
+            jwks.push(jwk);
+        }
+    }
+
+    Ok(JwtKeys {
+        algorithm,
+        kid: Some(kid),
+        encoding_key,
+        verification_keys,
+        secret_key: None,
+        jwks: json!({ "keys": jwks }),
+    })
+}
+
+static KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+/// Loads the keys up front so a misconfiguration stops the server at startup.
+pub fn init() -> Result<(), JwtKeyError> {
+    if KEYS.get().is_none() {
+        let _ = KEYS.set(load()?);
+    }
+    Ok(())
+}
+
+pub fn keys() -> &'static JwtKeys {
+    KEYS.get_or_init(|| load().unwrap_or_else(|e| panic!("Invalid JWT key configuration: {e}")))
+}
@@ -1,4 +1,30 @@
+pub mod accounts;
+pub mod activitypub;
+pub mod api_keys;
+pub mod audit;
 pub mod auth;
+pub mod client_ip;
+pub mod config;
+pub mod content;
 pub mod db;
+pub mod email_verification;
+pub mod graphql;
 pub mod handlers;
+pub mod http_client;
+pub mod i18n;
+pub mod jwt_keys;
+pub mod login_throttle;
+pub mod mailer;
 pub mod models;
+pub mod moderation;
+pub mod newsletter;
+pub mod oidc;
+pub mod password_policy;
+pub mod password_reset;
+pub mod recommend;
+pub mod routes;
+pub mod sessions;
+pub mod siwe;
+pub mod tokens;
+pub mod two_factor;
+pub mod webmention;
@@ -0,0 +1,201 @@
+use std::env;
+use std::net::IpAddr;
+
+use crate::audit::{self, AuditEvent, Outcome};
+use crate::db::DbPool;
+
+const SCOPE_USERNAME: &str = "username";
+const SCOPE_IP: &str = "ip";
+const SCOPE_RESET_EMAIL: &str = "reset_email";
+const SCOPE_RESET_IP: &str = "reset_ip";
+
+fn env_secs(name: &str, default: i64) -> i64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(default)
+}
+
+/// Failed attempts on one username before it is locked.
+fn max_attempts_per_username() -> i64 {
+    env_secs("LOGIN_MAX_ATTEMPTS", 5)
+}
+
+/// Failed attempts from one IP, across usernames, before it is locked.
+fn max_attempts_per_ip() -> i64 {
+    env_secs("LOGIN_MAX_ATTEMPTS_PER_IP", 20)
+}
+
+/// Failures older than this no longer count.
+fn attempt_window_secs() -> i64 {
+    env_secs("LOGIN_ATTEMPT_WINDOW_SECS", 900)
+}
+
+/// Lockout after the first failure over the limit; it doubles with every
+/// further failure up to `LOGIN_LOCKOUT_MAX_SECS`.
+pub fn lockout_secs(failures: i64, max_attempts: i64) -> i64 {
+    let base = env_secs("LOGIN_LOCKOUT_BASE_SECS", 30);
+    let max = env_secs("LOGIN_LOCKOUT_MAX_SECS", 3600);
+    let doublings = (failures - max_attempts).clamp(0, 20) as u32;
+    base.saturating_mul(1 << doublings).min(max)
+}
+
+fn subject_key(subject: &str) -> String {
+    subject.trim().to_lowercase()
+}
+
+fn subjects(username: &str, ip: Option<IpAddr>) -> Vec<(&'static str, String)> {
+    let mut subjects = vec![(SCOPE_USERNAME, subject_key(username))];
+    if let Some(ip) = ip {
+        subjects.push((SCOPE_IP, ip.to_string()));
+    }
+    subjects
+}
+
+fn reset_subjects(email: &str, ip: Option<IpAddr>) -> Vec<(&'static str, String)> {
+    let mut subjects = vec![(SCOPE_RESET_EMAIL, subject_key(email))];
+    if let Some(ip) = ip {
+        subjects.push((SCOPE_RESET_IP, ip.to_string()));
+    }
+    subjects
+}
+
+/// Seconds until the username or IP may try again, if either is locked.
+pub async fn locked_for(
+    pool: &DbPool,
+    username: &str,
+    ip: Option<IpAddr>,
+) -> Result<Option<i64>, sqlx::Error> {
+    locked_for_subjects(pool, subjects(username, ip)).await
+}
+
+/// Seconds until another password reset email may be requested for the
+/// address or from the IP, if either is locked.
+pub async fn reset_locked_for(
+    pool: &DbPool,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> Result<Option<i64>, sqlx::Error> {
+    locked_for_subjects(pool, reset_subjects(email, ip)).await
+}
+
+async fn locked_for_subjects(
+    pool: &DbPool,
+    subjects: Vec<(&'static str, String)>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let mut remaining = None;
+    for (scope, subject) in subjects {
+        let secs: Option<i64> = sqlx::query_scalar(
+            "SELECT CEIL(TIMESTAMPDIFF(MICROSECOND, NOW(3), locked_until) / 1000000)
+             FROM login_throttles
+             WHERE scope = ? AND subject = ? AND locked_until > NOW(3)",
+        )
+        .bind(scope)
+        .bind(&subject)
+        .fetch_optional(pool)
+        .await?;
+        remaining = remaining.max(secs);
+    }
+    Ok(remaining)
+}
+
+/// Counts a failed login against the username and the IP and locks whichever
+/// went over its limit. Lockouts are written to the audit log.
+pub async fn record_failure(
+    pool: &DbPool,
+    username: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), sqlx::Error> {
+    record_failures(pool, subjects(username, ip), ip).await
+}
+
+/// Counts a password reset request against the address and the IP, using the
+/// same limits and lockouts as failed logins.
+pub async fn record_reset_request(
+    pool: &DbPool,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), sqlx::Error> {
+    record_failures(pool, reset_subjects(email, ip), ip).await
+}
+
+async fn record_failures(
+    pool: &DbPool,
+    subjects: Vec<(&'static str, String)>,
+    ip: Option<IpAddr>,
+) -> Result<(), sqlx::Error> {
+    for (scope, subject) in subjects {
+        sqlx::query(
+            "INSERT INTO login_throttles (scope, subject, failures, last_failure_at)
+             VALUES (?, ?, 1, NOW(3))
+             ON DUPLICATE KEY UPDATE
+                 failures = IF(last_failure_at < NOW(3) - INTERVAL ? SECOND, 1, failures + 1),
+                 last_failure_at = NOW(3)",
+        )
+        .bind(scope)
+        .bind(&subject)
+        .bind(attempt_window_secs())
+        .execute(pool)
+        .await?;
+
+        let failures: i64 = sqlx::query_scalar(
+            "SELECT failures FROM login_throttles WHERE scope = ? AND subject = ?",
+        )
+        .bind(scope)
+        .bind(&subject)
+        .fetch_one(pool)
+        .await?;
+
+        let max_attempts = if scope == SCOPE_IP || scope == SCOPE_RESET_IP {
+            max_attempts_per_ip()
+        } else {
+            max_attempts_per_username()
+        };
+        if failures < max_attempts {
+            continue;
+        }
+
+        let lockout = lockout_secs(failures, max_attempts);
+        sqlx::query(
+            "UPDATE login_throttles SET locked_until = NOW(3) + INTERVAL ? SECOND
+             WHERE scope = ? AND subject = ?",
+        )
+        .bind(lockout)
+        .bind(scope)
+        .bind(&subject)
+        .execute(pool)
+        .await?;
+
+        tracing::warn!(
+            "Locked {} {} for {}s after {} failures",
+            scope,
+            subject,
+            lockout,
+            failures
+        );
+        audit::record(
+            pool,
+            &AuditEvent {
+                action: "auth.lockout",
+                target: format!("{scope}:{subject}"),
+                ip,
+                outcome: Outcome::Denied,
+                ..Default::default()
+            },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// A successful login clears the username's failures. The IP's are kept so
+/// one known password does not reset a spray across other accounts.
+pub async fn record_success(pool: &DbPool, username: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_throttles WHERE scope = ? AND subject = ?")
+        .bind(SCOPE_USERNAME)
+        .bind(subject_key(username))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use async_trait::async_trait;
+use lettre::{
+    message::{
+        header::{ContentType, HeaderName, HeaderValue},
+        Mailbox,
+    },
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Serialize;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Email {
+    pub fn new(to: impl Into<String>, subject: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            to: to.into(),
+            subject: subject.into(),
+            body: body.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct MailerError(pub String);
+
+impl std::fmt::Display for MailerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MailerError {}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), MailerError>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(
+        transport: AsyncSmtpTransport<Tokio1Executor>,
+        from: &str,
+    ) -> Result<Self, MailerError> {
+        let from = from
+            .parse()
+            .map_err(|e| MailerError(format!("Invalid sender address: {e}")))?;
+        Ok(Self { transport, from })
+    }
+
+    pub fn plaintext(host: &str, port: u16, from: &str) -> Result<Self, MailerError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .build();
+        Self::new(transport, from)
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailerError> {
+        let to: Mailbox = email
+            .to
+            .parse()
+            .map_err(|e| MailerError(format!("Invalid recipient address: {e}")))?;
+
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .header(ContentType::TEXT_PLAIN);
+        for (name, value) in &email.headers {
+            let name = HeaderName::new_from_ascii(name.clone())
+                .map_err(|e| MailerError(format!("Invalid header name: {e}")))?;
+            builder = builder.raw_header(HeaderValue::new(name, value.clone()));
+        }
+
+        let message = builder
+            .body(email.body.clone())
+            .map_err(|e| MailerError(format!("Failed to build email: {e}")))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| MailerError(format!("SMTP error: {e}")))?;
+
+        Ok(())
+    }
+}
+
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailerError> {
+        tracing::info!(
+            "Email to {} with subject {:?}:\n{}",
+            email.to,
+            email.subject,
+            email.body
+        );
+        Ok(())
+    }
+}
+
+/// Appends every email as one JSON line to a file; meant for tests and local development.
+pub struct FileMailer {
+    path: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailerError> {
+        let mut line = serde_json::to_string(email)
+            .map_err(|e| MailerError(format!("Failed to encode email: {e}")))?;
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| MailerError(format!("Failed to open mail file: {e}")))?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|e| MailerError(format!("Failed to write mail file: {e}")))?;
+
+        Ok(())
+    }
+}
+
+/// `MAIL_FILE` selects the file mailer, `SMTP_HOST` the SMTP mailer; otherwise
+/// emails are only logged.
+pub fn from_env() -> Result<Arc<dyn Mailer>, MailerError> {
+    if let Ok(path) = env::var("MAIL_FILE") {
+        if !path.is_empty() {
+            return Ok(Arc::new(FileMailer::new(path)));
+        }
+    }
+
+    let host = match env::var("SMTP_HOST") {
+        Ok(host) if !host.is_empty() => host,
+        _ => return Ok(Arc::new(LogMailer)),
+    };
+
+    let from = env::var("MAIL_FROM").unwrap_or_else(|_| "Blog <no-reply@localhost>".to_string());
+    let port = env::var("SMTP_PORT")
+        .ok()
+        .and_then(|p| p.parse::<u16>().ok());
+    let tls = env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string());
+
+    let mut builder = match tls.as_str() {
+        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)
+            .map_err(|e| MailerError(format!("Invalid SMTP relay: {e}")))?,
+        "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+            .map_err(|e| MailerError(format!("Invalid SMTP relay: {e}")))?,
+        other => return Err(MailerError(format!("Unsupported SMTP_TLS mode: {other}"))),
+    };
+
+    if let Some(port) = port {
+        builder = builder.port(port);
+    }
+
+    match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+        (Ok(username), Ok(password)) if !username.is_empty() => {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        _ => {}
+    }
+
+    Ok(Arc::new(SmtpMailer::new(builder.build(), &from)?))
+}
@@ -1,14 +1,10 @@
-use axum::{
-    middleware,
-    routing::{delete, get, post, put},
-    Router,
-};
 use dotenv::dotenv;
+use std::net::SocketAddr;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use blog_api::{auth, db, handlers};
+use blog_api::{accounts, content, db, jwt_keys, newsletter, routes};
 
 #[tokio::main]
 async fn main() {
@@ -22,48 +18,30 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    jwt_keys::init().expect("Invalid JWT key configuration");
+    newsletter::check_signing_secret().expect("Invalid newsletter configuration");
+
     let pool = db::create_pool()
         .await
         .expect("Failed to create database pool");
 
+    match content::backfill_post_meta(&pool).await {
+        Ok(0) => {}
+        Ok(filled) => tracing::info!("Derived summaries for {} existing posts", filled),
+        Err(e) => tracing::error!("Failed to derive summaries for existing posts: {}", e),
+    }
+
+    newsletter::spawn_digest_loop(pool.clone());
+    accounts::spawn_purge_loop(pool.clone());
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let public_routes = Router::new()
-        .route("/register", post(handlers::user_handler::register))
-        .route("/login", post(handlers::user_handler::login))
-        .route("/posts", get(handlers::post_handler::get_posts))
-        .route("/posts/:id", get(handlers::post_handler::get_post));
-
-    let protected_routes = Router::new()
-        .route("/posts", post(handlers::post_handler::create_post))
-        .route("/posts/:id", put(handlers::post_handler::update_post))
-        .route("/posts/:id", delete(handlers::post_handler::delete_post))
-        .route(
-            "/wallets/generate",
-            post(handlers::wallet_handler::generate_wallets),
-        )
-        .route(
-            "/transfer/batch",
-            post(handlers::transfer_handler::batch_transfer),
-        )
-        .route(
-            "/contract/call",
-            post(handlers::contract_handler::call_contract),
-        )
-        .route_layer(middleware::from_fn_with_state(
-            pool.clone(),
-            auth::auth_middleware,
-        ));
-
-    let app = Router::new()
-        .merge(public_routes)
-        .merge(protected_routes)
+    let app = routes::app(pool)
         .layer(cors)
-        .layer(TraceLayer::new_for_http())
-        .with_state(pool);
+        .layer(TraceLayer::new_for_http());
 
     let addr = std::env::var("SERVER_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".to_string());
 
@@ -73,7 +51,10 @@ async fn main() {
 
     tracing::info!("Server running on {}", addr);
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("Failed to start server");
 }
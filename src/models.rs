@@ -2,6 +2,9 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
+use crate::accounts::PostDisposal;
+use crate::auth::{role_permissions, Permission};
+
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
     pub id: i32,
@@ -9,23 +12,31 @@ pub struct User {
     pub email: String,
     #[serde(skip_serializing)]
     pub password_hash: String,
+    pub role: String,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub two_factor_enabled: bool,
+    pub ethereum_address: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+pub const USER_SELECT: &str = "SELECT id, username, email, password_hash, role, email_verified_at,
+            totp_enabled_at IS NOT NULL AS two_factor_enabled, ethereum_address,
+            created_at, updated_at
+     FROM users";
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct RegisterRequest {
     #[validate(length(min = 3, max = 50))]
     pub username: String,
     #[validate(email)]
     pub email: String,
-    #[validate(length(min = 6))]
     pub password: String,
 }
 
 #[derive(Debug, Deserialize, Validate)]
 pub struct LoginRequest {
-    #[validate(length(min = 3))]
+    #[validate(length(min = 3, max = 50))]
     pub username: String,
     #[validate(length(min = 6))]
     pub password: String,
@@ -34,14 +45,103 @@ pub struct LoginRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub token: String,
+    pub refresh_token: String,
+    /// Lifetime of `token` in seconds.
+    pub expires_in: i64,
     pub user: UserResponse,
 }
 
+/// `/login` either signs the user in or, with two-factor authentication
+/// enabled, asks for a code at `/login/2fa`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(AuthResponse),
+    TwoFactorRequired {
+        two_factor_required: bool,
+        challenge_token: String,
+        /// Lifetime of `challenge_token` in seconds.
+        expires_in: i64,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    /// A TOTP code or an unused recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SiweNonceResponse {
+    pub nonce: String,
+    /// Seconds until the nonce can no longer be used.
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SiweRequest {
+    /// The EIP-4361 message exactly as it was signed.
+    pub message: String,
+    /// Hex-encoded `personal_sign` signature.
+    pub signature: String,
+}
+
+/// Query of the redirect back from an OpenID Connect provider.
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcAuthorizationResponse {
+    pub authorization_url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct IdentityResponse {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LogoutRequest {
+    /// Also revokes this refresh token's family.
+    pub refresh_token: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserResponse {
     pub id: i32,
     pub username: String,
     pub email: String,
+    pub role: String,
+    pub permissions: Vec<Permission>,
+    pub email_verified: bool,
+    pub two_factor_enabled: bool,
+    pub ethereum_address: Option<String>,
 }
 
 impl From<User> for UserResponse {
@@ -50,10 +150,261 @@ impl From<User> for UserResponse {
             id: user.id,
             username: user.username,
             email: user.email,
+            permissions: role_permissions(&user.role).to_vec(),
+            role: user.role,
+            email_verified: user.email_verified_at.is_some(),
+            two_factor_enabled: user.two_factor_enabled,
+            ethereum_address: user.ethereum_address,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub created_at: DateTime<Utc>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangeEmailRequest {
+    #[validate(email, length(max = 100))]
+    pub email: String,
+    pub current_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub current_password: String,
+    pub posts: PostDisposal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountDeletionResponse {
+    pub delete_after: DateTime<Utc>,
+    pub posts: PostDisposal,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorEnrollmentResponse {
+    /// Base32 secret for authenticator apps that cannot scan the QR code.
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// Base64-encoded PNG of `otpauth_uri`.
+    pub qr_code_png: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown once; each code can replace a TOTP code a single time.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorRequest {
+    pub current_password: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(length(min = 1))]
+    pub scopes: Vec<Permission>,
+    /// Omit for a key that never expires.
+    #[validate(range(min = 1, max = 3650))]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ApiKeyResponse {
+    pub id: i64,
+    pub name: String,
+    #[sqlx(json)]
+    pub scopes: Vec<Permission>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SessionResponse {
+    pub id: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// Whether this is the session making the request.
+    #[sqlx(skip)]
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AuditEventResponse {
+    pub id: i64,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub target: String,
+    pub ip: Option<String>,
+    pub outcome: String,
+    pub request_digest: Option<String>,
+    pub prev_hash: String,
+    pub hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor_id: Option<i32>,
+    pub action: Option<String>,
+    /// Matches every action starting with it, e.g. `auth.`.
+    pub action_prefix: Option<String>,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Pages backwards from the oldest event of the previous page.
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl AuditQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 500)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditVerification {
+    pub valid: bool,
+    pub checked: u64,
+    pub first_invalid_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedApiKeyResponse {
+    #[serde(flatten)]
+    pub key: ApiKeyResponse,
+    /// Shown only once; only its hash is stored.
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssignRoleRequest {
+    pub role: String,
+}
+
+pub const MODERATED_USER_SELECT: &str = "SELECT id, username, email, password_hash, role,
+            email_verified_at, totp_enabled_at IS NOT NULL AS two_factor_enabled,
+            ethereum_address, created_at, updated_at, password_reset_required,
+            suspended_until, banned_at, moderation_reason
+     FROM users";
+
+/// A user together with the moderation state only admins see.
+#[derive(Debug, sqlx::FromRow)]
+pub struct ModeratedUser {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub password_reset_required: bool,
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub banned_at: Option<DateTime<Utc>>,
+    pub moderation_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    /// `active`, `suspended` or `banned`.
+    pub status: String,
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub banned_at: Option<DateTime<Utc>>,
+    pub moderation_reason: Option<String>,
+    pub password_reset_required: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ModeratedUser> for AdminUserResponse {
+    fn from(moderated: ModeratedUser) -> Self {
+        let status = if moderated.banned_at.is_some() {
+            "banned"
+        } else if moderated
+            .suspended_until
+            .is_some_and(|until| until > Utc::now())
+        {
+            "suspended"
+        } else {
+            "active"
+        };
+        Self {
+            status: status.to_string(),
+            suspended_until: moderated.suspended_until,
+            banned_at: moderated.banned_at,
+            moderation_reason: moderated.moderation_reason,
+            password_reset_required: moderated.password_reset_required,
+            created_at: moderated.user.created_at,
+            user: moderated.user.into(),
         }
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct UserSearchQuery {
+    /// Matches part of the username or email.
+    pub q: Option<String>,
+    pub role: Option<String>,
+    pub status: Option<String>,
+    pub before_id: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+impl UserSearchQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 500)
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SuspendUserRequest {
+    pub until: DateTime<Utc>,
+    #[validate(length(max = 500))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct BanUserRequest {
+    #[validate(length(max = 500))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImpersonationResponse {
+    /// An access token for the user; it cannot be refreshed.
+    pub token: String,
+    pub expires_in: i64,
+    pub user: UserResponse,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleResponse {
+    pub role: &'static str,
+    pub permissions: &'static [Permission],
+}
+
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Post {
     pub id: i32,
@@ -64,33 +415,170 @@ pub struct Post {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct CreatePostRequest {
     #[validate(length(min = 1, max = 255))]
     pub title: String,
     #[validate(length(min = 1))]
     pub content: String,
+    #[validate(length(max = 500))]
+    pub excerpt: Option<String>,
+    pub language: Option<String>,
+    /// Links the new post into the translation group of this post.
+    pub translation_of: Option<i32>,
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct UpdatePostRequest {
     #[validate(length(min = 1, max = 255))]
     pub title: Option<String>,
     #[validate(length(min = 1))]
     pub content: Option<String>,
+    /// An empty string drops a custom excerpt in favour of the derived one.
+    #[validate(length(max = 500))]
+    pub excerpt: Option<String>,
+    pub language: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u8,
+    pub text: String,
+    pub anchor: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct PostResponse {
     pub id: i32,
     pub title: String,
     pub content: String,
+    pub excerpt: String,
+    pub reading_time_minutes: i32,
+    #[sqlx(json)]
+    pub toc: Vec<TocEntry>,
+    pub language: String,
+    pub translation_group: Option<i32>,
     pub user_id: i32,
     pub username: String,
+    pub pinned: bool,
+    pub featured_slot: Option<i32>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+pub const POST_RESPONSE_SELECT: &str =
+    "SELECT p.id, p.title, p.content, p.excerpt, p.reading_time_minutes, p.toc, p.language,
+            p.translation_group, p.user_id, u.username, pp.post_id IS NOT NULL AS pinned,
+            fs.slot AS featured_slot, p.created_at, p.updated_at
+     FROM posts p
+     JOIN users u ON p.user_id = u.id
+     LEFT JOIN pinned_posts pp
+         ON pp.post_id = p.id AND (pp.expires_at IS NULL OR pp.expires_at > NOW())
+     LEFT JOIN featured_slots fs
+         ON fs.post_id = p.id AND (fs.expires_at IS NULL OR fs.expires_at > NOW())";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PostListQuery {
+    pub author_id: Option<i32>,
+    pub language: Option<String>,
+    /// Put the author's pinned posts first.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Put the site-wide featured posts first, in slot order.
+    #[serde(default)]
+    pub featured: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TranslationResponse {
+    pub id: i32,
+    pub language: String,
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PinPostRequest {
+    #[validate(range(min = 0, max = 100))]
+    pub position: Option<i32>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeaturedSlotRequest {
+    pub post_id: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct FeaturedSlotResponse {
+    pub slot: i32,
+    pub post_id: i32,
+    pub title: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub assigned_by: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecommendQuery {
+    pub limit: Option<usize>,
+}
+
+impl RecommendQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(10).clamp(1, 50)
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SubscribeRequest {
+    #[validate(email, length(max = 100))]
+    pub email: String,
+    pub author_id: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeQuery {
+    pub id: i32,
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebFingerQuery {
+    pub resource: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebmentionRequest {
+    pub source: String,
+    pub target: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct WebmentionResponse {
+    pub id: i64,
+    pub source_url: String,
+    pub title: Option<String>,
+    pub verified_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+impl MessageResponse {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
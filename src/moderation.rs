@@ -0,0 +1,232 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+
+use crate::db::DbPool;
+use crate::models::{ModeratedUser, UserSearchQuery, MODERATED_USER_SELECT};
+use crate::tokens;
+
+/// Account statuses an admin can filter the user list by.
+pub const STATUSES: [&str; 3] = ["active", "suspended", "banned"];
+
+/// Why a user may not sign in or use their tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restriction {
+    Banned,
+    Suspended(DateTime<Utc>),
+    /// An admin forced a password reset; sign-in resumes once it is done.
+    /// Sessions and API keys are revoked when it is forced and again when
+    /// the reset completes, so none issued before it keep working.
+    PasswordResetRequired,
+}
+
+impl Restriction {
+    /// Whether already issued tokens and API keys stop working as well, not
+    /// just new sign-ins.
+    pub fn blocks_requests(&self) -> bool {
+        !matches!(self, Restriction::PasswordResetRequired)
+    }
+}
+
+impl std::fmt::Display for Restriction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Restriction::Banned => f.write_str("This account has been banned"),
+            Restriction::Suspended(until) => write!(
+                f,
+                "This account is suspended until {}",
+                until.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            ),
+            Restriction::PasswordResetRequired => f.write_str(
+                "A password reset is required; use the link sent to your email or request a new one",
+            ),
+        }
+    }
+}
+
+/// user id -> (restriction, trusted until)
+type StatusCache = HashMap<i32, (Option<Restriction>, Instant)>;
+
+fn status_cache() -> MutexGuard<'static, StatusCache> {
+    static CACHE: OnceLock<Mutex<StatusCache>> = OnceLock::new();
+    CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Drops the cached status so changes made by this process apply at once;
+/// other instances pick them up within the revocation cache TTL.
+pub fn forget(user_id: i32) {
+    status_cache().remove(&user_id);
+}
+
+/// The restriction currently in force for the user, if any. Expired
+/// suspensions no longer count.
+pub async fn restriction(pool: &DbPool, user_id: i32) -> Result<Option<Restriction>, sqlx::Error> {
+    let now = Instant::now();
+    let cached = {
+        let mut cache = status_cache();
+        cache.retain(|_, (_, until)| *until > now);
+        cache.get(&user_id).map(|(restriction, _)| *restriction)
+    };
+
+    let restriction = match cached {
+        Some(restriction) => restriction,
+        None => {
+            let row: Option<(bool, Option<DateTime<Utc>>, bool)> = sqlx::query_as(
+                "SELECT banned_at IS NOT NULL, suspended_until, password_reset_required
+                 FROM users WHERE id = ?",
+            )
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+            let restriction = match row {
+                Some((true, _, _)) => Some(Restriction::Banned),
+                Some((_, Some(until), _)) if until > Utc::now() => {
+                    Some(Restriction::Suspended(until))
+                }
+                Some((_, _, true)) => Some(Restriction::PasswordResetRequired),
+                _ => None,
+            };
+            status_cache().insert(user_id, (restriction, now + tokens::revocation_cache_ttl()));
+            restriction
+        }
+    };
+
+    Ok(restriction.filter(|restriction| match restriction {
+        Restriction::Suspended(until) => *until > Utc::now(),
+        _ => true,
+    }))
+}
+
+pub async fn search(
+    pool: &DbPool,
+    query: &UserSearchQuery,
+) -> Result<Vec<ModeratedUser>, sqlx::Error> {
+    let mut conditions = vec!["deleted_at IS NULL"];
+    if query.q.is_some() {
+        conditions.push("(username LIKE CONCAT('%', ?, '%') OR email LIKE CONCAT('%', ?, '%'))");
+    }
+    if query.role.is_some() {
+        conditions.push("role = ?");
+    }
+    match query.status.as_deref() {
+        Some("active") => conditions
+            .push("banned_at IS NULL AND (suspended_until IS NULL OR suspended_until <= NOW())"),
+        Some("suspended") => conditions.push("banned_at IS NULL AND suspended_until > NOW()"),
+        Some("banned") => conditions.push("banned_at IS NOT NULL"),
+        _ => {}
+    }
+    if query.before_id.is_some() {
+        conditions.push("id < ?");
+    }
+
+    let sql = format!(
+        "{MODERATED_USER_SELECT} WHERE {} ORDER BY id DESC LIMIT ?",
+        conditions.join(" AND ")
+    );
+
+    let mut query_builder = sqlx::query_as::<_, ModeratedUser>(&sql);
+    if let Some(q) = &query.q {
+        let escaped = q
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        query_builder = query_builder.bind(escaped.clone()).bind(escaped);
+    }
+    if let Some(role) = &query.role {
+        query_builder = query_builder.bind(role);
+    }
+    if let Some(before_id) = query.before_id {
+        query_builder = query_builder.bind(before_id);
+    }
+
+    query_builder.bind(query.limit()).fetch_all(pool).await
+}
+
+pub async fn find(pool: &DbPool, user_id: i32) -> Result<Option<ModeratedUser>, sqlx::Error> {
+    sqlx::query_as::<_, ModeratedUser>(&format!(
+        "{MODERATED_USER_SELECT} WHERE id = ? AND deleted_at IS NULL"
+    ))
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Runs a status change on a live account. Returns `false` if there is no
+/// such account.
+async fn update_status(
+    pool: &DbPool,
+    user_id: i32,
+    query: sqlx::query::Query<'_, sqlx::MySql, sqlx::mysql::MySqlArguments>,
+) -> Result<bool, sqlx::Error> {
+    let result = query.execute(pool).await?;
+    forget(user_id);
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn suspend(
+    pool: &DbPool,
+    user_id: i32,
+    until: DateTime<Utc>,
+    reason: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    update_status(
+        pool,
+        user_id,
+        sqlx::query(
+            "UPDATE users SET suspended_until = ?, moderation_reason = ?, updated_at = updated_at
+             WHERE id = ? AND deleted_at IS NULL",
+        )
+        .bind(until)
+        .bind(reason)
+        .bind(user_id),
+    )
+    .await
+}
+
+pub async fn ban(pool: &DbPool, user_id: i32, reason: Option<&str>) -> Result<bool, sqlx::Error> {
+    update_status(
+        pool,
+        user_id,
+        sqlx::query(
+            "UPDATE users SET banned_at = COALESCE(banned_at, NOW()), moderation_reason = ?,
+                    updated_at = updated_at
+             WHERE id = ? AND deleted_at IS NULL",
+        )
+        .bind(reason)
+        .bind(user_id),
+    )
+    .await
+}
+
+/// Lifts a suspension or ban.
+pub async fn reinstate(pool: &DbPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    update_status(
+        pool,
+        user_id,
+        sqlx::query(
+            "UPDATE users SET suspended_until = NULL, banned_at = NULL, moderation_reason = NULL,
+                    updated_at = updated_at
+             WHERE id = ? AND deleted_at IS NULL",
+        )
+        .bind(user_id),
+    )
+    .await
+}
+
+pub async fn require_password_reset(pool: &DbPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    update_status(
+        pool,
+        user_id,
+        sqlx::query(
+            "UPDATE users SET password_reset_required = TRUE, updated_at = updated_at
+             WHERE id = ? AND deleted_at IS NULL",
+        )
+        .bind(user_id),
+    )
+    .await
+}
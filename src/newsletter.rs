@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use minijinja::{context, Environment};
+use serde::Serialize;
+use sha2::Sha256;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{base_url, site_name};
+use crate::db::DbPool;
+use crate::mailer::{self, Email, Mailer};
+
+const DIGEST_TEMPLATE: &str = include_str!("../templates/newsletter_digest.txt");
+const CONFIRM_TEMPLATE: &str = include_str!("../templates/newsletter_confirm.txt");
+const SUMMARY_CHARS: usize = 200;
+
+fn configured_secret() -> Option<String> {
+    ["NEWSLETTER_SECRET", "JWT_SECRET"]
+        .into_iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+}
+
+/// Checked at startup: with `JWT_ALGORITHM=RS256` or `EdDSA` there may be no
+/// `JWT_SECRET` to fall back on.
+pub fn check_signing_secret() -> Result<(), String> {
+    configured_secret()
+        .map(|_| ())
+        .ok_or_else(|| "NEWSLETTER_SECRET or JWT_SECRET must be set".to_string())
+}
+
+fn signing_secret() -> String {
+    configured_secret().expect("NEWSLETTER_SECRET or JWT_SECRET must be set")
+}
+
+fn unsubscribe_mac(subscription_id: i32) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(format!("unsubscribe:{subscription_id}").as_bytes());
+    mac
+}
+
+pub fn unsubscribe_token(subscription_id: i32) -> String {
+    hex::encode(unsubscribe_mac(subscription_id).finalize().into_bytes())
+}
+
+pub fn verify_unsubscribe_token(subscription_id: i32, token: &str) -> bool {
+    match hex::decode(token) {
+        Ok(bytes) => unsubscribe_mac(subscription_id)
+            .verify_slice(&bytes)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn unsubscribe_url(subscription_id: i32) -> String {
+    format!(
+        "{}/newsletter/unsubscribe?id={}&token={}",
+        base_url(),
+        subscription_id,
+        unsubscribe_token(subscription_id)
+    )
+}
+
+pub fn render_confirmation(
+    email: &str,
+    author: Option<&str>,
+    confirm_token: &str,
+) -> Result<String, minijinja::Error> {
+    let env = Environment::new();
+    env.render_str(
+        CONFIRM_TEMPLATE,
+        context! {
+            email,
+            author,
+            site_name => site_name(),
+            confirm_url => format!("{}/newsletter/confirm?token={}", base_url(), confirm_token),
+        },
+    )
+}
+
+#[derive(Debug, Serialize)]
+pub struct DigestPost {
+    pub title: String,
+    pub username: String,
+    pub created_at: String,
+    pub summary: String,
+    pub url: String,
+}
+
+pub fn render_digest(
+    author: Option<&str>,
+    posts: &[DigestPost],
+    unsubscribe_url: &str,
+) -> Result<String, minijinja::Error> {
+    let env = Environment::new();
+    env.render_str(
+        DIGEST_TEMPLATE,
+        context! {
+            author,
+            posts,
+            unsubscribe_url,
+            site_name => site_name(),
+        },
+    )
+}
+
+fn summarize(content: &str) -> String {
+    let mut summary: String = content.chars().take(SUMMARY_CHARS).collect();
+    if content.chars().count() > SUMMARY_CHARS {
+        summary.push('…');
+    }
+    summary
+}
+
+type DigestRow = (i32, String, Option<i32>, Option<String>, DateTime<Utc>);
+
+type DigestPostRow = (i32, String, String, String, DateTime<Utc>);
+
+pub async fn send_digests(pool: &DbPool, mailer: &dyn Mailer) -> Result<usize, sqlx::Error> {
+    let subscriptions: Vec<DigestRow> = sqlx::query_as(
+        "SELECT s.id, s.email, s.author_id, u.username, COALESCE(s.last_sent_at, s.confirmed_at)
+         FROM newsletter_subscriptions s
+         LEFT JOIN users u ON s.author_id = u.id
+         WHERE s.confirmed_at IS NOT NULL AND s.unsubscribed_at IS NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut sent = 0;
+
+    for (subscription_id, email, author_id, author, since) in subscriptions {
+        let posts: Vec<DigestPostRow> = sqlx::query_as(
+            "SELECT p.id, p.title, p.content, u.username, p.created_at
+             FROM posts p
+             JOIN users u ON p.user_id = u.id
+             WHERE p.created_at > ? AND (? IS NULL OR p.user_id = ?)
+             ORDER BY p.created_at ASC",
+        )
+        .bind(since)
+        .bind(author_id)
+        .bind(author_id)
+        .fetch_all(pool)
+        .await?;
+
+        if posts.is_empty() {
+            continue;
+        }
+
+        let post_count = posts.len() as i32;
+        // The next digest starts after the newest post in this one, so posts
+        // created while it goes out are not skipped.
+        let newest = posts.last().map_or(since, |post| post.4);
+        let digest_posts: Vec<DigestPost> = posts
+            .into_iter()
+            .map(|(id, title, content, username, created_at)| DigestPost {
+                title,
+                username,
+                created_at: created_at.format("%Y-%m-%d").to_string(),
+                summary: summarize(&content),
+                url: format!("{}/posts/{}", base_url(), id),
+            })
+            .collect();
+
+        let subject = match &author {
+            Some(author) => format!("New posts from {author} on {}", site_name()),
+            None => format!("New posts on {}", site_name()),
+        };
+        let unsubscribe_url = unsubscribe_url(subscription_id);
+
+        let result = match render_digest(author.as_deref(), &digest_posts, &unsubscribe_url) {
+            Ok(body) => {
+                let message = Email::new(&email, &subject, body)
+                    .header("List-Unsubscribe", format!("<{unsubscribe_url}>"))
+                    .header("List-Unsubscribe-Post", "List-Unsubscribe=One-Click");
+                mailer.send(&message).await.map_err(|e| e.to_string())
+            }
+            Err(e) => Err(format!("Template error: {e}")),
+        };
+
+        let (status, error) = match &result {
+            Ok(()) => ("sent", None),
+            Err(e) => ("failed", Some(e.as_str())),
+        };
+
+        sqlx::query(
+            "INSERT INTO newsletter_send_log (subscription_id, email, subject, post_count, status, error)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(subscription_id)
+        .bind(&email)
+        .bind(&subject)
+        .bind(post_count)
+        .bind(status)
+        .bind(error)
+        .execute(pool)
+        .await?;
+
+        match result {
+            Ok(()) => {
+                sqlx::query("UPDATE newsletter_subscriptions SET last_sent_at = ? WHERE id = ?")
+                    .bind(newest)
+                    .bind(subscription_id)
+                    .execute(pool)
+                    .await?;
+                sent += 1;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to send digest to subscription {}: {}",
+                    subscription_id,
+                    e
+                );
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+pub fn spawn_digest_loop(pool: DbPool) {
+    let interval_secs = env::var("NEWSLETTER_DIGEST_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    if interval_secs == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+
+            let mailer: Arc<dyn Mailer> = match mailer::from_env() {
+                Ok(mailer) => mailer,
+                Err(e) => {
+                    tracing::error!("Failed to configure mailer: {}", e);
+                    continue;
+                }
+            };
+
+            match send_digests(&pool, mailer.as_ref()).await {
+                Ok(sent) => tracing::info!("Sent {} newsletter digests", sent),
+                Err(e) => tracing::error!("Failed to send newsletter digests: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,508 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::auth::{generate_opaque_token, hash_opaque_token, hash_password};
+use crate::config::base_url;
+use crate::db::DbPool;
+use crate::http_client;
+
+/// How long a started login may take to come back from the provider.
+const LOGIN_TTL_SECS: i64 = 600;
+const METADATA_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+pub enum OidcError {
+    UnknownProvider,
+    /// The callback's `state` is unknown, expired or already used.
+    InvalidState,
+    /// The provider misbehaved or returned an unacceptable ID token.
+    Provider(String),
+    /// The identity cannot be attached to the requested account.
+    Conflict(String),
+    RegistrationClosed,
+    Internal(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for OidcError {
+    fn from(e: sqlx::Error) -> Self {
+        OidcError::Database(e)
+    }
+}
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OidcError::UnknownProvider => f.write_str("Unknown identity provider"),
+            OidcError::InvalidState => f.write_str("Invalid or expired login state"),
+            OidcError::Provider(reason) => write!(f, "Identity provider error: {reason}"),
+            OidcError::Conflict(reason) => f.write_str(reason),
+            OidcError::RegistrationClosed => f.write_str("No account is linked to this identity"),
+            OidcError::Internal(reason) => f.write_str(reason),
+            OidcError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for OidcError {}
+
+fn provider_error(e: impl std::fmt::Display) -> OidcError {
+    OidcError::Provider(e.to_string())
+}
+
+/// A provider configured through `OIDC_<NAME>_*` variables.
+#[derive(Debug, Clone)]
+pub struct ProviderConfig {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: String,
+    pub redirect_uri: String,
+    /// Create an account for identities not linked to one yet.
+    pub allow_registration: bool,
+    /// Attach identities to the account with the same verified email.
+    pub link_by_email: bool,
+}
+
+/// Names listed in `OIDC_PROVIDERS`, comma separated.
+pub fn provider_names() -> Vec<String> {
+    env::var("OIDC_PROVIDERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+pub fn provider(name: &str) -> Option<ProviderConfig> {
+    let name = name.to_ascii_lowercase();
+    if !provider_names().contains(&name) {
+        return None;
+    }
+
+    let prefix = format!("OIDC_{}_", name.to_ascii_uppercase().replace('-', "_"));
+    let var = |key: &str| {
+        env::var(format!("{prefix}{key}"))
+            .ok()
+            .filter(|value| !value.is_empty())
+    };
+    let flag = |key: &str, default: bool| var(key).map_or(default, |value| value == "true");
+
+    Some(ProviderConfig {
+        issuer: var("ISSUER")?.trim_end_matches('/').to_string(),
+        client_id: var("CLIENT_ID")?,
+        client_secret: var("CLIENT_SECRET"),
+        scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+        redirect_uri: var("REDIRECT_URI")
+            .unwrap_or_else(|| format!("{}/oidc/{name}/callback", base_url())),
+        allow_registration: flag("ALLOW_REGISTRATION", true),
+        link_by_email: flag("LINK_BY_EMAIL", false),
+        name,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Default)]
+struct MetadataCache {
+    /// issuer -> (discovery document, fetched at)
+    discovery: HashMap<String, (ProviderMetadata, Instant)>,
+    /// jwks_uri -> (key set, fetched at)
+    keys: HashMap<String, (JwkSet, Instant)>,
+}
+
+fn metadata_cache() -> MutexGuard<'static, MetadataCache> {
+    static CACHE: OnceLock<Mutex<MetadataCache>> = OnceLock::new();
+    CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, OidcError> {
+    http_client::shared()
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(provider_error)?
+        .json()
+        .await
+        .map_err(provider_error)
+}
+
+async fn discover(config: &ProviderConfig) -> Result<ProviderMetadata, OidcError> {
+    if let Some((metadata, fetched)) = metadata_cache().discovery.get(&config.issuer) {
+        if fetched.elapsed() < METADATA_CACHE_TTL {
+            return Ok(metadata.clone());
+        }
+    }
+
+    let metadata: ProviderMetadata = fetch_json(&format!(
+        "{}/.well-known/openid-configuration",
+        config.issuer
+    ))
+    .await?;
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(OidcError::Provider(
+            "Discovery document is for a different issuer".to_string(),
+        ));
+    }
+
+    metadata_cache()
+        .discovery
+        .insert(config.issuer.clone(), (metadata.clone(), Instant::now()));
+    Ok(metadata)
+}
+
+/// Finds the signing key `kid`, refetching the key set once if the provider
+/// has rotated to a key we have not seen.
+async fn signing_key(metadata: &ProviderMetadata, kid: &str) -> Result<DecodingKey, OidcError> {
+    let cached = metadata_cache()
+        .keys
+        .get(&metadata.jwks_uri)
+        .filter(|(_, fetched)| fetched.elapsed() < METADATA_CACHE_TTL)
+        .and_then(|(keys, _)| keys.find(kid).cloned());
+
+    let jwk = match cached {
+        Some(jwk) => jwk,
+        None => {
+            let keys: JwkSet = fetch_json(&metadata.jwks_uri).await?;
+            let jwk = keys.find(kid).cloned();
+            metadata_cache()
+                .keys
+                .insert(metadata.jwks_uri.clone(), (keys, Instant::now()));
+            jwk.ok_or_else(|| OidcError::Provider(format!("Unknown signing key {kid}")))?
+        }
+    };
+
+    DecodingKey::from_jwk(&jwk).map_err(provider_error)
+}
+
+/// Starts an authorization-code flow with PKCE and returns the provider URL
+/// to send the browser to. With `link_user_id` the identity is attached to
+/// that account instead of signing in.
+pub async fn begin_login(
+    pool: &DbPool,
+    config: &ProviderConfig,
+    link_user_id: Option<i32>,
+) -> Result<String, OidcError> {
+    let metadata = discover(config).await?;
+
+    let state = generate_opaque_token();
+    let nonce = generate_opaque_token();
+    let code_verifier = generate_opaque_token();
+    let code_challenge = BASE64_URL.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    sqlx::query("DELETE FROM oidc_logins WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    sqlx::query(
+        "INSERT INTO oidc_logins (state_hash, provider, code_verifier, nonce, link_user_id, expires_at)
+         VALUES (?, ?, ?, ?, ?, NOW() + INTERVAL ? SECOND)",
+    )
+    .bind(hash_opaque_token(&state))
+    .bind(&config.name)
+    .bind(&code_verifier)
+    .bind(&nonce)
+    .bind(link_user_id)
+    .bind(LOGIN_TTL_SECS)
+    .execute(pool)
+    .await?;
+
+    let url = Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("scope", config.scopes.as_str()),
+            ("state", state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(provider_error)?;
+
+    Ok(url.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+}
+
+/// The verified identity an ID token asserts.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+/// Handles the provider's redirect back: spends the login state, redeems the
+/// code and validates the ID token. Returns the identity and the account the
+/// login was started to link, if any.
+pub async fn complete_login(
+    pool: &DbPool,
+    config: &ProviderConfig,
+    code: &str,
+    state: &str,
+) -> Result<(Identity, Option<i32>), OidcError> {
+    let mut tx = pool.begin().await?;
+    let login: Option<(String, String, Option<i32>)> = sqlx::query_as(
+        "SELECT code_verifier, nonce, link_user_id FROM oidc_logins
+         WHERE state_hash = ? AND provider = ? AND expires_at > NOW()
+         FOR UPDATE",
+    )
+    .bind(hash_opaque_token(state))
+    .bind(&config.name)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let (code_verifier, nonce, link_user_id) = login.ok_or(OidcError::InvalidState)?;
+    sqlx::query("DELETE FROM oidc_logins WHERE state_hash = ?")
+        .bind(hash_opaque_token(state))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    let metadata = discover(config).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_uri.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", code_verifier.as_str()),
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+
+    let response = http_client::shared()
+        .post(&metadata.token_endpoint)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .form(&form)
+        .send()
+        .await
+        .map_err(provider_error)?;
+    if !response.status().is_success() {
+        return Err(OidcError::Provider(format!(
+            "Token request failed with {}",
+            response.status()
+        )));
+    }
+    let tokens: TokenResponse = response.json().await.map_err(provider_error)?;
+
+    let header = decode_header(&tokens.id_token).map_err(provider_error)?;
+    // Only signatures made with the provider's published keys are accepted.
+    if !matches!(
+        header.alg,
+        Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512
+            | Algorithm::ES256
+            | Algorithm::ES384
+            | Algorithm::EdDSA
+    ) {
+        return Err(OidcError::Provider(format!(
+            "Unsupported ID token algorithm {:?}",
+            header.alg
+        )));
+    }
+    let kid = header
+        .kid
+        .ok_or_else(|| OidcError::Provider("ID token has no key id".to_string()))?;
+    let key = signing_key(&metadata, &kid).await?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_audience(&[&config.client_id]);
+    let claims = decode::<IdTokenClaims>(&tokens.id_token, &key, &validation)
+        .map_err(provider_error)?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(nonce.as_str()) {
+        return Err(OidcError::Provider("ID token nonce mismatch".to_string()));
+    }
+
+    Ok((
+        Identity {
+            provider: config.name.clone(),
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+            preferred_username: claims.preferred_username,
+        },
+        link_user_id,
+    ))
+}
+
+async fn insert_identity<'e, E>(
+    executor: E,
+    user_id: i32,
+    identity: &Identity,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::MySql>,
+{
+    sqlx::query(
+        "INSERT INTO user_identities (user_id, provider, subject, email) VALUES (?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(&identity.provider)
+    .bind(&identity.subject)
+    .bind(&identity.email)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+fn username_candidate(identity: &Identity) -> Option<String> {
+    let name: String = identity
+        .preferred_username
+        .as_deref()?
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(40)
+        .collect();
+    (name.len() >= 3).then_some(name)
+}
+
+/// Finds or creates the account an identity signs in to and returns its id.
+pub async fn resolve_user(
+    pool: &DbPool,
+    config: &ProviderConfig,
+    identity: &Identity,
+    link_user_id: Option<i32>,
+) -> Result<i32, OidcError> {
+    let linked: Option<(i32,)> = sqlx::query_as(
+        "SELECT i.user_id FROM user_identities i
+         JOIN users u ON u.id = i.user_id
+         WHERE i.provider = ? AND i.subject = ? AND u.deleted_at IS NULL",
+    )
+    .bind(&identity.provider)
+    .bind(&identity.subject)
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some((user_id,)) = linked {
+        if link_user_id.is_some_and(|link| link != user_id) {
+            return Err(OidcError::Conflict(
+                "Identity is already linked to another account".to_string(),
+            ));
+        }
+        return Ok(user_id);
+    }
+
+    if let Some(user_id) = link_user_id {
+        insert_identity(pool, user_id, identity).await?;
+        return Ok(user_id);
+    }
+
+    let verified_email = identity
+        .email
+        .as_deref()
+        .filter(|_| identity.email_verified);
+
+    if let Some(email) = verified_email.filter(|_| config.link_by_email) {
+        let existing: Option<(i32,)> =
+            sqlx::query_as("SELECT id FROM users WHERE email = ? AND deleted_at IS NULL")
+                .bind(email)
+                .fetch_optional(pool)
+                .await?;
+        if let Some((user_id,)) = existing {
+            insert_identity(pool, user_id, identity).await?;
+            return Ok(user_id);
+        }
+    }
+
+    if !config.allow_registration {
+        return Err(OidcError::RegistrationClosed);
+    }
+
+    let subject_hash = &hash_opaque_token(&identity.subject)[..12];
+    let fallback_username = format!("{}-{subject_hash}", identity.provider);
+    let mut username = username_candidate(identity).unwrap_or_else(|| fallback_username.clone());
+    let taken: Option<(i32,)> = sqlx::query_as("SELECT id FROM users WHERE username = ?")
+        .bind(&username)
+        .fetch_optional(pool)
+        .await?;
+    if taken.is_some() {
+        username = fallback_username;
+    }
+
+    let email_taken = match verified_email {
+        Some(email) => sqlx::query_as::<_, (i32,)>("SELECT id FROM users WHERE email = ?")
+            .bind(email)
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+        None => true,
+    };
+    let (email, email_verified) = match verified_email {
+        Some(email) if !email_taken => (email.to_string(), true),
+        _ => (
+            format!("{}-{subject_hash}@oidc.invalid", identity.provider),
+            false,
+        ),
+    };
+
+    // The account signs in through its provider; nobody knows this password.
+    let password_hash = hash_password(&generate_opaque_token())
+        .map_err(|e| OidcError::Internal(format!("Password hashing error: {e}")))?;
+
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query(
+        "INSERT INTO users (username, email, password_hash, email_verified_at)
+         VALUES (?, ?, ?, IF(?, NOW(), NULL))",
+    )
+    .bind(&username)
+    .bind(&email)
+    .bind(&password_hash)
+    .bind(email_verified)
+    .execute(&mut *tx)
+    .await?;
+    let user_id = result.last_insert_id() as i32;
+    insert_identity(&mut *tx, user_id, identity).await?;
+    tx.commit().await?;
+
+    tracing::info!(
+        "Created account {} for {} identity {}",
+        user_id,
+        identity.provider,
+        identity.subject
+    );
+    Ok(user_id)
+}
@@ -0,0 +1,175 @@
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::OnceLock;
+
+/// SHA-1 hashes of common breached passwords, one uppercase hex digest per
+/// line. `BREACHED_PASSWORDS_FILE` swaps in a larger list in the same format;
+/// a `:count` suffix as in the Have I Been Pwned dumps is ignored.
+const BUNDLED_BREACHED_PASSWORDS: &str = include_str!("../data/breached-passwords.txt");
+
+/// Length of the hash prefix the list is bucketed by, as in the HIBP range API.
+const PREFIX_LEN: usize = 5;
+
+fn setting(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(default)
+}
+
+fn min_length() -> usize {
+    setting("PASSWORD_MIN_LENGTH", 10)
+}
+
+fn max_length() -> usize {
+    setting("PASSWORD_MAX_LENGTH", 128)
+}
+
+/// How many of lowercase, uppercase, digits and symbols must appear.
+fn min_character_classes() -> usize {
+    setting("PASSWORD_MIN_CHARACTER_CLASSES", 2).min(4)
+}
+
+fn breach_check_enabled() -> bool {
+    env::var("PASSWORD_BREACH_CHECK")
+        .map(|value| value != "false")
+        .unwrap_or(true)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    TooShort(usize),
+    TooLong(usize),
+    TooFewCharacterClasses(usize),
+    ContainsUsername,
+    ContainsEmail,
+    Breached,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyViolation::TooShort(min) => {
+                write!(f, "must be at least {min} characters long")
+            }
+            PolicyViolation::TooLong(max) => write!(f, "must be at most {max} characters long"),
+            PolicyViolation::TooFewCharacterClasses(min) => write!(
+                f,
+                "must mix at least {min} of lowercase letters, uppercase letters, digits and symbols"
+            ),
+            PolicyViolation::ContainsUsername => f.write_str("must not contain the username"),
+            PolicyViolation::ContainsEmail => f.write_str("must not contain the email address"),
+            PolicyViolation::Breached => {
+                f.write_str("appears in a list of breached passwords; choose another")
+            }
+        }
+    }
+}
+
+/// All the ways a password falls short, empty when it is acceptable.
+pub fn violations(password: &str, username: &str, email: &str) -> Vec<PolicyViolation> {
+    let mut violations = Vec::new();
+
+    let length = password.chars().count();
+    if length < min_length() {
+        violations.push(PolicyViolation::TooShort(min_length()));
+    }
+    if length > max_length() {
+        violations.push(PolicyViolation::TooLong(max_length()));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .into_iter()
+    .filter(|present| *present)
+    .count();
+    if classes < min_character_classes() {
+        violations.push(PolicyViolation::TooFewCharacterClasses(
+            min_character_classes(),
+        ));
+    }
+
+    let lowered = password.to_lowercase();
+    let contains = |part: &str| {
+        let part = part.trim().to_lowercase();
+        part.chars().count() >= 3 && lowered.contains(&part)
+    };
+    if contains(username) {
+        violations.push(PolicyViolation::ContainsUsername);
+    }
+    let local_part = email.split('@').next().unwrap_or_default();
+    if contains(email) || contains(local_part) {
+        violations.push(PolicyViolation::ContainsEmail);
+    }
+
+    if breach_check_enabled() && is_breached(password) {
+        violations.push(PolicyViolation::Breached);
+    }
+
+    violations
+}
+
+/// The policy check as an error message for the API, listing every
+/// violation at once.
+pub fn check(password: &str, username: &str, email: &str) -> Result<(), String> {
+    let violations = violations(password, username, email);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let reasons: Vec<String> = violations.iter().map(ToString::to_string).collect();
+    Err(format!("Password {}", reasons.join("; ")))
+}
+
+type BreachedHashes = HashMap<String, HashSet<String>>;
+
+fn parse_hashes(list: &str) -> BreachedHashes {
+    let mut hashes = BreachedHashes::new();
+    for line in list.lines() {
+        let hash = line.split(':').next().unwrap_or_default().trim();
+        if hash.len() != 40 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        let hash = hash.to_ascii_uppercase();
+        let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+        hashes
+            .entry(prefix.to_string())
+            .or_default()
+            .insert(suffix.to_string());
+    }
+    hashes
+}
+
+fn breached_hashes() -> &'static BreachedHashes {
+    static HASHES: OnceLock<BreachedHashes> = OnceLock::new();
+    HASHES.get_or_init(|| {
+        let Some(path) = env::var("BREACHED_PASSWORDS_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+        else {
+            return parse_hashes(BUNDLED_BREACHED_PASSWORDS);
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(list) => parse_hashes(&list),
+            Err(e) => {
+                tracing::error!("Failed to read breached password list {}: {}", path, e);
+                parse_hashes(BUNDLED_BREACHED_PASSWORDS)
+            }
+        }
+    })
+}
+
+/// Looks the password up the way a k-anonymity range query would: the first
+/// five hex digits of its SHA-1 pick a bucket, the rest is matched within it.
+pub fn is_breached(password: &str) -> bool {
+    let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+    breached_hashes()
+        .get(prefix)
+        .is_some_and(|suffixes| suffixes.contains(suffix))
+}
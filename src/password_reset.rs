@@ -0,0 +1,107 @@
+use minijinja::{context, Environment};
+use std::env;
+
+use crate::auth::{generate_opaque_token, hash_opaque_token};
+use crate::config::{base_url, site_name};
+use crate::db::DbPool;
+
+const RESET_TEMPLATE: &str = include_str!("../templates/password_reset.txt");
+
+pub fn reset_token_ttl_minutes() -> i64 {
+    env::var("PASSWORD_RESET_TTL_MINUTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|minutes| *minutes > 0)
+        .unwrap_or(60)
+}
+
+pub fn render_reset_email(username: &str, reset_token: &str) -> Result<String, minijinja::Error> {
+    let env = Environment::new();
+    env.render_str(
+        RESET_TEMPLATE,
+        context! {
+            username,
+            site_name => site_name(),
+            ttl_minutes => reset_token_ttl_minutes(),
+            reset_url => format!("{}/password/reset?token={}", base_url(), reset_token),
+        },
+    )
+}
+
+/// Issues a new reset token for the user. Only the latest token stays usable.
+pub async fn issue_reset_token(pool: &DbPool, user_id: i32) -> Result<String, sqlx::Error> {
+    let token = generate_opaque_token();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM password_reset_tokens WHERE user_id = ? AND used_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+         VALUES (?, ?, NOW() + INTERVAL ? MINUTE)",
+    )
+    .bind(user_id)
+    .bind(hash_opaque_token(&token))
+    .bind(reset_token_ttl_minutes())
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(token)
+}
+
+/// Username and email of the account a still-valid reset token belongs to,
+/// so the new password can be checked before the token is spent.
+pub async fn reset_token_owner(
+    pool: &DbPool,
+    token: &str,
+) -> Result<Option<(String, String)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT u.username, u.email FROM password_reset_tokens t
+         JOIN users u ON u.id = t.user_id
+         WHERE t.token_hash = ? AND t.used_at IS NULL AND t.expires_at > NOW()",
+    )
+    .bind(hash_opaque_token(token))
+    .fetch_optional(pool)
+    .await
+}
+
+/// Spends a reset token and stores the new password hash in one transaction,
+/// which also satisfies a password reset forced by an admin. Returns the user
+/// id, or `None` if the token is unknown, used or expired.
+pub async fn consume_reset_token(
+    pool: &DbPool,
+    token: &str,
+    password_hash: &str,
+) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let row: Option<(i64, i32)> = sqlx::query_as(
+        "SELECT id, user_id FROM password_reset_tokens
+         WHERE token_hash = ? AND used_at IS NULL AND expires_at > NOW()
+         FOR UPDATE",
+    )
+    .bind(hash_opaque_token(token))
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((id, user_id)) = row else {
+        return Ok(None);
+    };
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = NOW() WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE users SET password_hash = ?, password_reset_required = FALSE WHERE id = ?")
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(user_id))
+}
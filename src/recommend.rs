@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::models::PostResponse;
+
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "are", "but", "not", "you", "all", "any", "can", "had", "her", "was",
+    "one", "our", "out", "has", "have", "this", "that", "with", "from", "they", "will", "would",
+    "there", "their", "what", "about", "which", "when", "into", "than", "then", "them", "these",
+    "some", "its", "also", "been", "were", "more", "your", "how",
+];
+
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}')
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut cjk_run: Vec<char> = Vec::new();
+
+    let flush_word = |word: &mut String, tokens: &mut Vec<String>| {
+        if word.chars().count() >= 2 && !STOP_WORDS.contains(&word.as_str()) {
+            tokens.push(std::mem::take(word));
+        } else {
+            word.clear();
+        }
+    };
+
+    let flush_cjk = |run: &mut Vec<char>, tokens: &mut Vec<String>| {
+        match run.len() {
+            0 => {}
+            1 => tokens.push(run[0].to_string()),
+            _ => tokens.extend(run.windows(2).map(|w| w.iter().collect::<String>())),
+        }
+        run.clear();
+    };
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            flush_word(&mut word, &mut tokens);
+            cjk_run.push(c);
+        } else if c.is_alphanumeric() {
+            flush_cjk(&mut cjk_run, &mut tokens);
+            word.extend(c.to_lowercase());
+        } else {
+            flush_word(&mut word, &mut tokens);
+            flush_cjk(&mut cjk_run, &mut tokens);
+        }
+    }
+    flush_word(&mut word, &mut tokens);
+    flush_cjk(&mut cjk_run, &mut tokens);
+
+    tokens
+}
+
+fn term_frequencies(title: &str, content: &str) -> HashMap<String, f64> {
+    let mut tf = HashMap::new();
+    for token in tokenize(title) {
+        *tf.entry(token).or_insert(0.0) += 2.0;
+    }
+    for token in tokenize(content) {
+        *tf.entry(token).or_insert(0.0) += 1.0;
+    }
+    tf
+}
+
+pub struct Document<'a> {
+    pub id: i32,
+    pub title: &'a str,
+    pub content: &'a str,
+}
+
+pub fn rank_related(target: &Document, candidates: &[Document]) -> Vec<(i32, f64)> {
+    let target_tf = term_frequencies(target.title, target.content);
+    let candidate_tfs: Vec<(i32, HashMap<String, f64>)> = candidates
+        .iter()
+        .filter(|doc| doc.id != target.id)
+        .map(|doc| (doc.id, term_frequencies(doc.title, doc.content)))
+        .collect();
+
+    let total_docs = (candidate_tfs.len() + 1) as f64;
+    let mut doc_freq: HashMap<&str, f64> = HashMap::new();
+    for term in target_tf.keys() {
+        *doc_freq.entry(term.as_str()).or_insert(0.0) += 1.0;
+    }
+    for (_, tf) in &candidate_tfs {
+        for term in tf.keys() {
+            *doc_freq.entry(term.as_str()).or_insert(0.0) += 1.0;
+        }
+    }
+
+    let weigh = |tf: &HashMap<String, f64>| -> HashMap<String, f64> {
+        tf.iter()
+            .map(|(term, count)| {
+                let df = doc_freq.get(term.as_str()).copied().unwrap_or(1.0);
+                let idf = (total_docs / df).ln() + 1.0;
+                (term.clone(), (1.0 + count.ln()) * idf)
+            })
+            .collect()
+    };
+
+    let target_vec = weigh(&target_tf);
+    let target_norm = target_vec.values().map(|w| w * w).sum::<f64>().sqrt();
+    if target_norm == 0.0 {
+        return Vec::new();
+    }
+
+    let mut scored: Vec<(i32, f64)> = candidate_tfs
+        .iter()
+        .filter_map(|(id, tf)| {
+            let vec = weigh(tf);
+            let norm = vec.values().map(|w| w * w).sum::<f64>().sqrt();
+            if norm == 0.0 {
+                return None;
+            }
+            let dot: f64 = vec
+                .iter()
+                .filter_map(|(term, w)| target_vec.get(term).map(|tw| tw * w))
+                .sum();
+            let score = dot / (norm * target_norm);
+            (score > 0.0).then_some((*id, score))
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored
+}
+
+type Cache = HashMap<String, (Instant, Vec<PostResponse>)>;
+
+fn cache() -> MutexGuard<'static, Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn cache_ttl() -> Duration {
+    let secs = env::var("RECOMMEND_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300);
+    Duration::from_secs(secs)
+}
+
+pub fn cached(key: &str) -> Option<Vec<PostResponse>> {
+    let cache = cache();
+    cache
+        .get(key)
+        .filter(|(stored_at, _)| stored_at.elapsed() < cache_ttl())
+        .map(|(_, posts)| posts.clone())
+}
+
+pub fn store(key: String, posts: Vec<PostResponse>) {
+    let mut cache = cache();
+    let ttl = cache_ttl();
+    cache.retain(|_, (stored_at, _)| stored_at.elapsed() < ttl);
+    cache.insert(key, (Instant::now(), posts));
+}
+
+pub fn invalidate() {
+    cache().clear();
+}
+
+pub fn trending_half_life_hours() -> f64 {
+    env::var("TRENDING_HALF_LIFE_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v: &f64| *v > 0.0)
+        .unwrap_or(24.0)
+}
+
+pub fn trending_window_hours() -> i64 {
+    env::var("TRENDING_WINDOW_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v: &i64| *v > 0)
+        .unwrap_or(168)
+}
+
+/// How many views one reaction, such as a federated like, is worth in the
+/// trending score.
+pub fn trending_reaction_weight() -> f64 {
+    env::var("TRENDING_REACTION_WEIGHT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v: &f64| *v >= 0.0)
+        .unwrap_or(3.0)
+}
+
+/// Repeat views of a post by the same viewer within this many minutes count
+/// once. Zero counts every view.
+pub fn view_dedup_window_minutes() -> i64 {
+    env::var("VIEW_DEDUP_WINDOW_MINUTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v: &i64| *v >= 0)
+        .unwrap_or(30)
+}
+
+/// Key for hashing viewers. `VIEWER_HASH_SECRET` keeps the hashes stable
+/// across restarts and instances; without it each process picks a random
+/// key and repeat views are only recognised within that process.
+fn viewer_hash_key() -> &'static [u8] {
+    static KEY: OnceLock<Vec<u8>> = OnceLock::new();
+    KEY.get_or_init(|| match env::var("VIEWER_HASH_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+        _ => {
+            let mut key = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            key
+        }
+    })
+}
+
+/// Identifies a viewer for deduplication: the signed-in user, otherwise the
+/// client IP. Stored as an HMAC under a server-side key, so `post_views`
+/// keeps no addresses and they cannot be recovered by hashing every IPv4
+/// address.
+pub fn viewer_key(user_id: Option<i32>, ip: Option<IpAddr>) -> Option<String> {
+    let viewer = match (user_id, ip) {
+        (Some(user_id), _) => format!("user:{user_id}"),
+        (None, Some(ip)) => format!("ip:{ip}"),
+        (None, None) => return None,
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(viewer_hash_key()).expect("HMAC accepts keys of any length");
+    mac.update(viewer.as_bytes());
+    Some(hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,293 @@
+use axum::{
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+
+use crate::auth::{self, Permission};
+use crate::db::DbPool;
+use crate::handlers;
+
+/// Every route the server exposes, with its authentication and permission
+/// layers. `main` adds CORS and tracing on top.
+pub fn app(pool: DbPool) -> Router {
+    let public_routes = Router::new()
+        .route("/register", post(handlers::user_handler::register))
+        .route("/login", post(handlers::user_handler::login))
+        .route("/login/2fa", post(handlers::user_handler::login_two_factor))
+        .route("/siwe/nonce", get(handlers::siwe_handler::get_nonce))
+        .route("/siwe/login", post(handlers::siwe_handler::siwe_login))
+        .route("/.well-known/jwks.json", get(handlers::jwks_handler::jwks))
+        .route("/oidc", get(handlers::oidc_handler::list_providers))
+        .route(
+            "/oidc/:provider/authorize",
+            get(handlers::oidc_handler::authorize),
+        )
+        .route(
+            "/oidc/:provider/callback",
+            get(handlers::oidc_handler::callback),
+        )
+        .route(
+            "/token/refresh",
+            post(handlers::user_handler::refresh_token),
+        )
+        .route(
+            "/password/forgot",
+            post(handlers::password_handler::forgot_password),
+        )
+        .route(
+            "/password/reset",
+            post(handlers::password_handler::reset_password),
+        )
+        .route("/verify-email", get(handlers::user_handler::verify_email))
+        .route("/posts", get(handlers::post_handler::get_posts))
+        .route(
+            "/graphql",
+            get(handlers::graphql_handler::graphql_playground)
+                .post(handlers::graphql_handler::graphql),
+        )
+        .route(
+            "/posts/trending",
+            get(handlers::recommend_handler::get_trending_posts),
+        )
+        .route("/posts/:id", get(handlers::post_handler::get_post))
+        .route(
+            "/posts/:id/translations",
+            get(handlers::post_handler::get_post_translations),
+        )
+        .route(
+            "/posts/:id/related",
+            get(handlers::recommend_handler::get_related_posts),
+        )
+        .route(
+            "/posts/:id/webmentions",
+            get(handlers::webmention_handler::get_post_webmentions),
+        )
+        .route(
+            "/webmention",
+            post(handlers::webmention_handler::receive_webmention),
+        )
+        .route(
+            "/newsletter/subscribe",
+            post(handlers::newsletter_handler::subscribe),
+        )
+        .route(
+            "/newsletter/confirm",
+            get(handlers::newsletter_handler::confirm_subscription),
+        )
+        .route(
+            "/newsletter/unsubscribe",
+            get(handlers::newsletter_handler::unsubscribe)
+                .post(handlers::newsletter_handler::unsubscribe),
+        )
+        .route(
+            "/.well-known/webfinger",
+            get(handlers::activitypub_handler::webfinger),
+        )
+        .route(
+            "/users/:username",
+            get(handlers::activitypub_handler::get_actor),
+        )
+        .route(
+            "/users/:username/inbox",
+            post(handlers::activitypub_handler::inbox),
+        )
+        .route(
+            "/users/:username/outbox",
+            get(handlers::activitypub_handler::get_outbox),
+        )
+        .route(
+            "/users/:username/followers",
+            get(handlers::activitypub_handler::get_followers),
+        )
+        .route(
+            "/users/:username/posts/:id",
+            get(handlers::activitypub_handler::get_article),
+        );
+
+    let account_routes = Router::new()
+        .route("/logout", post(handlers::user_handler::logout))
+        .route("/logout/all", post(handlers::user_handler::logout_all))
+        .route(
+            "/verify-email/resend",
+            post(handlers::user_handler::resend_verification_email),
+        )
+        .route(
+            "/me",
+            get(handlers::account_handler::get_me).delete(handlers::account_handler::delete_me),
+        )
+        .route(
+            "/me/password",
+            put(handlers::account_handler::change_password),
+        )
+        .route("/me/email", put(handlers::account_handler::change_email))
+        .route("/me/2fa", delete(handlers::two_factor_handler::disable))
+        .route("/me/2fa/enroll", post(handlers::two_factor_handler::enroll))
+        .route(
+            "/me/2fa/confirm",
+            post(handlers::two_factor_handler::confirm),
+        )
+        .route(
+            "/me/2fa/recovery-codes",
+            post(handlers::two_factor_handler::regenerate_recovery_codes),
+        )
+        .route("/me/siwe", post(handlers::siwe_handler::link_address))
+        .route(
+            "/me/identities",
+            get(handlers::oidc_handler::list_identities),
+        )
+        .route(
+            "/me/identities/:provider",
+            post(handlers::oidc_handler::link).delete(handlers::oidc_handler::unlink),
+        )
+        .route(
+            "/me/api-keys",
+            get(handlers::api_key_handler::list_api_keys)
+                .post(handlers::api_key_handler::create_api_key),
+        )
+        .route(
+            "/me/api-keys/:id",
+            delete(handlers::api_key_handler::delete_api_key),
+        )
+        .route(
+            "/me/sessions",
+            get(handlers::session_handler::list_sessions),
+        )
+        .route(
+            "/me/sessions/:id",
+            delete(handlers::session_handler::delete_session),
+        )
+        .route_layer(middleware::from_fn(auth::require_session));
+
+    let protected_routes = Router::new()
+        .merge(account_routes)
+        .route(
+            "/posts",
+            post(handlers::post_handler::create_post).route_layer(middleware::from_fn_with_state(
+                (pool.clone(), Permission::PostsWrite),
+                auth::require_permission,
+            )),
+        )
+        .route(
+            "/posts/:id",
+            put(handlers::post_handler::update_post)
+                .delete(handlers::post_handler::delete_post)
+                .route_layer(middleware::from_fn_with_state(
+                    (pool.clone(), Permission::PostsWrite),
+                    auth::require_permission,
+                )),
+        )
+        .route(
+            "/posts/:id/pin",
+            put(handlers::curation_handler::pin_post)
+                .delete(handlers::curation_handler::unpin_post)
+                .route_layer(middleware::from_fn_with_state(
+                    (pool.clone(), Permission::PostsWrite),
+                    auth::require_permission,
+                )),
+        )
+        .route(
+            "/wallets/generate",
+            post(handlers::wallet_handler::generate_wallets).route_layer(
+                middleware::from_fn_with_state(
+                    (pool.clone(), Permission::WalletsGenerate),
+                    auth::require_permission,
+                ),
+            ),
+        )
+        .route(
+            "/transfer/batch",
+            post(handlers::transfer_handler::batch_transfer).route_layer(
+                middleware::from_fn_with_state(
+                    (pool.clone(), Permission::TransferSend),
+                    auth::require_permission,
+                ),
+            ),
+        )
+        .route(
+            "/contract/call",
+            post(handlers::contract_handler::call_contract).route_layer(
+                middleware::from_fn_with_state(
+                    (pool.clone(), Permission::ContractCall),
+                    auth::require_permission,
+                ),
+            ),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            pool.clone(),
+            auth::auth_middleware,
+        ));
+
+    let admin_routes = Router::new()
+        .route(
+            "/admin/featured",
+            get(handlers::curation_handler::list_featured_slots),
+        )
+        .route(
+            "/admin/featured/:slot",
+            put(handlers::curation_handler::set_featured_slot)
+                .delete(handlers::curation_handler::clear_featured_slot),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            (pool.clone(), Permission::PostsFeature),
+            auth::require_permission,
+        ))
+        .merge(
+            Router::new()
+                .route("/admin/roles", get(handlers::admin_handler::list_roles))
+                .route("/admin/users", get(handlers::admin_handler::list_users))
+                .route(
+                    "/admin/users/:id/role",
+                    put(handlers::admin_handler::assign_role),
+                )
+                .route(
+                    "/admin/users/:id/suspend",
+                    post(handlers::admin_handler::suspend_user),
+                )
+                .route(
+                    "/admin/users/:id/ban",
+                    post(handlers::admin_handler::ban_user),
+                )
+                .route(
+                    "/admin/users/:id/reinstate",
+                    post(handlers::admin_handler::reinstate_user),
+                )
+                .route(
+                    "/admin/users/:id/password-reset",
+                    post(handlers::admin_handler::force_password_reset),
+                )
+                .route(
+                    "/admin/users/:id/impersonate",
+                    post(handlers::admin_handler::impersonate_user),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    (pool.clone(), Permission::UsersManage),
+                    auth::require_permission,
+                )),
+        )
+        .merge(
+            Router::new()
+                .route(
+                    "/admin/audit-events",
+                    get(handlers::audit_handler::list_audit_events),
+                )
+                .route(
+                    "/admin/audit-events/verify",
+                    get(handlers::audit_handler::verify_audit_chain),
+                )
+                .route_layer(middleware::from_fn_with_state(
+                    (pool.clone(), Permission::AuditRead),
+                    auth::require_permission,
+                )),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            pool.clone(),
+            auth::auth_middleware,
+        ));
+
+    Router::new()
+        .merge(public_routes)
+        .merge(protected_routes)
+        .merge(admin_routes)
+        .with_state(pool)
+}
@@ -0,0 +1,138 @@
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::{header, request::Parts};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::client_ip::ClientIp;
+use crate::db::DbPool;
+use crate::models::SessionResponse;
+use crate::tokens;
+
+/// Longest user agent kept for a session.
+const USER_AGENT_MAX_LEN: usize = 512;
+
+/// How often a session's last-seen time is written while its access tokens
+/// are in use.
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The device a sign-in or refresh came from, as shown in the session list.
+#[derive(Debug, Clone, Default)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Device
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(USER_AGENT_MAX_LEN).collect());
+        Ok(Device { user_agent, ip })
+    }
+}
+
+/// Records a session for a new refresh token family, or refreshes the
+/// device details of an existing one, and returns its id. `mfa` is only
+/// stored when the session is created.
+pub async fn upsert<'e, E>(
+    executor: E,
+    user_id: i32,
+    family_id: &str,
+    device: &Device,
+    mfa: bool,
+) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::MySql>,
+{
+    let result = sqlx::query(
+        "INSERT INTO sessions (user_id, family_id, user_agent, ip, mfa, created_at, last_seen_at)
+         VALUES (?, ?, ?, ?, ?, NOW(3), NOW(3))
+         ON DUPLICATE KEY UPDATE
+             user_agent = COALESCE(VALUES(user_agent), user_agent),
+             ip = COALESCE(VALUES(ip), ip),
+             last_seen_at = NOW(3),
+             id = LAST_INSERT_ID(id)",
+    )
+    .bind(user_id)
+    .bind(family_id)
+    .bind(&device.user_agent)
+    .bind(device.ip.map(|ip| ip.to_string()))
+    .bind(mfa)
+    .execute(executor)
+    .await?;
+    Ok(result.last_insert_id() as i64)
+}
+
+/// Marks a session as seen, at most once a minute per process.
+pub async fn touch(pool: &DbPool, session_id: i64) -> Result<(), sqlx::Error> {
+    static TOUCHED: OnceLock<Mutex<HashMap<i64, Instant>>> = OnceLock::new();
+    let now = Instant::now();
+    {
+        let mut touched = TOUCHED
+            .get_or_init(Mutex::default)
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if touched
+            .get(&session_id)
+            .is_some_and(|at| now.duration_since(*at) < TOUCH_INTERVAL)
+        {
+            return Ok(());
+        }
+        touched.retain(|_, at| now.duration_since(*at) < TOUCH_INTERVAL);
+        touched.insert(session_id, now);
+    }
+
+    sqlx::query("UPDATE sessions SET last_seen_at = NOW(3) WHERE id = ?")
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// The user's sessions that can still be refreshed, most recently used first.
+pub async fn list(pool: &DbPool, user_id: i32) -> Result<Vec<SessionResponse>, sqlx::Error> {
+    sqlx::query_as::<_, SessionResponse>(
+        "SELECT s.id, s.user_agent, s.ip, s.created_at, s.last_seen_at
+         FROM sessions s
+         WHERE s.user_id = ?
+           AND EXISTS(SELECT 1 FROM refresh_tokens r
+                      WHERE r.family_id = s.family_id AND r.used_at IS NULL
+                        AND r.revoked_at IS NULL AND r.expires_at > NOW())
+         ORDER BY s.last_seen_at DESC, s.id DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Signs one of the user's sessions out: its refresh tokens are revoked and
+/// access tokens carrying its id are rejected. Returns false when the user
+/// has no such session.
+pub async fn revoke(pool: &DbPool, user_id: i32, session_id: i64) -> Result<bool, sqlx::Error> {
+    let family: Option<String> =
+        sqlx::query_scalar("SELECT family_id FROM sessions WHERE id = ? AND user_id = ?")
+            .bind(session_id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+    let Some(family_id) = family else {
+        return Ok(false);
+    };
+    tokens::revoke_family(pool, &family_id).await?;
+    tokens::remember_revoked_session(session_id);
+    Ok(true)
+}
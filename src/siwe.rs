@@ -0,0 +1,262 @@
+use chrono::{DateTime, Duration, Utc};
+use ethers::types::{Address, Signature};
+use ethers::utils::to_checksum;
+use std::env;
+use std::str::FromStr;
+use url::Url;
+
+use crate::auth::generate_opaque_token;
+use crate::config::base_url;
+use crate::db::DbPool;
+
+const PREAMBLE: &str = " wants you to sign in with your Ethereum account:";
+
+/// Tolerated clock drift for `Issued At` timestamps from the future.
+const CLOCK_SKEW_SECS: i64 = 300;
+
+#[derive(Debug)]
+pub enum SiweError {
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for SiweError {
+    fn from(e: sqlx::Error) -> Self {
+        SiweError::Database(e)
+    }
+}
+
+impl std::fmt::Display for SiweError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SiweError::Invalid(reason) => f.write_str(reason),
+            SiweError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SiweError {}
+
+fn invalid(reason: impl Into<String>) -> SiweError {
+    SiweError::Invalid(reason.into())
+}
+
+/// An EIP-4361 message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+fn parse_time(field: &str, value: &str) -> Result<DateTime<Utc>, SiweError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| invalid(format!("Invalid {field}")))
+}
+
+impl FromStr for SiweMessage {
+    type Err = SiweError;
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        let mut lines = message.lines().peekable();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(PREAMBLE))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| invalid("Missing SIWE preamble"))?
+            .to_string();
+
+        let address_text = lines.next().ok_or_else(|| invalid("Missing address"))?;
+        let address = Address::from_str(address_text).map_err(|_| invalid("Invalid address"))?;
+        // EIP-4361 requires the EIP-55 mixed-case form.
+        if to_checksum(&address, None) != address_text {
+            return Err(invalid("Address is not EIP-55 checksummed"));
+        }
+
+        if lines.next() != Some("") {
+            return Err(invalid("Expected a blank line after the address"));
+        }
+
+        // The statement is optional; older clients leave an empty line in its place.
+        let mut statement = None;
+        match lines.peek() {
+            Some(line) if line.starts_with("URI: ") => {}
+            Some(&"") => {
+                lines.next();
+            }
+            Some(line) => {
+                statement = Some(line.to_string());
+                lines.next();
+                if lines.next() != Some("") {
+                    return Err(invalid("Expected a blank line after the statement"));
+                }
+            }
+            None => return Err(invalid("Missing URI")),
+        }
+
+        let mut uri = None;
+        let mut version = None;
+        let mut chain_id = None;
+        let mut nonce = None;
+        let mut issued_at = None;
+        let mut expiration_time = None;
+        let mut not_before = None;
+        let mut request_id = None;
+        let mut resources = Vec::new();
+
+        while let Some(line) = lines.next() {
+            if line == "Resources:" {
+                for resource in lines.by_ref() {
+                    let resource = resource
+                        .strip_prefix("- ")
+                        .ok_or_else(|| invalid("Malformed resource"))?;
+                    resources.push(resource.to_string());
+                }
+                break;
+            }
+
+            let (field, value) = line
+                .split_once(": ")
+                .ok_or_else(|| invalid(format!("Malformed line: {line}")))?;
+            match field {
+                "URI" => uri = Some(value.to_string()),
+                "Version" => version = Some(value.to_string()),
+                "Chain ID" => {
+                    chain_id = Some(value.parse().map_err(|_| invalid("Invalid Chain ID"))?)
+                }
+                "Nonce" => nonce = Some(value.to_string()),
+                "Issued At" => issued_at = Some(parse_time(field, value)?),
+                "Expiration Time" => expiration_time = Some(parse_time(field, value)?),
+                "Not Before" => not_before = Some(parse_time(field, value)?),
+                "Request ID" => request_id = Some(value.to_string()),
+                _ => return Err(invalid(format!("Unknown field: {field}"))),
+            }
+        }
+
+        let nonce = nonce.ok_or_else(|| invalid("Missing Nonce"))?;
+        if nonce.len() < 8 || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid("Invalid Nonce"));
+        }
+
+        Ok(SiweMessage {
+            domain,
+            address,
+            statement,
+            uri: uri.ok_or_else(|| invalid("Missing URI"))?,
+            version: version.ok_or_else(|| invalid("Missing Version"))?,
+            chain_id: chain_id.ok_or_else(|| invalid("Missing Chain ID"))?,
+            nonce,
+            issued_at: issued_at.ok_or_else(|| invalid("Missing Issued At"))?,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        })
+    }
+}
+
+/// The domain messages must be addressed to: `SIWE_DOMAIN`, or the host
+/// (and port) of `PUBLIC_BASE_URL`.
+pub fn expected_domain() -> String {
+    env::var("SIWE_DOMAIN").unwrap_or_else(|_| {
+        Url::parse(&base_url())
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_string();
+                Some(match url.port() {
+                    Some(port) => format!("{host}:{port}"),
+                    None => host,
+                })
+            })
+            .unwrap_or_default()
+    })
+}
+
+pub fn nonce_ttl_secs() -> i64 {
+    env::var("SIWE_NONCE_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(600)
+}
+
+/// Whether an address with no linked account may sign up by signing in.
+pub fn registration_allowed() -> bool {
+    env::var("SIWE_ALLOW_REGISTRATION")
+        .map(|value| value != "false")
+        .unwrap_or(true)
+}
+
+pub async fn issue_nonce(pool: &DbPool) -> Result<String, sqlx::Error> {
+    sqlx::query("DELETE FROM siwe_nonces WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+
+    let nonce = generate_opaque_token();
+    sqlx::query(
+        "INSERT INTO siwe_nonces (nonce, expires_at) VALUES (?, NOW() + INTERVAL ? SECOND)",
+    )
+    .bind(&nonce)
+    .bind(nonce_ttl_secs())
+    .execute(pool)
+    .await?;
+    Ok(nonce)
+}
+
+/// Checks a signed message and returns the address that signed it. The
+/// message's nonce is spent, so each signature works once.
+pub async fn verify(pool: &DbPool, message: &str, signature: &str) -> Result<Address, SiweError> {
+    let parsed: SiweMessage = message.parse()?;
+
+    if parsed.domain != expected_domain() {
+        return Err(invalid("Message is addressed to a different domain"));
+    }
+    if parsed.version != "1" {
+        return Err(invalid("Unsupported SIWE version"));
+    }
+
+    let now = Utc::now();
+    if parsed.issued_at > now + Duration::seconds(CLOCK_SKEW_SECS) {
+        return Err(invalid("Message is issued in the future"));
+    }
+    if parsed.expiration_time.is_some_and(|expires| expires <= now) {
+        return Err(invalid("Message has expired"));
+    }
+    if parsed.not_before.is_some_and(|not_before| not_before > now) {
+        return Err(invalid("Message is not valid yet"));
+    }
+
+    let signature = Signature::from_str(signature).map_err(|_| invalid("Invalid signature"))?;
+    let signer = signature
+        .recover(message)
+        .map_err(|_| invalid("Invalid signature"))?;
+    if signer != parsed.address {
+        return Err(invalid("Signature does not match the message address"));
+    }
+
+    let spent = sqlx::query("DELETE FROM siwe_nonces WHERE nonce = ? AND expires_at > NOW()")
+        .bind(&parsed.nonce)
+        .execute(pool)
+        .await?;
+    if spent.rows_affected() == 0 {
+        return Err(invalid("Unknown or expired nonce"));
+    }
+
+    Ok(signer)
+}
+
+/// How addresses are stored: lowercase and `0x`-prefixed.
+pub fn address_key(address: &Address) -> String {
+    format!("{address:#x}")
+}
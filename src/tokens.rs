@@ -0,0 +1,377 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration as StdDuration, Instant};
+
+use crate::auth::{access_token_ttl_secs, generate_opaque_token, hash_opaque_token, Claims};
+use crate::db::DbPool;
+use crate::sessions::{self, Device};
+
+#[derive(Debug)]
+pub enum RefreshError {
+    Invalid,
+    /// An already rotated token was presented again; its whole family is now revoked.
+    Reused,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RefreshError {
+    fn from(e: sqlx::Error) -> Self {
+        RefreshError::Database(e)
+    }
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::Invalid => f.write_str("Invalid refresh token"),
+            RefreshError::Reused => f.write_str("Refresh token reuse detected"),
+            RefreshError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RefreshError {}
+
+pub fn refresh_token_ttl() -> Duration {
+    let days = env::var("REFRESH_TOKEN_TTL_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|days| *days > 0)
+        .unwrap_or(30);
+    Duration::days(days)
+}
+
+async fn insert_refresh_token<'e, E>(
+    executor: E,
+    user_id: i32,
+    family_id: &str,
+) -> Result<String, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::MySql>,
+{
+    let token = generate_opaque_token();
+    sqlx::query(
+        "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+         VALUES (?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(family_id)
+    .bind(hash_opaque_token(&token))
+    .bind(Utc::now() + refresh_token_ttl())
+    .execute(executor)
+    .await?;
+    Ok(token)
+}
+
+/// Starts a new refresh token family and the session it represents, one per
+/// sign-in. `mfa` records whether the sign-in passed a second factor.
+/// Returns the token and the session id.
+pub async fn issue_refresh_token(
+    pool: &DbPool,
+    user_id: i32,
+    device: &Device,
+    mfa: bool,
+) -> Result<(String, i64), sqlx::Error> {
+    let family_id = generate_opaque_token();
+    let mut tx = pool.begin().await?;
+    let token = insert_refresh_token(&mut *tx, user_id, &family_id).await?;
+    let session_id = sessions::upsert(&mut *tx, user_id, &family_id, device, mfa).await?;
+    tx.commit().await?;
+    Ok((token, session_id))
+}
+
+/// A rotated refresh token and what the new access token is issued for.
+pub struct Rotation {
+    pub user_id: i32,
+    pub session_id: i64,
+    /// Whether the session signed in with a second factor.
+    pub mfa: bool,
+    pub refresh_token: String,
+}
+
+/// Exchanges a refresh token for a new one in the same family and records
+/// the device on the family's session.
+pub async fn rotate_refresh_token(
+    pool: &DbPool,
+    token: &str,
+    device: &Device,
+) -> Result<Rotation, RefreshError> {
+    let mut tx = pool.begin().await?;
+
+    let row: Option<(i64, i32, String, bool, bool, bool)> = sqlx::query_as(
+        "SELECT id, user_id, family_id, used_at IS NOT NULL, revoked_at IS NOT NULL,
+                expires_at <= NOW()
+         FROM refresh_tokens
+         WHERE token_hash = ?
+         FOR UPDATE",
+    )
+    .bind(hash_opaque_token(token))
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((id, user_id, family_id, used, revoked, expired)) = row else {
+        return Err(RefreshError::Invalid);
+    };
+
+    if used {
+        revoke_family(&mut *tx, &family_id).await?;
+        tx.commit().await?;
+        tracing::warn!(
+            "Refresh token reuse detected for user {}, family revoked",
+            user_id
+        );
+        return Err(RefreshError::Reused);
+    }
+
+    if revoked || expired {
+        return Err(RefreshError::Invalid);
+    }
+
+    sqlx::query("UPDATE refresh_tokens SET used_at = NOW() WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    let replacement = insert_refresh_token(&mut *tx, user_id, &family_id).await?;
+    // Families started before sessions were recorded get one here, with no
+    // second factor on record.
+    let session_id = sessions::upsert(&mut *tx, user_id, &family_id, device, false).await?;
+    let mfa: bool = sqlx::query_scalar("SELECT mfa FROM sessions WHERE id = ?")
+        .bind(session_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(Rotation {
+        user_id,
+        session_id,
+        mfa,
+        refresh_token: replacement,
+    })
+}
+
+pub async fn revoke_family<'e, E>(executor: E, family_id: &str) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::MySql>,
+{
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = NOW()
+         WHERE family_id = ? AND revoked_at IS NULL",
+    )
+    .bind(family_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// How long a "still active" verdict for an access token is trusted before
+/// MySQL is consulted again. Revocations made by this process apply at once;
+/// those made by other instances apply within this window.
+pub(crate) fn revocation_cache_ttl() -> StdDuration {
+    let secs = env::var("REVOCATION_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(30);
+    StdDuration::from_secs(secs)
+}
+
+const REVOCATION_CACHE_LIMIT: usize = 10_000;
+
+#[derive(Default)]
+struct RevocationCache {
+    /// jti -> (revoked, trusted until)
+    tokens: HashMap<String, (bool, Instant)>,
+    /// user id -> (unix time in ms before which tokens are rejected, trusted until)
+    cutoffs: HashMap<i32, (i64, Instant)>,
+    /// session id -> kept until its access tokens have expired
+    revoked_sessions: HashMap<i64, Instant>,
+}
+
+impl RevocationCache {
+    fn prune(&mut self, now: Instant) {
+        if self.tokens.len() > REVOCATION_CACHE_LIMIT {
+            self.tokens.retain(|_, (_, until)| *until > now);
+        }
+        self.cutoffs.retain(|_, (_, until)| *until > now);
+        self.revoked_sessions.retain(|_, until| *until > now);
+    }
+}
+
+fn revocation_cache() -> MutexGuard<'static, RevocationCache> {
+    static CACHE: OnceLock<Mutex<RevocationCache>> = OnceLock::new();
+    CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Remaining lifetime of an access token, used to bound cache entries.
+fn remaining_lifetime(claims: &Claims) -> StdDuration {
+    let remaining = claims.exp as i64 - Utc::now().timestamp();
+    StdDuration::from_secs(remaining.max(0) as u64)
+}
+
+/// Checks that the token's user still exists, that the token was not revoked
+/// by `/logout`, that its session was not signed out and that it was issued
+/// after the user's last "log out everywhere".
+pub async fn is_access_token_active(pool: &DbPool, claims: &Claims) -> Result<bool, sqlx::Error> {
+    let now = Instant::now();
+    {
+        let mut cache = revocation_cache();
+        cache.prune(now);
+        if claims
+            .sid
+            .is_some_and(|sid| cache.revoked_sessions.contains_key(&sid))
+        {
+            return Ok(false);
+        }
+        if let Some((cutoff, _)) = cache.cutoffs.get(&claims.sub) {
+            if claims.issued_at_millis() < *cutoff {
+                return Ok(false);
+            }
+        }
+        if let Some((revoked, until)) = cache.tokens.get(&claims.jti) {
+            if *until > now {
+                return Ok(!revoked);
+            }
+        }
+    }
+
+    // A session is live while its family still has a refresh token to use.
+    let row: Option<(Option<DateTime<Utc>>, bool, bool)> = sqlx::query_as(
+        "SELECT u.tokens_valid_after,
+                EXISTS(SELECT 1 FROM revoked_tokens r WHERE r.jti = ?),
+                ? IS NULL OR EXISTS(
+                    SELECT 1 FROM sessions s
+                    JOIN refresh_tokens t ON t.family_id = s.family_id
+                    WHERE s.id = ? AND s.user_id = u.id AND t.used_at IS NULL
+                      AND t.revoked_at IS NULL AND t.expires_at > NOW())
+         FROM users u
+         WHERE u.id = ?",
+    )
+    .bind(&claims.jti)
+    .bind(claims.sid)
+    .bind(claims.sid)
+    .bind(claims.sub)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((valid_after, revoked, session_live)) = row else {
+        return Ok(false);
+    };
+    let superseded = !session_live
+        || valid_after.is_some_and(|cutoff| claims.issued_at_millis() < cutoff.timestamp_millis());
+
+    let until = if revoked || superseded {
+        now + remaining_lifetime(claims)
+    } else {
+        now + revocation_cache_ttl()
+    };
+    revocation_cache()
+        .tokens
+        .insert(claims.jti.clone(), (revoked || superseded, until));
+
+    Ok(!(revoked || superseded))
+}
+
+/// Revokes a single access token until it would have expired anyway.
+pub async fn revoke_access_token(pool: &DbPool, claims: &Claims) -> Result<(), sqlx::Error> {
+    let expires_at = DateTime::<Utc>::from_timestamp(claims.exp as i64, 0).unwrap_or_else(Utc::now);
+    sqlx::query("INSERT IGNORE INTO revoked_tokens (jti, user_id, expires_at) VALUES (?, ?, ?)")
+        .bind(&claims.jti)
+        .bind(claims.sub)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+
+    revocation_cache().tokens.insert(
+        claims.jti.clone(),
+        (true, Instant::now() + remaining_lifetime(claims)),
+    );
+    Ok(())
+}
+
+/// Revokes the refresh token family `token` belongs to, if it is owned by `user_id`.
+pub async fn revoke_refresh_token(
+    pool: &DbPool,
+    user_id: i32,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    let family: Option<(String,)> =
+        sqlx::query_as("SELECT family_id FROM refresh_tokens WHERE token_hash = ? AND user_id = ?")
+            .bind(hash_opaque_token(token))
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+    if let Some((family_id,)) = family {
+        revoke_family(pool, &family_id).await?;
+    }
+    Ok(())
+}
+
+/// Rejects access tokens of a signed-out session in this process right
+/// away; other instances notice within the revocation cache TTL.
+pub fn remember_revoked_session(session_id: i64) {
+    let ttl = StdDuration::from_secs(access_token_ttl_secs() as u64);
+    revocation_cache()
+        .revoked_sessions
+        .insert(session_id, Instant::now() + ttl);
+}
+
+async fn set_access_token_cutoff<'e, E>(
+    executor: E,
+    user_id: i32,
+    cutoff: i64,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::MySql>,
+{
+    sqlx::query("UPDATE users SET tokens_valid_after = ?, updated_at = updated_at WHERE id = ?")
+        .bind(DateTime::<Utc>::from_timestamp_millis(cutoff))
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+fn remember_access_token_cutoff(user_id: i32, cutoff: i64) {
+    let ttl = StdDuration::from_secs(access_token_ttl_secs() as u64);
+    revocation_cache()
+        .cutoffs
+        .insert(user_id, (cutoff, Instant::now() + ttl));
+}
+
+/// Rejects every access token issued before now but keeps refresh tokens, so
+/// clients pick up changed claims such as a new role on their next refresh.
+pub async fn expire_access_tokens(pool: &DbPool, user_id: i32) -> Result<(), sqlx::Error> {
+    let cutoff = Utc::now().timestamp_millis();
+    set_access_token_cutoff(pool, user_id, cutoff).await?;
+    remember_access_token_cutoff(user_id, cutoff);
+    Ok(())
+}
+
+/// Signs the user out everywhere: every access token issued before now is
+/// rejected and every outstanding refresh token is revoked.
+pub async fn revoke_all_sessions(pool: &DbPool, user_id: i32) -> Result<(), sqlx::Error> {
+    let cutoff = Utc::now().timestamp_millis();
+    let mut tx = pool.begin().await?;
+
+    set_access_token_cutoff(&mut *tx, user_id, cutoff).await?;
+
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = NOW()
+         WHERE user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    remember_access_token_cutoff(user_id, cutoff);
+    Ok(())
+}
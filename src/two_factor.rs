@@ -0,0 +1,249 @@
+use base64::Engine;
+use image::{ImageFormat, Luma};
+use qrcode::QrCode;
+use rand::RngCore;
+use std::env;
+use std::io::Cursor;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::auth::hash_opaque_token;
+use crate::config::site_name;
+use crate::db::DbPool;
+
+const STEP_SECS: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// Roles listed in `TOTP_REQUIRED_ROLES` (comma separated) must use 2FA.
+pub fn required_for_role(role: &str) -> bool {
+    env::var("TOTP_REQUIRED_ROLES")
+        .map(|roles| roles.split(',').any(|required| required.trim() == role))
+        .unwrap_or(false)
+}
+
+pub fn challenge_ttl_secs() -> i64 {
+    env::var("TOTP_CHALLENGE_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(300)
+}
+
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, username: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    // Colons separate issuer and account in the otpauth label.
+    Some(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP_SECS,
+        secret,
+        Some(site_name().replace(':', "")),
+        username.replace(':', ""),
+    ))
+}
+
+pub fn otpauth_uri(secret: &str, username: &str) -> Option<String> {
+    totp(secret, username).map(|totp| totp.get_url())
+}
+
+/// Renders the otpauth URI as a base64-encoded PNG QR code.
+pub fn qr_code_png(uri: &str) -> Result<String, String> {
+    let code = QrCode::new(uri.as_bytes()).map_err(|e| e.to_string())?;
+    let image = code.render::<Luma<u8>>().min_dimensions(200, 200).build();
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(png))
+}
+
+/// Checks a code against the previous, current and next time step and returns
+/// the matching step. Steps at or before `last_step` are refused so a code
+/// cannot be replayed.
+pub fn verify_code(
+    secret: &str,
+    username: &str,
+    code: &str,
+    last_step: Option<i64>,
+) -> Option<i64> {
+    let totp = totp(secret, username)?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    [now - STEP_SECS, now, now + STEP_SECS]
+        .into_iter()
+        .filter(|time| totp.check(code.trim(), *time))
+        .map(|time| (time / STEP_SECS) as i64)
+        .find(|step| last_step.is_none_or(|last| *step > last))
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Replaces the user's recovery codes and returns the new plaintext codes.
+pub async fn replace_recovery_codes(
+    conn: &mut sqlx::MySqlConnection,
+    user_id: i32,
+) -> Result<Vec<String>, sqlx::Error> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+
+    sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    for code in &codes {
+        sqlx::query("INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash_opaque_token(&normalize_recovery_code(code)))
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(codes)
+}
+
+/// Spends a recovery code; each one works once.
+async fn consume_recovery_code(
+    tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+    user_id: i32,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE totp_recovery_codes SET used_at = NOW()
+         WHERE user_id = ? AND code_hash = ? AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(hash_opaque_token(&normalize_recovery_code(code)))
+    .execute(&mut **tx)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Checks a TOTP code, or failing that a recovery code, for an enrolled user
+/// and records the used time step.
+pub async fn verify_second_factor(
+    tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+    user_id: i32,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let row: Option<(String, Option<String>, Option<i64>)> = sqlx::query_as(
+        "SELECT username, totp_secret, totp_last_step FROM users
+         WHERE id = ? AND totp_enabled_at IS NOT NULL
+         FOR UPDATE",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    let Some((username, Some(secret), last_step)) = row else {
+        return Ok(false);
+    };
+
+    if let Some(step) = verify_code(&secret, &username, code, last_step) {
+        sqlx::query("UPDATE users SET totp_last_step = ? WHERE id = ?")
+            .bind(step)
+            .bind(user_id)
+            .execute(&mut **tx)
+            .await?;
+        return Ok(true);
+    }
+
+    consume_recovery_code(tx, user_id, code).await
+}
+
+/// Starts the second login step for a user whose password checked out.
+pub async fn create_challenge(pool: &DbPool, user_id: i32) -> Result<String, sqlx::Error> {
+    let token = crate::auth::generate_opaque_token();
+    sqlx::query(
+        "INSERT INTO totp_challenges (token_hash, user_id, expires_at)
+         VALUES (?, ?, NOW() + INTERVAL ? SECOND)",
+    )
+    .bind(hash_opaque_token(&token))
+    .bind(user_id)
+    .bind(challenge_ttl_secs())
+    .execute(pool)
+    .await?;
+    Ok(token)
+}
+
+/// The username a live login challenge belongs to, so the second step can
+/// be throttled like the first.
+pub async fn challenge_username(pool: &DbPool, token: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT u.username FROM totp_challenges c
+         JOIN users u ON u.id = c.user_id
+         WHERE c.token_hash = ? AND c.expires_at > NOW()",
+    )
+    .bind(hash_opaque_token(token))
+    .fetch_optional(pool)
+    .await
+}
+
+pub enum ChallengeOutcome {
+    Passed(i32),
+    WrongCode,
+    Invalid,
+}
+
+/// Completes a login challenge. A challenge is spent on success and after
+/// too many wrong codes.
+pub async fn complete_challenge(
+    pool: &DbPool,
+    token: &str,
+    code: &str,
+) -> Result<ChallengeOutcome, sqlx::Error> {
+    let token_hash = hash_opaque_token(token);
+    let mut tx = pool.begin().await?;
+
+    let challenge: Option<(i32, i32)> = sqlx::query_as(
+        "SELECT user_id, attempts FROM totp_challenges
+         WHERE token_hash = ? AND expires_at > NOW()
+         FOR UPDATE",
+    )
+    .bind(&token_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((user_id, attempts)) = challenge else {
+        return Ok(ChallengeOutcome::Invalid);
+    };
+
+    if verify_second_factor(&mut tx, user_id, code).await? {
+        sqlx::query("DELETE FROM totp_challenges WHERE token_hash = ?")
+            .bind(&token_hash)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(ChallengeOutcome::Passed(user_id));
+    }
+
+    if attempts + 1 >= MAX_CHALLENGE_ATTEMPTS {
+        sqlx::query("DELETE FROM totp_challenges WHERE token_hash = ?")
+            .bind(&token_hash)
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        sqlx::query("UPDATE totp_challenges SET attempts = attempts + 1 WHERE token_hash = ?")
+            .bind(&token_hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    Ok(ChallengeOutcome::WrongCode)
+}
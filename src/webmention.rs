@@ -0,0 +1,272 @@
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use url::Url;
+
+use crate::config::base_url;
+use crate::db::DbPool;
+use crate::http_client;
+
+const MAX_SOURCE_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub struct WebmentionError(pub String);
+
+impl std::fmt::Display for WebmentionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for WebmentionError {}
+
+pub fn post_url(post_id: i32) -> String {
+    format!("{}/posts/{}", base_url(), post_id)
+}
+
+pub fn endpoint_url() -> String {
+    format!("{}/webmention", base_url())
+}
+
+pub fn post_id_from_target(target: &str) -> Option<i32> {
+    let target = target.split(['#', '?']).next()?;
+    target
+        .strip_prefix(&format!("{}/posts/", base_url()))?
+        .trim_end_matches('/')
+        .parse()
+        .ok()
+}
+
+fn link_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"https?://[^\s<>"'()\[\]]+"#).expect("valid regex"))
+}
+
+fn tag_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?is)<(?:link|a)\b[^>]*>").expect("valid regex"))
+}
+
+fn attr_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r#"(?is)\b(rel|href)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#)
+            .expect("valid regex")
+    })
+}
+
+fn title_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").expect("valid regex"))
+}
+
+pub fn outbound_links(content: &str) -> Vec<String> {
+    let own = base_url();
+    let mut seen = HashSet::new();
+    link_regex()
+        .find_iter(content)
+        .map(|m| {
+            m.as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?'])
+                .to_string()
+        })
+        .filter(|link| !link.starts_with(&own))
+        .filter(|link| seen.insert(link.clone()))
+        .collect()
+}
+
+fn endpoint_from_link_header(value: &str) -> Option<String> {
+    value.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        let is_webmention = params.split(';').any(|param| {
+            param
+                .trim()
+                .strip_prefix("rel=")
+                .map(|rel| {
+                    rel.trim_matches('"')
+                        .split_whitespace()
+                        .any(|r| r.eq_ignore_ascii_case("webmention"))
+                })
+                .unwrap_or(false)
+        });
+        is_webmention.then(|| {
+            target
+                .trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+fn endpoint_from_html(html: &str) -> Option<String> {
+    tag_regex().find_iter(html).find_map(|tag| {
+        let mut rel = None;
+        let mut href = None;
+        for attr in attr_regex().captures_iter(tag.as_str()) {
+            let value = attr
+                .get(2)
+                .or_else(|| attr.get(3))
+                .or_else(|| attr.get(4))
+                .map(|v| v.as_str().to_string());
+            match attr[1].to_lowercase().as_str() {
+                "rel" => rel = value,
+                "href" => href = value,
+                _ => {}
+            }
+        }
+        let is_webmention = rel?
+            .split_whitespace()
+            .any(|r| r.eq_ignore_ascii_case("webmention"));
+        if is_webmention {
+            href
+        } else {
+            None
+        }
+    })
+}
+
+/// Rejects URLs that would make the server reach into its own network.
+fn check_url(url: &str) -> Result<(), WebmentionError> {
+    let parsed = Url::parse(url).map_err(|e| WebmentionError(format!("Invalid URL {url}: {e}")))?;
+    http_client::check_public_url(&parsed).map_err(WebmentionError)
+}
+
+async fn fetch_limited(url: &str) -> Result<(reqwest::header::HeaderMap, String), WebmentionError> {
+    check_url(url)?;
+    let mut response = http_client::public_only()
+        .get(url)
+        .header("Accept", "text/html, */*;q=0.5")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| WebmentionError(format!("Failed to fetch {url}: {e}")))?;
+
+    let headers = response.headers().clone();
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| WebmentionError(format!("Failed to read {url}: {e}")))?
+    {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_SOURCE_BYTES {
+            body.truncate(MAX_SOURCE_BYTES);
+            break;
+        }
+    }
+
+    Ok((headers, String::from_utf8_lossy(&body).into_owned()))
+}
+
+pub async fn discover_endpoint(target: &str) -> Result<Option<String>, WebmentionError> {
+    let (headers, html) = fetch_limited(target).await?;
+
+    let endpoint = headers
+        .get_all(reqwest::header::LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(endpoint_from_link_header)
+        .or_else(|| endpoint_from_html(&html));
+
+    let Some(endpoint) = endpoint else {
+        return Ok(None);
+    };
+
+    let base = Url::parse(target).map_err(|e| WebmentionError(format!("Invalid URL: {e}")))?;
+    let resolved = base
+        .join(&endpoint)
+        .map_err(|e| WebmentionError(format!("Invalid endpoint {endpoint}: {e}")))?;
+
+    Ok(Some(resolved.to_string()))
+}
+
+pub async fn send(source: &str, target: &str) -> Result<Option<String>, WebmentionError> {
+    let Some(endpoint) = discover_endpoint(target).await? else {
+        return Ok(None);
+    };
+
+    check_url(&endpoint)?;
+    http_client::public_only()
+        .post(&endpoint)
+        .form(&[("source", source), ("target", target)])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| WebmentionError(format!("Failed to notify {endpoint}: {e}")))?;
+
+    Ok(Some(endpoint))
+}
+
+pub fn spawn_send_for_post(pool: DbPool, post_id: i32, content: String) {
+    let links = outbound_links(&content);
+    if links.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let source = post_url(post_id);
+        for target in links {
+            let (status, endpoint, error) = match send(&source, &target).await {
+                Ok(Some(endpoint)) => ("sent", Some(endpoint), None),
+                Ok(None) => ("no_endpoint", None, None),
+                Err(e) => ("failed", None, Some(e.to_string())),
+            };
+
+            if let Err(e) = sqlx::query(
+                "INSERT INTO sent_webmentions (post_id, target_url, endpoint_url, status, error)
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(post_id)
+            .bind(&target)
+            .bind(&endpoint)
+            .bind(status)
+            .bind(&error)
+            .execute(&pool)
+            .await
+            {
+                tracing::warn!("Failed to record webmention to {}: {}", target, e);
+            }
+        }
+    });
+}
+
+fn page_title(html: &str) -> Option<String> {
+    title_regex()
+        .captures(html)
+        .map(|c| c[1].split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|title| !title.is_empty())
+        .map(|title| title.chars().take(255).collect())
+}
+
+pub async fn verify(pool: &DbPool, mention_id: i64, source: &str, target: &str) {
+    let outcome = match fetch_limited(source).await {
+        Ok((_, html)) if html.contains(target) => Ok(page_title(&html)),
+        Ok(_) => Err("Source does not link to target".to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    let result = match &outcome {
+        Ok(title) => {
+            sqlx::query(
+                "UPDATE webmentions SET status = 'verified', title = ?, error = NULL, verified_at = NOW()
+                 WHERE id = ?",
+            )
+            .bind(title)
+            .bind(mention_id)
+            .execute(pool)
+            .await
+        }
+        Err(error) => {
+            sqlx::query("UPDATE webmentions SET status = 'rejected', error = ? WHERE id = ?")
+                .bind(error)
+                .bind(mention_id)
+                .execute(pool)
+                .await
+        }
+    };
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to update webmention {}: {}", mention_id, e);
+    }
+}
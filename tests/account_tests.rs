@@ -0,0 +1,382 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use blog_api::auth::Permission;
+use blog_api::{accounts, models};
+use serde_json::json;
+use uuid::Uuid;
+
+mod common;
+
+use common::PASSWORD;
+
+#[tokio::test]
+async fn test_change_password_and_email() {
+    let (server, pool) = common::setup_test_server().await;
+    let auth = common::register(&server, "account").await;
+    let other = common::register(&server, "account").await;
+    let key = create_api_key(&server, &auth.token).await;
+
+    let me: models::AccountResponse = server
+        .get("/me")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .await
+        .json();
+    assert_eq!(me.user.username, auth.user.username);
+    assert!(me.deletion_scheduled_at.is_none());
+
+    server
+        .put("/me/password")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "current_password": "wrong-password", "new_password": "password456" }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let renewed: models::AuthResponse = server
+        .put("/me/password")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "current_password": PASSWORD, "new_password": "password456" }))
+        .await
+        .json();
+
+    server
+        .get("/me")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    assert_api_key_revoked(&server, &key).await;
+
+    server
+        .post("/login")
+        .json(&json!({ "username": auth.user.username, "password": "password456" }))
+        .await
+        .assert_status_ok();
+
+    server
+        .put("/me/email")
+        .add_header("Authorization", format!("Bearer {}", renewed.token))
+        .json(&json!({ "email": other.user.email, "current_password": "password456" }))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let new_email = format!("changed_{}@test.com", Uuid::new_v4().simple());
+    let updated: models::UserResponse = server
+        .put("/me/email")
+        .add_header("Authorization", format!("Bearer {}", renewed.token))
+        .json(&json!({ "email": new_email, "current_password": "password456" }))
+        .await
+        .json();
+    assert_eq!(updated.email, new_email);
+    assert!(!updated.email_verified);
+
+    let changes: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_events
+         WHERE actor_id = ? AND action = 'account.email_change'",
+    )
+    .bind(auth.user.id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(changes, 1);
+}
+
+async fn create_api_key(server: &TestServer, token: &str) -> String {
+    let created: models::CreatedApiKeyResponse = server
+        .post("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .json(&json!({ "name": "script", "scopes": ["posts:write"] }))
+        .await
+        .json();
+    created.token
+}
+
+async fn assert_api_key_revoked(server: &TestServer, key: &str) {
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {key}"))
+        .json(&json!({ "title": "Key revoked", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_delete_account() {
+    let (server, pool) = common::setup_test_server().await;
+
+    for disposal in ["anonymize", "delete"] {
+        let auth = common::register(&server, "account").await;
+        let key = create_api_key(&server, &auth.token).await;
+        let post: models::PostResponse = server
+            .post("/posts")
+            .add_header("Authorization", format!("Bearer {}", auth.token))
+            .json(&json!({ "title": "Soon gone", "content": "Content" }))
+            .await
+            .json();
+
+        server
+            .delete("/me")
+            .add_header("Authorization", format!("Bearer {}", auth.token))
+            .json(&json!({ "current_password": "wrong-password", "posts": disposal }))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        let scheduled: models::AccountDeletionResponse = server
+            .delete("/me")
+            .add_header("Authorization", format!("Bearer {}", auth.token))
+            .json(&json!({ "current_password": PASSWORD, "posts": disposal }))
+            .await
+            .json();
+        assert!(scheduled.delete_after > chrono::Utc::now());
+
+        server
+            .get("/me")
+            .add_header("Authorization", format!("Bearer {}", auth.token))
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+        assert_api_key_revoked(&server, &key).await;
+
+        // Signing back in within the grace period cancels the deletion.
+        let login = json!({ "username": auth.user.username, "password": PASSWORD });
+        let again: models::AuthResponse = server.post("/login").json(&login).await.json();
+        let me: models::AccountResponse = server
+            .get("/me")
+            .add_header("Authorization", format!("Bearer {}", again.token))
+            .await
+            .json();
+        assert!(me.deletion_scheduled_at.is_none());
+
+        server
+            .delete("/me")
+            .add_header("Authorization", format!("Bearer {}", again.token))
+            .json(&json!({ "current_password": PASSWORD, "posts": disposal }))
+            .await
+            .assert_status(StatusCode::ACCEPTED);
+
+        let requests: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM audit_events
+             WHERE actor_id = ? AND action = 'auth.account_delete'",
+        )
+        .bind(auth.user.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(requests, 2);
+
+        sqlx::query(
+            "UPDATE users SET deletion_scheduled_at = NOW() - INTERVAL 1 MINUTE WHERE id = ?",
+        )
+        .bind(auth.user.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        accounts::purge_due_accounts(&pool).await.unwrap();
+
+        server
+            .post("/login")
+            .json(&login)
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+
+        let response = server.get(&format!("/posts/{}", post.id)).await;
+        if disposal == "anonymize" {
+            let kept: models::PostResponse = response.json();
+            assert_eq!(kept.username, format!("deleted-{}", auth.user.id));
+        } else {
+            response.assert_status(StatusCode::NOT_FOUND);
+        }
+    }
+}
+
+// API keys
+
+#[tokio::test]
+async fn test_api_key_scopes() {
+    let (server, _) = common::setup_test_server().await;
+    let auth = common::register(&server, "apikey").await;
+
+    let created: models::CreatedApiKeyResponse = server
+        .post("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "name": "deploy script", "scopes": ["posts:write"], "expires_in_days": 30 }))
+        .await
+        .json();
+    assert!(created.token.starts_with("pat_"));
+    assert_eq!(created.key.scopes, vec![Permission::PostsWrite]);
+    assert!(created.key.expires_at.is_some());
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", created.token))
+        .json(&json!({ "title": "From a script", "content": "Body" }))
+        .await
+        .assert_status_ok();
+
+    // Outside its scopes, and on account routes, the key is refused.
+    server
+        .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {}", created.token))
+        .json(&json!({ "count": 1 }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    server
+        .get("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {}", created.token))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let keys: Vec<models::ApiKeyResponse> = server
+        .get("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .await
+        .json();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].name, "deploy script");
+    assert!(keys[0].last_used_at.is_some());
+
+    // Authors cannot hand out permissions they do not hold.
+    server
+        .post("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "name": "payouts", "scopes": ["transfer:send"] }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    server
+        .post("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "name": "moderation", "scopes": ["posts:edit_any"] }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .delete(&format!("/me/api-keys/{}", created.key.id))
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", created.token))
+        .json(&json!({ "title": "Revoked", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_api_key_expiry() {
+    let (server, pool) = common::setup_test_server().await;
+    let auth = common::register(&server, "apikey").await;
+
+    let created: models::CreatedApiKeyResponse = server
+        .post("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "name": "short lived", "scopes": ["posts:write"] }))
+        .await
+        .json();
+    assert!(created.key.expires_at.is_none());
+
+    sqlx::query("UPDATE api_keys SET expires_at = NOW() - INTERVAL 1 MINUTE WHERE id = ?")
+        .bind(created.key.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", created.token))
+        .json(&json!({ "title": "Expired", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+// Sessions
+
+async fn list_sessions(server: &TestServer, token: &str) -> Vec<models::SessionResponse> {
+    server
+        .get("/me/sessions")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .await
+        .json()
+}
+
+#[tokio::test]
+async fn test_list_and_revoke_sessions() {
+    let (server, _) = common::setup_test_server().await;
+    let username = format!("sessions_{}", Uuid::new_v4().simple());
+
+    let laptop: models::AuthResponse = server
+        .post("/register")
+        .add_header("User-Agent", "Laptop/1.0")
+        .add_header("X-Forwarded-For", "10.0.0.1, 203.0.113.7")
+        .json(&json!({
+            "username": username,
+            "email": format!("{}@test.com", username),
+            "password": PASSWORD
+        }))
+        .await
+        .json();
+    let phone: models::AuthResponse = server
+        .post("/login")
+        .add_header("User-Agent", "Phone/2.0")
+        .json(&json!({ "username": username, "password": PASSWORD }))
+        .await
+        .json();
+
+    let sessions = list_sessions(&server, &laptop.token).await;
+    assert_eq!(sessions.len(), 2);
+    let current = sessions.iter().find(|s| s.current).unwrap();
+    assert_eq!(current.user_agent.as_deref(), Some("Laptop/1.0"));
+    assert_eq!(current.ip.as_deref(), Some("203.0.113.7"));
+    let other = sessions.iter().find(|s| !s.current).unwrap();
+    assert_eq!(other.user_agent.as_deref(), Some("Phone/2.0"));
+
+    // Refreshing keeps the session.
+    let refreshed: models::AuthResponse = server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": laptop.refresh_token }))
+        .await
+        .json();
+    let sessions = list_sessions(&server, &refreshed.token).await;
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions.iter().find(|s| s.current).unwrap().id, current.id);
+
+    // Another user cannot see or end the session.
+    let stranger = common::register(&server, "sessions").await;
+    assert_eq!(list_sessions(&server, &stranger.token).await.len(), 1);
+    server
+        .delete(&format!("/me/sessions/{}", other.id))
+        .add_header("Authorization", format!("Bearer {}", stranger.token))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    server
+        .delete(&format!("/me/sessions/{}", other.id))
+        .add_header("Authorization", format!("Bearer {}", refreshed.token))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    // The phone is signed out: its access and refresh tokens stop working.
+    server
+        .get("/me/sessions")
+        .add_header("Authorization", format!("Bearer {}", phone.token))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": phone.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let sessions = list_sessions(&server, &refreshed.token).await;
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].current);
+
+    server
+        .delete(&format!("/me/sessions/{}", other.id))
+        .add_header("Authorization", format!("Bearer {}", refreshed.token))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .delete("/me/sessions/0")
+        .add_header("Authorization", format!("Bearer {}", refreshed.token))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,370 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_test::TestServer;
+use blog_api::{activitypub, db, models};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+mod common;
+
+#[derive(Clone)]
+struct FakeRemote {
+    actor: Value,
+    impostor: Value,
+    received: Arc<Mutex<Vec<(HeaderMap, Value)>>>,
+}
+
+struct RemoteInstance {
+    actor_id: String,
+    key_id: String,
+    private_key_pem: String,
+    /// Serves a document claiming to be `actor_id`, with its own key.
+    impostor_url: String,
+    impostor_key_id: String,
+    impostor_private_key_pem: String,
+    received: Arc<Mutex<Vec<(HeaderMap, Value)>>>,
+}
+
+async fn remote_actor(State(remote): State<FakeRemote>) -> Json<Value> {
+    Json(remote.actor)
+}
+
+async fn remote_impostor(State(remote): State<FakeRemote>) -> Json<Value> {
+    Json(remote.impostor)
+}
+
+async fn remote_inbox(
+    State(remote): State<FakeRemote>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let activity: Value = serde_json::from_slice(&body).unwrap();
+    remote.received.lock().unwrap().push((headers, activity));
+    StatusCode::ACCEPTED
+}
+
+async fn start_remote_instance() -> RemoteInstance {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let actor_id = format!("{base}/users/remote");
+    let key_id = format!("{actor_id}#main-key");
+    let keys = activitypub::generate_keys().unwrap();
+    let impostor_url = format!("{base}/users/impostor");
+    let impostor_key_id = format!("{impostor_url}#main-key");
+    let impostor_keys = activitypub::generate_keys().unwrap();
+
+    let remote = FakeRemote {
+        actor: json!({
+            "@context": ["https://www.w3.org/ns/activitystreams", "https://w3id.org/security/v1"],
+            "id": actor_id,
+            "type": "Person",
+            "preferredUsername": "remote",
+            "inbox": format!("{base}/inbox"),
+            "publicKey": {
+                "id": key_id,
+                "owner": actor_id,
+                "publicKeyPem": keys.public_key_pem
+            }
+        }),
+        impostor: json!({
+            "id": actor_id,
+            "type": "Person",
+            "inbox": format!("{base}/inbox"),
+            "publicKey": {
+                "id": impostor_key_id,
+                "owner": actor_id,
+                "publicKeyPem": impostor_keys.public_key_pem
+            }
+        }),
+        received: Arc::new(Mutex::new(Vec::new())),
+    };
+    let received = remote.received.clone();
+
+    let app = Router::new()
+        .route("/users/remote", get(remote_actor))
+        .route("/users/impostor", get(remote_impostor))
+        .route("/inbox", post(remote_inbox))
+        .with_state(remote);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    RemoteInstance {
+        actor_id,
+        key_id,
+        private_key_pem: keys.private_key_pem,
+        impostor_url,
+        impostor_key_id,
+        impostor_private_key_pem: impostor_keys.private_key_pem,
+        received,
+    }
+}
+
+async fn setup_test_server() -> (TestServer, db::DbPool) {
+    // The fake remote instance listens on localhost.
+    std::env::set_var("ALLOW_PRIVATE_NETWORK_FETCHES", "true");
+    common::setup_test_server().await
+}
+
+async fn post_signed(
+    server: &TestServer,
+    remote: &RemoteInstance,
+    username: &str,
+    activity: &Value,
+) -> axum_test::TestResponse {
+    post_signed_with_key(
+        server,
+        &remote.key_id,
+        &remote.private_key_pem,
+        username,
+        activity,
+    )
+    .await
+}
+
+async fn post_signed_with_key(
+    server: &TestServer,
+    key_id: &str,
+    private_key_pem: &str,
+    username: &str,
+    activity: &Value,
+) -> axum_test::TestResponse {
+    let path = format!("/users/{username}/inbox");
+    let body = serde_json::to_vec(activity).unwrap();
+    let headers = activitypub::sign_request(
+        "POST",
+        &format!("http://localhost{path}"),
+        &body,
+        key_id,
+        private_key_pem,
+    )
+    .unwrap();
+
+    let mut request = server
+        .post(&path)
+        .content_type(activitypub::ACTIVITY_JSON)
+        .bytes(body.into());
+    for (name, value) in headers {
+        request = request.add_header(
+            axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            axum::http::HeaderValue::from_str(&value).unwrap(),
+        );
+    }
+    request.await
+}
+
+#[tokio::test]
+async fn test_activitypub_federation() {
+    let _env = common::env_lock().await;
+    let (server, pool) = setup_test_server().await;
+    let remote = start_remote_instance().await;
+
+    let auth = common::register(&server, "fedi").await;
+    let username = auth.user.username.clone();
+    let actor = activitypub::actor_url(&username);
+
+    let webfinger: Value = server
+        .get("/.well-known/webfinger")
+        .add_query_param(
+            "resource",
+            format!("acct:{}@{}", username, activitypub::domain()),
+        )
+        .await
+        .json();
+    assert_eq!(webfinger["links"][0]["href"], json!(actor));
+
+    let person: Value = server.get(&format!("/users/{username}")).await.json();
+    assert_eq!(person["type"], "Person");
+    assert!(person["publicKey"]["publicKeyPem"]
+        .as_str()
+        .unwrap()
+        .contains("PUBLIC KEY"));
+
+    let follow_id = format!("{}/follows/{}", remote.actor_id, Uuid::new_v4());
+    let follow = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": follow_id,
+        "type": "Follow",
+        "actor": remote.actor_id,
+        "object": actor
+    });
+
+    server
+        .post(&format!("/users/{username}/inbox"))
+        .json(&follow)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    post_signed(&server, &remote, &username, &follow)
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+
+    let mut accepted = false;
+    for _ in 0..100 {
+        if let Some((headers, activity)) = remote.received.lock().unwrap().first() {
+            assert_eq!(activity["type"], "Accept");
+            assert_eq!(activity["object"]["id"], json!(follow_id));
+            assert!(headers.contains_key("signature"));
+            accepted = true;
+        }
+        if accepted {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(accepted, "remote instance received an Accept");
+
+    let followers: Value = server
+        .get(&format!("/users/{username}/followers"))
+        .await
+        .json();
+    assert_eq!(followers["totalItems"], 1);
+
+    let post: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "Federated post",
+            "content": "Hello fediverse"
+        }))
+        .await
+        .json();
+    let article_id = activitypub::article_url(&username, post.id);
+
+    let outbox: Value = server
+        .get(&format!("/users/{username}/outbox"))
+        .await
+        .json();
+    assert_eq!(outbox["orderedItems"][0]["object"]["id"], json!(article_id));
+    assert_eq!(outbox["orderedItems"][0]["object"]["type"], "Article");
+
+    post_signed(
+        &server,
+        &remote,
+        &username,
+        &json!({
+            "id": format!("{}/likes/{}", remote.actor_id, Uuid::new_v4()),
+            "type": "Like",
+            "actor": remote.actor_id,
+            "object": article_id
+        }),
+    )
+    .await
+    .assert_status(StatusCode::ACCEPTED);
+
+    let note_id = format!("{}/notes/{}", remote.actor_id, Uuid::new_v4());
+    post_signed(
+        &server,
+        &remote,
+        &username,
+        &json!({
+            "id": format!("{note_id}/activity"),
+            "type": "Create",
+            "actor": remote.actor_id,
+            "object": {
+                "id": note_id,
+                "type": "Note",
+                "attributedTo": remote.actor_id,
+                "inReplyTo": article_id,
+                "content": "<p>Nice <b>post</b>!</p>",
+                "to": [activitypub::PUBLIC]
+            }
+        }),
+    )
+    .await
+    .assert_status(StatusCode::ACCEPTED);
+
+    // Followers-only replies and direct messages stay off the post.
+    for audience in [format!("{}/followers", remote.actor_id), actor.clone()] {
+        let note_id = format!("{}/notes/{}", remote.actor_id, Uuid::new_v4());
+        post_signed(
+            &server,
+            &remote,
+            &username,
+            &json!({
+                "id": format!("{note_id}/activity"),
+                "type": "Create",
+                "actor": remote.actor_id,
+                "object": {
+                    "id": note_id,
+                    "type": "Note",
+                    "attributedTo": remote.actor_id,
+                    "inReplyTo": article_id,
+                    "content": "<p>Just between us</p>",
+                    "to": [audience]
+                }
+            }),
+        )
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+    }
+
+    let (likes,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM post_reactions WHERE post_id = ? AND kind = 'like'")
+            .bind(post.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(likes, 1);
+
+    let comments: Vec<String> =
+        sqlx::query_scalar("SELECT content FROM post_comments WHERE post_id = ?")
+            .bind(post.id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(comments, vec!["Nice post!"]);
+
+    post_signed(
+        &server,
+        &remote,
+        &username,
+        &json!({
+            "id": format!("{follow_id}/undo"),
+            "type": "Undo",
+            "actor": remote.actor_id,
+            "object": follow
+        }),
+    )
+    .await
+    .assert_status(StatusCode::ACCEPTED);
+
+    let followers: Value = server
+        .get(&format!("/users/{username}/followers"))
+        .await
+        .json();
+    assert_eq!(followers["totalItems"], 0);
+
+    // A key served from elsewhere cannot speak for the remote actor, even
+    // when its document claims the actor's id.
+    let spoofed_follow = json!({
+        "id": format!("{}/follows/{}", remote.actor_id, Uuid::new_v4()),
+        "type": "Follow",
+        "actor": remote.actor_id,
+        "object": actor
+    });
+    post_signed_with_key(
+        &server,
+        &remote.impostor_key_id,
+        &remote.impostor_private_key_pem,
+        &username,
+        &spoofed_follow,
+    )
+    .await
+    .assert_status(StatusCode::UNAUTHORIZED);
+    assert!(activitypub::fetch_actor(&remote.impostor_url)
+        .await
+        .is_err());
+
+    let followers: Value = server
+        .get(&format!("/users/{username}/followers"))
+        .await
+        .json();
+    assert_eq!(followers["totalItems"], 0);
+}
@@ -0,0 +1,593 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use blog_api::auth::{self, Permission};
+use blog_api::{audit, models, password_reset};
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+mod common;
+
+use common::{error_message, PASSWORD};
+
+#[tokio::test]
+async fn test_role_permissions() {
+    let (server, pool) = common::setup_test_server().await;
+    let author = common::register(&server, "rbac").await;
+    let editor = common::register_with_role(&server, &pool, "rbac", "editor").await;
+    let admin = common::register_with_role(&server, &pool, "rbac", "admin").await;
+
+    assert_eq!(author.user.permissions, vec![Permission::PostsWrite]);
+    assert!(editor.user.permissions.contains(&Permission::PostsEditAny));
+
+    let post: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", author.token))
+        .json(&json!({ "title": "By author", "content": "Content" }))
+        .await
+        .json();
+    let editor_post: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", editor.token))
+        .json(&json!({ "title": "By editor", "content": "Content" }))
+        .await
+        .json();
+
+    // Editors may edit anyone's posts, authors only their own.
+    server
+        .put(&format!("/posts/{}", post.id))
+        .add_header("Authorization", format!("Bearer {}", editor.token))
+        .json(&json!({ "title": "Edited by editor" }))
+        .await
+        .assert_status_ok();
+
+    server
+        .put(&format!("/posts/{}", editor_post.id))
+        .add_header("Authorization", format!("Bearer {}", author.token))
+        .json(&json!({ "title": "Edited by author" }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    for user in [&author, &editor] {
+        server
+            .post("/wallets/generate")
+            .add_header("Authorization", format!("Bearer {}", user.token))
+            .json(&json!({ "count": 1 }))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    // Refusals never reach the handler, so the permission layer logs them.
+    let (target, outcome): (String, String) = sqlx::query_as(
+        "SELECT target, outcome FROM audit_events
+         WHERE actor_id = ? AND action = 'auth.permission_denied'
+         ORDER BY id DESC LIMIT 1",
+    )
+    .bind(author.user.id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(target, "wallets:generate POST /wallets/generate");
+    assert_eq!(outcome, "denied");
+
+    server
+        .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "count": 1 }))
+        .await
+        .assert_status_ok();
+
+    server
+        .delete(&format!("/posts/{}", post.id))
+        .add_header("Authorization", format!("Bearer {}", editor.token))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_assign_role() {
+    let (server, pool) = common::setup_test_server().await;
+    let author = common::register(&server, "rbac").await;
+    let admin = common::register_with_role(&server, &pool, "rbac", "admin").await;
+
+    let roles: Vec<serde_json::Value> = server
+        .get("/admin/roles")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .json();
+    assert_eq!(roles.len(), 3);
+
+    server
+        .put(&format!("/admin/users/{}/role", admin.user.id))
+        .add_header("Authorization", format!("Bearer {}", author.token))
+        .json(&json!({ "role": "author" }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    server
+        .put(&format!("/admin/users/{}/role", author.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "role": "superuser" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .put(&format!("/admin/users/{}/role", admin.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "role": "author" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let promoted: models::UserResponse = server
+        .put(&format!("/admin/users/{}/role", author.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "role": "editor" }))
+        .await
+        .json();
+    assert_eq!(promoted.role, "editor");
+
+    let (target, request_digest): (String, Option<String>) = sqlx::query_as(
+        "SELECT target, request_digest FROM audit_events
+         WHERE actor_id = ? AND action = 'admin.role_assign'
+         ORDER BY id DESC LIMIT 1",
+    )
+    .bind(admin.user.id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(target, format!("user:{}", author.user.id));
+    assert_eq!(
+        request_digest,
+        Some(audit::digest(
+            &json!({ "old_role": "author", "new_role": "editor" })
+        ))
+    );
+
+    // Tokens carrying the old role are rejected; a refresh picks up the new one.
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", author.token))
+        .json(&json!({ "title": "Stale", "content": "Content" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let refreshed = common::refresh(&server, &author).await;
+    assert_eq!(refreshed.user.role, "editor");
+    assert!(refreshed
+        .user
+        .permissions
+        .contains(&Permission::PostsFeature));
+}
+
+// User moderation
+
+#[tokio::test]
+async fn test_list_and_search_users() {
+    let (server, pool) = common::setup_test_server().await;
+    let admin = common::register_with_role(&server, &pool, "moderated", "admin").await;
+    let user = common::register(&server, "moderated").await;
+
+    let response = server
+        .get(&format!("/admin/users?q={}", user.user.username))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await;
+    response.assert_status_ok();
+    let users: Vec<models::AdminUserResponse> = response.json();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].user.id, user.user.id);
+    assert_eq!(users[0].status, "active");
+
+    let response = server
+        .get(&format!(
+            "/admin/users?q={}&status=banned",
+            user.user.username
+        ))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await;
+    assert!(response.json::<Vec<models::AdminUserResponse>>().is_empty());
+
+    server
+        .get("/admin/users?status=sleeping")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .get("/admin/users")
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_suspension_blocks_tokens_and_sign_in_until_reinstated() {
+    let (server, pool) = common::setup_test_server().await;
+    let admin = common::register_with_role(&server, &pool, "moderated", "admin").await;
+    let user = common::register(&server, "moderated").await;
+
+    server
+        .post(&format!("/admin/users/{}/suspend", user.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "until": Utc::now() - Duration::hours(1) }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .post(&format!("/admin/users/{}/suspend", admin.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "until": Utc::now() + Duration::days(1) }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let response = server
+        .post(&format!("/admin/users/{}/suspend", user.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "until": Utc::now() + Duration::days(1), "reason": "Spam" }))
+        .await;
+    response.assert_status_ok();
+    let suspended: models::AdminUserResponse = response.json();
+    assert_eq!(suspended.status, "suspended");
+    assert_eq!(suspended.moderation_reason.as_deref(), Some("Spam"));
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .json(&json!({ "title": "Still here", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": user.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    let response = common::login(&server, &user.user.username, PASSWORD).await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert!(error_message(&response).contains("suspended until"));
+
+    server
+        .post(&format!("/admin/users/{}/reinstate", user.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .assert_status_ok();
+    let signed_in: models::AuthResponse = common::login(&server, &user.user.username, PASSWORD)
+        .await
+        .json();
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", signed_in.token))
+        .json(&json!({ "title": "Back again", "content": "Body" }))
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_ban_is_enforced_on_outstanding_tokens() {
+    let (server, pool) = common::setup_test_server().await;
+    let admin = common::register_with_role(&server, &pool, "moderated", "admin").await;
+    let user = common::register(&server, "moderated").await;
+
+    // Set out of band, so only the restriction check stands in the way.
+    sqlx::query("UPDATE users SET banned_at = NOW() WHERE id = ?")
+        .bind(user.user.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    blog_api::moderation::forget(user.user.id);
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .json(&json!({ "title": "Banned", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": user.refresh_token }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let response = server
+        .post(&format!("/admin/users/{}/ban", user.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "reason": "Abuse" }))
+        .await;
+    response.assert_status_ok();
+    assert_eq!(
+        response.json::<models::AdminUserResponse>().status,
+        "banned"
+    );
+
+    let response = common::login(&server, &user.user.username, PASSWORD).await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(error_message(&response), "This account has been banned");
+
+    server
+        .post("/admin/users/2147483647/ban")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_forced_password_reset() {
+    let (server, pool) = common::setup_test_server().await;
+    let admin = common::register_with_role(&server, &pool, "moderated", "admin").await;
+    let user = common::register(&server, "moderated").await;
+    let key: models::CreatedApiKeyResponse = server
+        .post("/me/api-keys")
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .json(&json!({ "name": "script", "scopes": ["posts:write"] }))
+        .await
+        .json();
+
+    let response = server
+        .post(&format!("/admin/users/{}/password-reset", user.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await;
+    response.assert_status_ok();
+    assert!(
+        response
+            .json::<models::AdminUserResponse>()
+            .password_reset_required
+    );
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .json(&json!({ "title": "Signed out", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", key.token))
+        .json(&json!({ "title": "Key revoked", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    let response = common::login(&server, &user.user.username, PASSWORD).await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert!(error_message(&response).contains("password reset is required"));
+
+    let token = password_reset::issue_reset_token(&pool, user.user.id)
+        .await
+        .unwrap();
+    server
+        .post("/password/reset")
+        .json(&json!({ "token": token, "password": "battery-staple-77" }))
+        .await
+        .assert_status_ok();
+
+    server
+        .post("/login")
+        .json(&json!({ "username": user.user.username, "password": "battery-staple-77" }))
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_impersonation_is_limited_and_audited() {
+    let (server, pool) = common::setup_test_server().await;
+    let admin = common::register_with_role(&server, &pool, "moderated", "admin").await;
+    let other_admin = common::register_with_role(&server, &pool, "moderated", "admin").await;
+    let user = common::register(&server, "moderated").await;
+
+    server
+        .post(&format!("/admin/users/{}/impersonate", other_admin.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    server
+        .post(&format!("/admin/users/{}/impersonate", user.user.id))
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let response = server
+        .post(&format!("/admin/users/{}/impersonate", user.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await;
+    response.assert_status_ok();
+    let impersonation: models::ImpersonationResponse = response.json();
+    assert_eq!(impersonation.user.id, user.user.id);
+    assert!(impersonation.expires_in > 0);
+
+    let claims = auth::verify_token(&impersonation.token).unwrap();
+    assert_eq!(claims.sub, user.user.id);
+    assert_eq!(claims.act, Some(admin.user.id));
+    assert_eq!(claims.sid, None);
+
+    let post: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", impersonation.token))
+        .json(&json!({ "title": "On behalf", "content": "Body" }))
+        .await
+        .json();
+    assert_eq!(post.user_id, user.user.id);
+
+    // Account and session management stay with the user themselves.
+    server
+        .get("/me/sessions")
+        .add_header("Authorization", format!("Bearer {}", impersonation.token))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let events: Vec<models::AuditEventResponse> = server
+        .get(&format!("/admin/audit-events?actor_id={}", admin.user.id))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .json();
+    let actions: Vec<&str> = events.iter().map(|event| event.action.as_str()).collect();
+    assert!(actions.contains(&"admin.impersonate"));
+    assert!(events.iter().any(|event| {
+        event.action == "admin.impersonation_request"
+            && event.target == format!("user:{} POST /posts", user.user.id)
+    }));
+
+    // The token dies with the admin's own right to manage users.
+    sqlx::query("UPDATE users SET role = 'editor' WHERE id = ?")
+        .bind(admin.user.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", impersonation.token))
+        .json(&json!({ "title": "Still on behalf", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+// Audit log
+
+async fn events(
+    server: &TestServer,
+    admin: &models::AuthResponse,
+    query: &str,
+) -> Vec<models::AuditEventResponse> {
+    let response = server
+        .get(&format!("/admin/audit-events?{query}"))
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await;
+    response.assert_status_ok();
+    response.json()
+}
+
+#[tokio::test]
+async fn test_audit_log_records_and_chains_events() {
+    let (server, pool) = common::setup_test_server().await;
+    let admin = common::register_with_role(&server, &pool, "audit", "admin").await;
+    let user = common::register(&server, "audit").await;
+    let actor = format!("actor_id={}", user.user.id);
+
+    let registered = events(&server, &admin, &format!("{actor}&action=auth.register")).await;
+    assert_eq!(registered.len(), 1);
+    assert_eq!(registered[0].target, format!("user:{}", user.user.id));
+    assert_eq!(registered[0].outcome, "success");
+
+    server
+        .post("/login")
+        .add_header("X-Forwarded-For", "198.51.100.23")
+        .json(&json!({ "username": user.user.username, "password": "wrong-password" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    let failed = events(
+        &server,
+        &admin,
+        &format!("target=username:{}", user.user.username),
+    )
+    .await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].action, "auth.login");
+    assert_eq!(failed[0].outcome, "denied");
+    assert_eq!(failed[0].ip.as_deref(), Some("198.51.100.23"));
+
+    // Oversized targets are cut to fit instead of losing the event.
+    server
+        .post("/login")
+        .json(&json!({ "username": "x".repeat(300), "password": "wrong-password" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    let long_target = format!("username:{}{}", user.user.username, "x".repeat(300));
+    audit::record(
+        &pool,
+        &audit::AuditEvent {
+            action: "auth.login",
+            target: long_target.clone(),
+            outcome: audit::Outcome::Denied,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let cut: String = long_target.chars().take(255).collect();
+    let truncated = events(&server, &admin, &format!("target={cut}")).await;
+    assert_eq!(truncated.len(), 1);
+
+    let post: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .json(&json!({ "title": "Audited", "content": "Body" }))
+        .await
+        .json();
+    let created = events(&server, &admin, &format!("target=post:{}", post.id)).await;
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].action, "post.create");
+    assert_eq!(created[0].actor_id, Some(user.user.id));
+    assert_eq!(
+        created[0].request_digest.as_ref().map(String::len),
+        Some(64)
+    );
+
+    // The digest covers the transfers but never the private key. Only
+    // admins may send transfers.
+    let transfers = json!([{
+        "to_address": "0x000000000000000000000000000000000000dEaD",
+        "amount": "0.1"
+    }]);
+    server
+        .post("/transfer/batch")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({
+            "chain": "nowhere",
+            "private_key": "11".repeat(32),
+            "transfers": transfers
+        }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    let transfer = events(
+        &server,
+        &admin,
+        &format!("actor_id={}&action=transfer.batch", admin.user.id),
+    )
+    .await;
+    assert_eq!(transfer.len(), 1);
+    assert_eq!(transfer[0].target, "chain:nowhere");
+    assert_eq!(transfer[0].outcome, "failure");
+    assert_eq!(
+        transfer[0].request_digest.as_deref(),
+        Some(audit::digest(&json!({ "chain": "nowhere", "transfers": transfers })).as_str())
+    );
+
+    // Filters combine, and pages run newest first.
+    let auth_events = events(&server, &admin, &format!("{actor}&action_prefix=auth.")).await;
+    assert_eq!(auth_events.len(), 2);
+    assert!(auth_events[0].id > auth_events[1].id);
+    let older = events(
+        &server,
+        &admin,
+        &format!("{actor}&limit=1&before_id={}", transfer[0].id),
+    )
+    .await;
+    assert_eq!(older[0].id, created[0].id);
+    server
+        .get("/admin/audit-events?outcome=maybe")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Each event links to the one before it.
+    assert_eq!(created[0].hash.len(), 64);
+    assert_ne!(created[0].prev_hash, created[0].hash);
+    let verification: models::AuditVerification = server
+        .get("/admin/audit-events/verify")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .json();
+    assert!(verification.valid);
+    assert!(verification.checked >= 4);
+
+    // Only admins read the log, and the table refuses edits.
+    server
+        .get("/admin/audit-events")
+        .add_header("Authorization", format!("Bearer {}", user.token))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    assert!(
+        sqlx::query("UPDATE audit_events SET outcome = 'success' WHERE id = ?")
+            .bind(transfer[0].id)
+            .execute(&pool)
+            .await
+            .is_err()
+    );
+    assert!(sqlx::query("DELETE FROM audit_events WHERE id = ?")
+        .bind(transfer[0].id)
+        .execute(&pool)
+        .await
+        .is_err());
+}
@@ -1,59 +1,24 @@
-use axum::{
-    http::StatusCode,
-    middleware,
-    routing::{delete, get, post, put},
-    Router,
-};
+use axum::http::StatusCode;
 use axum_test::TestServer;
-use blog_api::{auth, db, handlers, models};
+use blog_api::{content, models, recommend};
 use serde_json::json;
 use uuid::Uuid;
 
-async fn setup_test_server() -> TestServer {
-    dotenv::dotenv().ok();
-
-    let pool = db::create_pool()
-        .await
-        .expect("Failed to create database pool");
-
-    let public_routes = Router::new()
-        .route("/register", post(handlers::user_handler::register))
-        .route("/login", post(handlers::user_handler::login))
-        .route("/posts", get(handlers::post_handler::get_posts))
-        .route("/posts/:id", get(handlers::post_handler::get_post))
-        .route(
-            "/wallets/generate",
-            post(handlers::wallet_handler::generate_wallets),
-        );
-
-    let protected_routes = Router::new()
-        .route("/posts", post(handlers::post_handler::create_post))
-        .route("/posts/:id", put(handlers::post_handler::update_post))
-        .route("/posts/:id", delete(handlers::post_handler::delete_post))
-        .route_layer(middleware::from_fn_with_state(
-            pool.clone(),
-            auth::auth_middleware,
-        ));
-
-    let app = Router::new()
-        .merge(public_routes)
-        .merge(protected_routes)
-        .with_state(pool);
-
-    TestServer::new(app).unwrap()
-}
+mod common;
+
+use common::PASSWORD;
 
 #[tokio::test]
 async fn test_register() {
-    let server = setup_test_server().await;
+    let (server, _) = common::setup_test_server().await;
 
-    let username = format!("testuser_{}", Uuid::new_v4().to_string().replace("-", ""));
+    let username = format!("testuser_{}", Uuid::new_v4().simple());
     let response = server
         .post("/register")
         .json(&json!({
             "username": username,
             "email": format!("{}@test.com", username),
-            "password": "password123"
+            "password": PASSWORD
         }))
         .await;
 
@@ -66,24 +31,16 @@ async fn test_register() {
 
 #[tokio::test]
 async fn test_register_duplicate_username() {
-    let server = setup_test_server().await;
+    let (server, _) = common::setup_test_server().await;
 
-    let username = format!("testuser_{}", Uuid::new_v4().to_string().replace("-", ""));
-    server
-        .post("/register")
-        .json(&json!({
-            "username": username,
-            "email": format!("{}@test.com", username),
-            "password": "password123"
-        }))
-        .await;
+    let username = common::register(&server, "testuser").await.user.username;
 
     let response = server
         .post("/register")
         .json(&json!({
             "username": username,
             "email": format!("another_{}@test.com", username),
-            "password": "password123"
+            "password": PASSWORD
         }))
         .await;
 
@@ -92,25 +49,11 @@ async fn test_register_duplicate_username() {
 
 #[tokio::test]
 async fn test_login() {
-    let server = setup_test_server().await;
+    let (server, _) = common::setup_test_server().await;
 
-    let username = format!("testuser_{}", Uuid::new_v4().to_string().replace("-", ""));
-    server
-        .post("/register")
-        .json(&json!({
-            "username": username,
-            "email": format!("{}@test.com", username),
-            "password": "password123"
-        }))
-        .await;
+    let username = common::register(&server, "testuser").await.user.username;
 
-    let response = server
-        .post("/login")
-        .json(&json!({
-            "username": username,
-            "password": "password123"
-        }))
-        .await;
+    let response = common::login(&server, &username, PASSWORD).await;
 
     response.assert_status(StatusCode::OK);
 
@@ -121,7 +64,7 @@ async fn test_login() {
 
 #[tokio::test]
 async fn test_login_invalid_credentials() {
-    let server = setup_test_server().await;
+    let (server, _) = common::setup_test_server().await;
 
     let response = server
         .post("/login")
@@ -135,20 +78,119 @@ async fn test_login_invalid_credentials() {
 }
 
 #[tokio::test]
-async fn test_create_post() {
-    let server = setup_test_server().await;
+async fn test_refresh_token_rotation() {
+    let (server, _) = common::setup_test_server().await;
 
-    let username = format!("testuser_{}", Uuid::new_v4().to_string().replace("-", ""));
-    let register_response = server
-        .post("/register")
-        .json(&json!({
-            "username": username,
-            "email": format!("{}@test.com", username),
-            "password": "password123"
-        }))
+    let auth = common::register(&server, "testuser").await;
+    let username = auth.user.username.clone();
+    assert!(auth.expires_in > 0);
+    assert!(!auth.refresh_token.is_empty());
+
+    let response = server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": auth.refresh_token }))
         .await;
 
-    let auth: models::AuthResponse = register_response.json();
+    response.assert_status_ok();
+    let rotated: models::AuthResponse = response.json();
+    assert_ne!(rotated.refresh_token, auth.refresh_token);
+    assert_eq!(rotated.user.username, username);
+
+    // Replaying the first token revokes every token in its family.
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": auth.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": rotated.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": "not-a-token" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_logout() {
+    let (server, _) = common::setup_test_server().await;
+
+    let first = common::register(&server, "testuser").await;
+    let username = first.user.username.clone();
+
+    let second: models::AuthResponse = common::login(&server, &username, PASSWORD).await.json();
+    let third: models::AuthResponse = common::login(&server, &username, PASSWORD).await.json();
+
+    let post = json!({ "title": "Session", "content": "Session check" });
+
+    server
+        .post("/logout")
+        .add_header("Authorization", format!("Bearer {}", first.token))
+        .json(&json!({ "refresh_token": first.refresh_token }))
+        .await
+        .assert_status_ok();
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", first.token))
+        .json(&post)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": first.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // Other sessions are unaffected by a single logout.
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", second.token))
+        .json(&post)
+        .await
+        .assert_status_ok();
+
+    server
+        .post("/logout/all")
+        .add_header("Authorization", format!("Bearer {}", third.token))
+        .await
+        .assert_status_ok();
+
+    for token in [&second.token, &third.token] {
+        server
+            .post("/posts")
+            .add_header("Authorization", format!("Bearer {token}"))
+            .json(&post)
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": second.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let fresh: models::AuthResponse = common::login(&server, &username, PASSWORD).await.json();
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", fresh.token))
+        .json(&post)
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_create_post() {
+    let (server, _) = common::setup_test_server().await;
+
+    let auth = common::register(&server, "testuser").await;
 
     let response = server
         .post("/posts")
@@ -168,7 +210,7 @@ async fn test_create_post() {
 
 #[tokio::test]
 async fn test_create_post_unauthorized() {
-    let server = setup_test_server().await;
+    let (server, _) = common::setup_test_server().await;
 
     let response = server
         .post("/posts")
@@ -183,7 +225,7 @@ async fn test_create_post_unauthorized() {
 
 #[tokio::test]
 async fn test_get_posts() {
-    let server = setup_test_server().await;
+    let (server, _) = common::setup_test_server().await;
 
     let response = server.get("/posts").await;
 
@@ -192,19 +234,9 @@ async fn test_get_posts() {
 
 #[tokio::test]
 async fn test_update_post() {
-    let server = setup_test_server().await;
-
-    let username = format!("testuser_{}", Uuid::new_v4().to_string().replace("-", ""));
-    let register_response = server
-        .post("/register")
-        .json(&json!({
-            "username": username,
-            "email": format!("{}@test.com", username),
-            "password": "password123"
-        }))
-        .await;
+    let (server, _) = common::setup_test_server().await;
 
-    let auth: models::AuthResponse = register_response.json();
+    let auth = common::register(&server, "testuser").await;
 
     let create_response = server
         .post("/posts")
@@ -234,19 +266,9 @@ async fn test_update_post() {
 
 #[tokio::test]
 async fn test_delete_post() {
-    let server = setup_test_server().await;
+    let (server, _) = common::setup_test_server().await;
 
-    let username = format!("testuser_{}", Uuid::new_v4().to_string().replace("-", ""));
-    let register_response = server
-        .post("/register")
-        .json(&json!({
-            "username": username,
-            "email": format!("{}@test.com", username),
-            "password": "password123"
-        }))
-        .await;
-
-    let auth: models::AuthResponse = register_response.json();
+    let auth = common::register(&server, "testuser").await;
 
     let create_response = server
         .post("/posts")
@@ -271,12 +293,224 @@ async fn test_delete_post() {
     get_response.assert_status(StatusCode::NOT_FOUND);
 }
 
+#[tokio::test]
+async fn test_post_translations() {
+    let (server, _) = common::setup_test_server().await;
+
+    let auth = common::register(&server, "testuser").await;
+
+    let english: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "Hello",
+            "content": "Hello world",
+            "language": "en"
+        }))
+        .await
+        .json();
+    assert_eq!(english.language, "en");
+    assert_eq!(english.translation_group, None);
+
+    let response = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "你好",
+            "content": "你好，世界",
+            "language": "zh_cn",
+            "translation_of": english.id
+        }))
+        .await;
+
+    response.assert_status_ok();
+    let chinese: models::PostResponse = response.json();
+    assert_eq!(chinese.language, "zh-CN");
+    assert_eq!(chinese.translation_group, Some(english.id));
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "Hello again",
+            "content": "Duplicate language",
+            "language": "en",
+            "translation_of": chinese.id
+        }))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let translations: Vec<models::TranslationResponse> = server
+        .get(&format!("/posts/{}/translations", chinese.id))
+        .await
+        .json();
+    let languages: Vec<&str> = translations
+        .iter()
+        .map(|translation| translation.language.as_str())
+        .collect();
+    assert_eq!(languages, vec!["en", "zh-CN"]);
+
+    let response = server
+        .get(&format!("/posts/{}", english.id))
+        .add_header("Accept-Language", "zh-TW, zh;q=0.9, en;q=0.5")
+        .await;
+
+    response.assert_status_ok();
+    assert_eq!(response.header("content-language"), "zh-CN");
+    let selected: models::PostResponse = response.json();
+    assert_eq!(selected.id, chinese.id);
+
+    let selected: models::PostResponse = server
+        .get(&format!("/posts/{}", chinese.id))
+        .add_header("Accept-Language", "fr")
+        .await
+        .json();
+    assert_eq!(selected.id, chinese.id);
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "Bad",
+            "content": "Bad language",
+            "language": "not a language"
+        }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Well formed, but longer than the column.
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "Bad",
+            "content": "Long language",
+            "language": "zh-Hant-CN-private1"
+        }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_post_derived_metadata() {
+    let (server, pool) = common::setup_test_server().await;
+
+    let auth = common::register(&server, "testuser").await;
+
+    let content = format!(
+        "# Introduction\n\nRust makes **systems** programming approachable.\n\n## 安装\n\n{}\n\n## Introduction\n\n{}",
+        "中文".repeat(200),
+        "word ".repeat(300)
+    );
+    let create_response = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "Derived Metadata",
+            "content": content
+        }))
+        .await;
+
+    create_response.assert_status_ok();
+    let created_post: models::PostResponse = create_response.json();
+    assert!(created_post
+        .excerpt
+        .starts_with("Rust makes systems programming approachable."));
+    assert_eq!(created_post.reading_time_minutes, 3);
+    let anchors: Vec<&str> = created_post
+        .toc
+        .iter()
+        .map(|entry| entry.anchor.as_str())
+        .collect();
+    assert_eq!(anchors, vec!["introduction", "安装", "introduction-1"]);
+    assert_eq!(created_post.toc[1].level, 2);
+
+    let response = server
+        .put(&format!("/posts/{}", created_post.id))
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "excerpt": "A hand-written summary"
+        }))
+        .await;
+
+    response.assert_status(StatusCode::OK);
+    let updated_post: models::PostResponse = response.json();
+    assert_eq!(updated_post.excerpt, "A hand-written summary");
+
+    let response = server
+        .put(&format!("/posts/{}", created_post.id))
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "content": "Short and sweet."
+        }))
+        .await;
+
+    let updated_post: models::PostResponse = response.json();
+    assert_eq!(updated_post.excerpt, "A hand-written summary");
+    assert_eq!(updated_post.reading_time_minutes, 1);
+    assert!(updated_post.toc.is_empty());
+
+    let response = server
+        .put(&format!("/posts/{}", created_post.id))
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "excerpt": ""
+        }))
+        .await;
+
+    let updated_post: models::PostResponse = response.json();
+    assert_eq!(updated_post.excerpt, "Short and sweet.");
+
+    // Posts from before the migration are filled in at startup.
+    sqlx::query(
+        "UPDATE posts SET content = '## Legacy\n\nWritten long ago.', excerpt = '',
+                          reading_time_minutes = 0, toc = JSON_ARRAY()
+         WHERE id = ?",
+    )
+    .bind(created_post.id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    assert!(content::backfill_post_meta(&pool).await.unwrap() >= 1);
+
+    let legacy_post: models::PostResponse = server
+        .get(&format!("/posts/{}", created_post.id))
+        .await
+        .json();
+    assert_eq!(legacy_post.excerpt, "Written long ago.");
+    assert_eq!(legacy_post.reading_time_minutes, 1);
+    assert_eq!(legacy_post.toc[0].anchor, "legacy");
+}
+
+/// A server and an admin token, since generating wallets takes
+/// `wallets:generate`.
+async fn setup_wallet_server() -> (TestServer, String) {
+    let (server, pool) = common::setup_test_server().await;
+    let admin = common::register_with_role(&server, &pool, "wallets", "admin").await;
+    (server, admin.token)
+}
+
+#[tokio::test]
+async fn test_generate_wallets_unauthorized() {
+    let (server, _) = common::setup_test_server().await;
+
+    let response = server
+        .post("/wallets/generate")
+        .json(&json!({
+            "count": 1
+        }))
+        .await;
+
+    response.assert_status(StatusCode::UNAUTHORIZED);
+}
+
 #[tokio::test]
 async fn test_generate_single_wallet() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
     let response = server
         .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
         .json(&json!({
             "count": 1
         }))
@@ -296,10 +530,11 @@ async fn test_generate_single_wallet() {
 
 #[tokio::test]
 async fn test_generate_multiple_wallets() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
     let response = server
         .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
         .json(&json!({
             "count": 5
         }))
@@ -329,10 +564,11 @@ async fn test_generate_multiple_wallets() {
 
 #[tokio::test]
 async fn test_generate_maximum_wallets() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
     let response = server
         .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
         .json(&json!({
             "count": 10000
         }))
@@ -347,10 +583,11 @@ async fn test_generate_maximum_wallets() {
 
 #[tokio::test]
 async fn test_generate_wallets_invalid_count_zero() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
     let response = server
         .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
         .json(&json!({
             "count": 0
         }))
@@ -361,10 +598,11 @@ async fn test_generate_wallets_invalid_count_zero() {
 
 #[tokio::test]
 async fn test_generate_wallets_invalid_count_too_large() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
     let response = server
         .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
         .json(&json!({
             "count": 10001
         }))
@@ -375,19 +613,24 @@ async fn test_generate_wallets_invalid_count_too_large() {
 
 #[tokio::test]
 async fn test_generate_wallets_missing_count() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
-    let response = server.post("/wallets/generate").json(&json!({})).await;
+    let response = server
+        .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .json(&json!({}))
+        .await;
 
     response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
 }
 
 #[tokio::test]
 async fn test_wallet_address_format_validation() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
     let response = server
         .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
         .json(&json!({
             "count": 3
         }))
@@ -407,10 +650,11 @@ async fn test_wallet_address_format_validation() {
 
 #[tokio::test]
 async fn test_generate_wallets_with_string_count() {
-    let server = setup_test_server().await;
+    let (server, token) = setup_wallet_server().await;
 
     let response = server
         .post("/wallets/generate")
+        .add_header("Authorization", format!("Bearer {token}"))
         .json(&json!({
             "count": "10"
         }))
@@ -422,3 +666,253 @@ async fn test_generate_wallets_with_string_count() {
     assert_eq!(body.count, 10);
     assert_eq!(body.wallets.len(), 10);
 }
+
+#[tokio::test]
+async fn test_related_posts() {
+    let (server, _) = common::setup_test_server().await;
+
+    let auth = common::register(&server, "testuser").await;
+    let marker = Uuid::new_v4().to_string().replace("-", "");
+
+    let source: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": format!("Rust async runtime {}", marker),
+            "content": format!("Tokio executors and async tasks {}", marker)
+        }))
+        .await
+        .json();
+
+    let similar: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": format!("Async tasks in Rust {}", marker),
+            "content": format!("Scheduling tokio runtime executors {}", marker)
+        }))
+        .await
+        .json();
+
+    let response = server
+        .get(&format!("/posts/{}/related?limit=5", source.id))
+        .await;
+
+    response.assert_status(StatusCode::OK);
+
+    let related: Vec<models::PostResponse> = response.json();
+    assert!(related.len() <= 5);
+    assert!(related.iter().all(|post| post.id != source.id));
+    assert_eq!(related.first().map(|post| post.id), Some(similar.id));
+}
+
+#[tokio::test]
+async fn test_related_posts_not_found() {
+    let (server, _) = common::setup_test_server().await;
+
+    let response = server.get("/posts/0/related").await;
+
+    response.assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_trending_posts() {
+    let (server, _) = common::setup_test_server().await;
+
+    let response = server.get("/posts/trending?limit=3").await;
+
+    response.assert_status(StatusCode::OK);
+
+    let trending: Vec<models::PostResponse> = response.json();
+    assert!(trending.len() <= 3);
+}
+
+#[tokio::test]
+async fn test_trending_counts_distinct_views_and_reactions() {
+    let (server, pool) = common::setup_test_server().await;
+    let author = common::register(&server, "trending").await;
+    let viewed = create_curated_post(&server, &author.token, "Viewed").await;
+
+    // Repeat views count once per viewer: per account when signed in,
+    // otherwise per address.
+    for ip in ["198.51.100.7", "198.51.100.7", "198.51.100.8"] {
+        server
+            .get(&format!("/posts/{}", viewed.id))
+            .add_header("X-Forwarded-For", ip)
+            .await
+            .assert_status_ok();
+    }
+    for ip in ["198.51.100.7", "198.51.100.9"] {
+        server
+            .get(&format!("/posts/{}", viewed.id))
+            .add_header("X-Forwarded-For", ip)
+            .add_header("Authorization", format!("Bearer {}", author.token))
+            .await
+            .assert_status_ok();
+    }
+    let views: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM post_views WHERE post_id = ?")
+        .bind(viewed.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(views, 3);
+
+    // A post nobody opened still trends on its likes.
+    let liked = create_curated_post(&server, &author.token, "Liked").await;
+    for _ in 0..20 {
+        sqlx::query(
+            "INSERT INTO post_reactions (post_id, actor_uri, activity_id, kind)
+             VALUES (?, ?, ?, 'like')",
+        )
+        .bind(liked.id)
+        .bind("https://remote.example/users/fan")
+        .bind(format!("https://remote.example/likes/{}", Uuid::new_v4()))
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    recommend::invalidate();
+    let trending: Vec<models::PostResponse> = server
+        .get("/posts/trending")
+        .add_query_param("limit", 50)
+        .await
+        .json();
+    assert!(trending.iter().any(|post| post.id == liked.id));
+}
+
+async fn create_curated_post(
+    server: &TestServer,
+    token: &str,
+    title: &str,
+) -> models::PostResponse {
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .json(&json!({ "title": title, "content": "Curated content" }))
+        .await
+        .json()
+}
+
+#[tokio::test]
+async fn test_pinned_posts() {
+    let (server, _) = common::setup_test_server().await;
+    let author = common::register(&server, "curate").await;
+    let other = common::register(&server, "curate").await;
+
+    let older = create_curated_post(&server, &author.token, "Older").await;
+    let newer = create_curated_post(&server, &author.token, "Newer").await;
+
+    server
+        .put(&format!("/posts/{}/pin", older.id))
+        .add_header("Authorization", format!("Bearer {}", other.token))
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let pinned: models::PostResponse = server
+        .put(&format!("/posts/{}/pin", older.id))
+        .add_header("Authorization", format!("Bearer {}", author.token))
+        .json(&json!({ "position": 1 }))
+        .await
+        .json();
+    assert!(pinned.pinned);
+
+    let posts: Vec<models::PostResponse> = server
+        .get("/posts")
+        .add_query_param("author_id", author.user.id)
+        .add_query_param("pinned", true)
+        .await
+        .json();
+    assert_eq!(posts[0].id, older.id);
+    assert_eq!(posts[1].id, newer.id);
+
+    let posts: Vec<models::PostResponse> = server
+        .get("/posts")
+        .add_query_param("author_id", author.user.id)
+        .await
+        .json();
+    assert_eq!(posts[0].id, newer.id);
+
+    server
+        .delete(&format!("/posts/{}/pin", older.id))
+        .add_header("Authorization", format!("Bearer {}", author.token))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    let posts: Vec<models::PostResponse> = server
+        .get("/posts")
+        .add_query_param("author_id", author.user.id)
+        .add_query_param("pinned", true)
+        .await
+        .json();
+    assert_eq!(posts[0].id, newer.id);
+    assert!(!posts[1].pinned);
+}
+
+#[tokio::test]
+async fn test_featured_slots_admin_only() {
+    let (server, pool) = common::setup_test_server().await;
+    let author = common::register(&server, "curate").await;
+    let admin = common::register_with_role(&server, &pool, "curate", "admin").await;
+    assert_eq!(admin.user.role, "admin");
+
+    let post = create_curated_post(&server, &author.token, "Featured").await;
+    create_curated_post(&server, &author.token, "Not featured").await;
+
+    server
+        .put("/admin/featured/1")
+        .add_header("Authorization", format!("Bearer {}", author.token))
+        .json(&json!({ "post_id": post.id }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    server
+        .put("/admin/featured/0")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "post_id": post.id }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .put("/admin/featured/1")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "post_id": post.id, "expires_at": "2000-01-01T00:00:00Z" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let slot: models::FeaturedSlotResponse = server
+        .put("/admin/featured/1")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .json(&json!({ "post_id": post.id }))
+        .await
+        .json();
+    assert_eq!(slot.post_id, post.id);
+
+    let posts: Vec<models::PostResponse> = server
+        .get("/posts")
+        .add_query_param("featured", true)
+        .await
+        .json();
+    assert_eq!(posts[0].id, post.id);
+    assert_eq!(posts[0].featured_slot, Some(1));
+
+    let slots: Vec<models::FeaturedSlotResponse> = server
+        .get("/admin/featured")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .json();
+    assert!(slots.iter().any(|slot| slot.post_id == post.id));
+
+    server
+        .delete("/admin/featured/1")
+        .add_header("Authorization", format!("Bearer {}", admin.token))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    let posts: Vec<models::PostResponse> = server
+        .get("/posts")
+        .add_query_param("author_id", author.user.id)
+        .await
+        .json();
+    assert!(posts.iter().all(|post| post.featured_slot.is_none()));
+}
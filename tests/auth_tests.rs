@@ -0,0 +1,670 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use blog_api::password_policy::{self, PolicyViolation};
+use blog_api::{auth, db, models};
+use rand::Rng;
+use serde_json::{json, Value};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+mod common;
+
+use common::{error_message, PASSWORD};
+
+async fn setup_test_server() -> (TestServer, db::DbPool) {
+    // No other scenario here uses editors, so this only affects the 2FA ones.
+    std::env::set_var("TOTP_REQUIRED_ROLES", "editor");
+    common::setup_test_server().await
+}
+
+async fn login_status(server: &TestServer, username: &str, password: &str) -> StatusCode {
+    common::login(server, username, password)
+        .await
+        .status_code()
+}
+
+// Password reset
+
+#[tokio::test]
+async fn test_password_reset_flow() {
+    let _env = common::env_lock().await;
+    let (server, _) = setup_test_server().await;
+    let auth = common::register(&server, "reset").await;
+    let username = auth.user.username.clone();
+    let email = auth.user.email.clone();
+
+    server
+        .post("/password/forgot")
+        .json(&json!({ "email": email }))
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+
+    // Unknown addresses get the same answer.
+    server
+        .post("/password/forgot")
+        .json(&json!({ "email": format!("nobody_{}@test.com", Uuid::new_v4().simple()) }))
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+
+    let token = common::link_token_sent_to(&email, "/password/reset").await;
+
+    // A rejected password leaves the token usable.
+    let response = server
+        .post("/password/reset")
+        .json(&json!({ "token": token, "password": "Password123" }))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert!(error_message(&response).contains("breached"));
+
+    server
+        .post("/password/reset")
+        .json(&json!({ "token": token, "password": "new-password456" }))
+        .await
+        .assert_status_ok();
+
+    // Single use.
+    server
+        .post("/password/reset")
+        .json(&json!({ "token": token, "password": "another-password789" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Existing sessions are gone.
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "title": "After reset", "content": "Body" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": auth.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    assert_eq!(
+        login_status(&server, &username, PASSWORD).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        login_status(&server, &username, "new-password456").await,
+        StatusCode::OK
+    );
+
+    server
+        .post("/password/reset")
+        .json(&json!({ "token": "not-a-token", "password": "new-password456" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+// Two-factor authentication
+
+/// Code for the time step `offset` steps away from now.
+fn code(secret: &str, offset: i64) -> String {
+    let totp = TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        0,
+        30,
+        Secret::Encoded(secret.to_string()).to_bytes().unwrap(),
+        None,
+        String::new(),
+    );
+    let time = chrono::Utc::now().timestamp() + offset * 30;
+    totp.generate(time as u64)
+}
+
+/// Enrolls the user and returns the secret, the code used to confirm and the
+/// recovery codes.
+async fn enable_two_factor(server: &TestServer, token: &str) -> (String, String, Vec<String>) {
+    let enrollment: models::TwoFactorEnrollmentResponse = server
+        .post("/me/2fa/enroll")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .await
+        .json();
+    assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/"));
+    assert!(enrollment.otpauth_uri.contains(&enrollment.secret));
+    assert!(!enrollment.qr_code_png.is_empty());
+
+    server
+        .post("/me/2fa/confirm")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .json(&json!({ "code": "abcdef" }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // The previous step keeps the next login code from being a replay.
+    let confirm_code = code(&enrollment.secret, -1);
+    let confirmed: models::RecoveryCodesResponse = server
+        .post("/me/2fa/confirm")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .json(&json!({ "code": confirm_code }))
+        .await
+        .json();
+    assert_eq!(confirmed.recovery_codes.len(), 10);
+
+    (enrollment.secret, confirm_code, confirmed.recovery_codes)
+}
+
+async fn login_challenge(server: &TestServer, username: &str) -> String {
+    let response: Value = common::login(server, username, PASSWORD).await.json();
+    assert_eq!(response["two_factor_required"], true);
+    assert!(response.get("token").is_none());
+    response["challenge_token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_two_factor_login() {
+    let _env = common::env_lock().await;
+    let (server, pool) = setup_test_server().await;
+    let auth = common::register(&server, "totp").await;
+    let username = auth.user.username.clone();
+
+    let (secret, confirm_code, recovery_codes) = enable_two_factor(&server, &auth.token).await;
+
+    // Enabling signs out the session that had only used a password.
+    server
+        .post("/me/2fa/enroll")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let challenge = login_challenge(&server, &username).await;
+
+    server
+        .post("/login/2fa")
+        .json(&json!({ "challenge_token": challenge, "code": confirm_code }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let signed_in: models::AuthResponse = server
+        .post("/login/2fa")
+        .json(&json!({ "challenge_token": challenge, "code": code(&secret, 1) }))
+        .await
+        .json();
+    assert!(signed_in.user.two_factor_enabled);
+
+    server
+        .post("/me/2fa/enroll")
+        .add_header("Authorization", format!("Bearer {}", signed_in.token))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    // A passed challenge cannot be used twice.
+    server
+        .post("/login/2fa")
+        .json(&json!({ "challenge_token": challenge, "code": recovery_codes[0] }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let challenge = login_challenge(&server, &username).await;
+    server
+        .post("/login/2fa")
+        .json(&json!({ "challenge_token": challenge, "code": recovery_codes[0] }))
+        .await
+        .assert_status_ok();
+
+    let challenge = login_challenge(&server, &username).await;
+    server
+        .post("/login/2fa")
+        .json(&json!({ "challenge_token": challenge, "code": recovery_codes[0] }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let regenerated: models::RecoveryCodesResponse = server
+        .post("/me/2fa/recovery-codes")
+        .add_header("Authorization", format!("Bearer {}", signed_in.token))
+        .json(&json!({ "code": recovery_codes[1] }))
+        .await
+        .json();
+    assert_eq!(regenerated.recovery_codes.len(), 10);
+    let regenerations: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_events
+         WHERE actor_id = ? AND action = 'auth.2fa_recovery_codes'",
+    )
+    .bind(auth.user.id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(regenerations, 1);
+
+    server
+        .delete("/me/2fa")
+        .add_header("Authorization", format!("Bearer {}", signed_in.token))
+        .json(&json!({ "current_password": PASSWORD, "code": recovery_codes[2] }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    server
+        .delete("/me/2fa")
+        .add_header("Authorization", format!("Bearer {}", signed_in.token))
+        .json(&json!({
+            "current_password": PASSWORD,
+            "code": regenerated.recovery_codes[0]
+        }))
+        .await
+        .assert_status_ok();
+
+    let response: models::AuthResponse = common::login(&server, &username, PASSWORD).await.json();
+    assert!(!response.user.two_factor_enabled);
+}
+
+#[tokio::test]
+async fn test_two_factor_required_for_role() {
+    let _env = common::env_lock().await;
+    let (server, pool) = setup_test_server().await;
+    let editor = common::register_with_role(&server, &pool, "totp", "editor").await;
+
+    let post = json!({ "title": "Needs 2FA", "content": "Body" });
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", editor.token))
+        .json(&post)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let (secret, _, recovery_codes) = enable_two_factor(&server, &editor.token).await;
+
+    // The password-only session is gone, so its refresh token cannot be
+    // traded for a token that claims the second factor.
+    server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": editor.refresh_token }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let challenge = login_challenge(&server, &editor.user.username).await;
+    let editor: models::AuthResponse = server
+        .post("/login/2fa")
+        .json(&json!({ "challenge_token": challenge, "code": code(&secret, 1) }))
+        .await
+        .json();
+    let editor = common::refresh(&server, &editor).await;
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", editor.token))
+        .json(&post)
+        .await
+        .assert_status_ok();
+
+    server
+        .delete("/me/2fa")
+        .add_header("Authorization", format!("Bearer {}", editor.token))
+        .json(&json!({ "current_password": PASSWORD, "code": recovery_codes[0] }))
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+}
+
+// Login throttling
+
+fn random_ip() -> String {
+    let mut rng = rand::thread_rng();
+    format!(
+        "10.{}.{}.{}",
+        rng.gen::<u8>(),
+        rng.gen::<u8>(),
+        rng.gen_range(1..255)
+    )
+}
+
+async fn login_from(server: &TestServer, ip: &str, username: &str, password: &str) -> StatusCode {
+    server
+        .post("/login")
+        .add_header("X-Forwarded-For", ip.to_string())
+        .json(&json!({ "username": username, "password": password }))
+        .await
+        .status_code()
+}
+
+async fn lockout_audits(pool: &db::DbPool, target: &str) -> i64 {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_events WHERE action = 'auth.lockout' AND target = ?",
+    )
+    .bind(target)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+// Settings are read from the environment, so the scenarios share one test.
+#[tokio::test]
+async fn test_failed_logins_lock_username_and_ip() {
+    let _env = common::env_lock().await;
+    std::env::set_var("LOGIN_MAX_ATTEMPTS", "3");
+    std::env::set_var("LOGIN_MAX_ATTEMPTS_PER_IP", "5");
+    let (server, pool) = setup_test_server().await;
+    let username = common::register(&server, "throttle").await.user.username;
+
+    // A success clears earlier failures on the username.
+    let ip = random_ip();
+    for _ in 0..2 {
+        assert_eq!(
+            login_from(&server, &ip, &username, "wrong").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    assert_eq!(
+        login_from(&server, &ip, &username, PASSWORD).await,
+        StatusCode::OK
+    );
+
+    // The third failure in a row locks the username, from any address and
+    // even with the right password.
+    for _ in 0..3 {
+        assert_eq!(
+            login_from(&server, &random_ip(), &username, "wrong").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    let response = server
+        .post("/login")
+        .add_header("X-Forwarded-For", random_ip())
+        .json(&json!({ "username": username, "password": PASSWORD }))
+        .await;
+    response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert!(error_message(&response).contains("try again in"));
+    assert_eq!(
+        lockout_audits(&pool, &format!("username:{username}")).await,
+        1
+    );
+
+    // Spraying unknown usernames from one address locks the address.
+    let ip = random_ip();
+    for _ in 0..5 {
+        let unknown = format!("nobody_{}", Uuid::new_v4().simple());
+        assert_eq!(
+            login_from(&server, &ip, &unknown, "wrong").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    let other = format!("nobody_{}", Uuid::new_v4().simple());
+    assert_eq!(
+        login_from(&server, &ip, &other, "wrong").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(lockout_audits(&pool, &format!("ip:{ip}")).await, 1);
+
+    // Entries the client puts in front of the proxy's do not change the IP.
+    let spoofed = format!("{}, {ip}", random_ip());
+    assert_eq!(
+        login_from(&server, &spoofed, &other, "wrong").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Wrong second-factor codes count against the username as well, and
+    // challenges started with the right password do not reset them.
+    let auth = common::register(&server, "throttle_totp").await;
+    let username = auth.user.username.clone();
+    enable_two_factor(&server, &auth.token).await;
+    let mut challenges = Vec::new();
+    for _ in 0..4 {
+        challenges.push(login_challenge(&server, &username).await);
+    }
+    let ip = random_ip();
+    for (i, challenge) in challenges.iter().enumerate() {
+        let expected = if i < 3 {
+            StatusCode::UNAUTHORIZED
+        } else {
+            StatusCode::TOO_MANY_REQUESTS
+        };
+        server
+            .post("/login/2fa")
+            .add_header("X-Forwarded-For", ip.clone())
+            .json(&json!({ "challenge_token": challenge, "code": "000000" }))
+            .await
+            .assert_status(expected);
+    }
+    assert_eq!(
+        login_from(&server, &random_ip(), &username, PASSWORD).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // So do wrong codes sent from a signed-in session.
+    let auth = common::register(&server, "throttle_session").await;
+    let username = auth.user.username.clone();
+    let (secret, _, recovery_codes) = enable_two_factor(&server, &auth.token).await;
+    let challenge = login_challenge(&server, &username).await;
+    let session: models::AuthResponse = server
+        .post("/login/2fa")
+        .json(&json!({ "challenge_token": challenge, "code": code(&secret, 1) }))
+        .await
+        .json();
+    for _ in 0..3 {
+        server
+            .post("/me/2fa/recovery-codes")
+            .add_header("Authorization", format!("Bearer {}", session.token))
+            .json(&json!({ "code": "000000" }))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+    server
+        .post("/me/2fa/recovery-codes")
+        .add_header("Authorization", format!("Bearer {}", session.token))
+        .json(&json!({ "code": recovery_codes[0] }))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    server
+        .delete("/me/2fa")
+        .add_header("Authorization", format!("Bearer {}", session.token))
+        .json(&json!({ "current_password": PASSWORD, "code": recovery_codes[0] }))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    // Reset emails are limited per address, registered or not, and per IP.
+    let email = common::register(&server, "throttle_reset").await.user.email;
+    let unknown = format!("nobody_{}@test.com", Uuid::new_v4().simple());
+    for address in [&email, &unknown] {
+        for _ in 0..3 {
+            assert_eq!(
+                forgot_from(&server, &random_ip(), address).await,
+                StatusCode::ACCEPTED
+            );
+        }
+        assert_eq!(
+            forgot_from(&server, &random_ip(), address).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+    assert_eq!(
+        lockout_audits(&pool, &format!("reset_email:{email}")).await,
+        1
+    );
+
+    let ip = random_ip();
+    for _ in 0..5 {
+        let address = format!("nobody_{}@test.com", Uuid::new_v4().simple());
+        assert_eq!(
+            forgot_from(&server, &ip, &address).await,
+            StatusCode::ACCEPTED
+        );
+    }
+    let address = format!("nobody_{}@test.com", Uuid::new_v4().simple());
+    assert_eq!(
+        forgot_from(&server, &ip, &address).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    std::env::remove_var("LOGIN_MAX_ATTEMPTS");
+    std::env::remove_var("LOGIN_MAX_ATTEMPTS_PER_IP");
+}
+
+async fn forgot_from(server: &TestServer, ip: &str, email: &str) -> StatusCode {
+    server
+        .post("/password/forgot")
+        .add_header("X-Forwarded-For", ip.to_string())
+        .json(&json!({ "email": email }))
+        .await
+        .status_code()
+}
+
+// Password hashing
+
+async fn stored_hash(pool: &db::DbPool, username: &str) -> String {
+    sqlx::query_scalar("SELECT password_hash FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[test]
+fn test_hash_and_verify() {
+    let hash = auth::hash_password("correct horse").unwrap();
+    assert!(hash.starts_with("$argon2id$v=19$"));
+    assert!(auth::verify_password("correct horse", &hash).unwrap());
+    assert!(!auth::verify_password("wrong horse", &hash).unwrap());
+
+    let legacy = bcrypt::hash("correct horse", 4).unwrap();
+    assert!(auth::verify_password("correct horse", &legacy).unwrap());
+    assert!(auth::password_needs_rehash(&legacy));
+}
+
+// Argon2 parameters are read from the environment, so the scenarios share
+// one test.
+#[tokio::test]
+async fn test_login_rehashes_outdated_hashes() {
+    let _env = common::env_lock().await;
+    std::env::remove_var("ARGON2_ITERATIONS");
+    let (server, pool) = setup_test_server().await;
+
+    // A bcrypt hash from before the switch still signs in and is upgraded.
+    let username = format!("rehash_{}", Uuid::new_v4().simple());
+    sqlx::query("INSERT INTO users (username, email, password_hash) VALUES (?, ?, ?)")
+        .bind(&username)
+        .bind(format!("{username}@test.com"))
+        .bind(bcrypt::hash(PASSWORD, 4).unwrap())
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        login_status(&server, &username, PASSWORD).await,
+        StatusCode::OK
+    );
+    let upgraded = stored_hash(&pool, &username).await;
+    assert!(upgraded.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+    assert!(!auth::password_needs_rehash(&upgraded));
+
+    // A failed login leaves the hash alone.
+    assert_eq!(
+        login_status(&server, &username, "wrong-password").await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(stored_hash(&pool, &username).await, upgraded);
+
+    // Raising the cost upgrades existing Argon2 hashes on their next login.
+    std::env::set_var("ARGON2_ITERATIONS", "3");
+    assert!(auth::password_needs_rehash(&upgraded));
+    assert_eq!(
+        login_status(&server, &username, PASSWORD).await,
+        StatusCode::OK
+    );
+    assert!(stored_hash(&pool, &username)
+        .await
+        .starts_with("$argon2id$v=19$m=19456,t=3,p=1$"));
+    std::env::remove_var("ARGON2_ITERATIONS");
+
+    // Passwords are no longer cut off at bcrypt's 72 bytes.
+    let username = format!("long_{}", Uuid::new_v4().simple());
+    let password = "correct-horse-".repeat(6);
+    let _: models::AuthResponse = server
+        .post("/register")
+        .json(&json!({
+            "username": username,
+            "email": format!("{}@test.com", username),
+            "password": password
+        }))
+        .await
+        .json();
+    assert_eq!(
+        login_status(&server, &username, &password).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        login_status(&server, &username, &password[..72]).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+// Password policy
+
+#[test]
+fn test_policy_rules() {
+    let check =
+        |password: &str| password_policy::violations(password, "alice", "alice.w@example.com");
+
+    assert!(check("correct-horse-42").is_empty());
+    assert_eq!(check("short1"), vec![PolicyViolation::TooShort(10)]);
+    assert_eq!(
+        check("onlylowercaseletters"),
+        vec![PolicyViolation::TooFewCharacterClasses(2)]
+    );
+    assert_eq!(check(&"a1".repeat(65)), vec![PolicyViolation::TooLong(128)]);
+    assert_eq!(
+        check("horse-ALICE-42"),
+        vec![PolicyViolation::ContainsUsername]
+    );
+    assert!(check("alice.w-horse-42").contains(&PolicyViolation::ContainsEmail));
+    assert_eq!(
+        check("qwerty123"),
+        vec![PolicyViolation::TooShort(10), PolicyViolation::Breached]
+    );
+    assert!(password_policy::is_breached("Password123"));
+    assert!(!password_policy::is_breached("correct-horse-42"));
+}
+
+#[tokio::test]
+async fn test_policy_applies_to_register_and_change() {
+    let _env = common::env_lock().await;
+    let (server, _) = setup_test_server().await;
+    let username = format!("policy_{}", Uuid::new_v4().simple());
+    let email = format!("{}@test.com", username);
+
+    let response = server
+        .post("/register")
+        .json(&json!({ "username": username, "email": email, "password": "password1234" }))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(
+        error_message(&response),
+        "Password appears in a list of breached passwords; choose another"
+    );
+
+    let response = server
+        .post("/register")
+        .json(&json!({
+            "username": username,
+            "email": email,
+            "password": format!("{username}-42")
+        }))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert!(error_message(&response).contains("must not contain the username"));
+
+    let auth: models::AuthResponse = server
+        .post("/register")
+        .json(&json!({ "username": username, "email": email, "password": PASSWORD }))
+        .await
+        .json();
+
+    let response = server
+        .put("/me/password")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "current_password": PASSWORD, "new_password": "tooshort" }))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(
+        error_message(&response),
+        "Password must be at least 10 characters long; must mix at least 2 of lowercase \
+         letters, uppercase letters, digits and symbols"
+    );
+
+    server
+        .put("/me/password")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "current_password": PASSWORD, "new_password": "battery-staple-7" }))
+        .await
+        .assert_status_ok();
+}
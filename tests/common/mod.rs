@@ -0,0 +1,134 @@
+// Each test binary uses its own subset of these helpers.
+#![allow(dead_code)]
+
+use axum_test::{TestResponse, TestServer};
+use blog_api::{db, models, routes};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::{Once, OnceLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const PASSWORD: &str = "correct-horse-42";
+
+/// Where outgoing mail is written instead of being sent, one file per test
+/// binary.
+pub fn mail_file() -> &'static Path {
+    static MAIL_FILE: OnceLock<PathBuf> = OnceLock::new();
+    MAIL_FILE
+        .get_or_init(|| std::env::temp_dir().join(format!("blog-mail-{}.jsonl", Uuid::new_v4())))
+}
+
+/// Tests in one binary run in parallel but share the process environment.
+/// A test that sets environment variables holds this for its whole run, so
+/// no other such test sees its settings or changes them underneath it.
+pub async fn env_lock() -> tokio::sync::MutexGuard<'static, ()> {
+    static LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(Default::default).lock().await
+}
+
+/// The application exactly as `main` serves it. Set any environment the
+/// scenario depends on, under [`env_lock`], before calling this.
+pub async fn setup_test_server() -> (TestServer, db::DbPool) {
+    // Settings every scenario shares are written once, before any server
+    // reads them.
+    static SHARED_ENV: Once = Once::new();
+    SHARED_ENV.call_once(|| {
+        dotenv::dotenv().ok();
+        std::env::set_var("MAIL_FILE", mail_file());
+        // Lets tests pick the client IP with X-Forwarded-For.
+        std::env::set_var("TRUST_PROXY_HEADERS", "true");
+    });
+
+    let pool = db::create_pool()
+        .await
+        .expect("Failed to create database pool");
+
+    (TestServer::new(routes::app(pool.clone())).unwrap(), pool)
+}
+
+/// Registers a fresh author whose username starts with `prefix`.
+pub async fn register(server: &TestServer, prefix: &str) -> models::AuthResponse {
+    let username = format!("{prefix}_{}", Uuid::new_v4().simple());
+    let response = server
+        .post("/register")
+        .json(&json!({
+            "username": username,
+            "email": format!("{}@test.com", username),
+            "password": PASSWORD
+        }))
+        .await;
+    response.assert_status_ok();
+    response.json()
+}
+
+pub async fn register_with_role(
+    server: &TestServer,
+    pool: &db::DbPool,
+    prefix: &str,
+    role: &str,
+) -> models::AuthResponse {
+    let user = register(server, prefix).await;
+    sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+        .bind(role)
+        .bind(user.user.id)
+        .execute(pool)
+        .await
+        .unwrap();
+    // The role travels in the token, so pick it up with a fresh one.
+    refresh(server, &user).await
+}
+
+pub async fn refresh(server: &TestServer, auth: &models::AuthResponse) -> models::AuthResponse {
+    let response = server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": auth.refresh_token }))
+        .await;
+    response.assert_status_ok();
+    response.json()
+}
+
+pub async fn login(server: &TestServer, username: &str, password: &str) -> TestResponse {
+    server
+        .post("/login")
+        .json(&json!({ "username": username, "password": password }))
+        .await
+}
+
+pub fn error_message(response: &TestResponse) -> String {
+    response.json::<Value>()["error"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Waits for the background mail task and pulls the token out of the newest
+/// link to `path` sent to `email`.
+pub async fn link_token_sent_to(email: &str, path: &str) -> String {
+    let marker = format!("{path}?token=");
+    for _ in 0..50 {
+        if let Ok(contents) = tokio::fs::read_to_string(mail_file()).await {
+            let token = contents
+                .lines()
+                .rev()
+                .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+                .filter(|message| message["to"] == email)
+                .filter_map(|message| {
+                    let body = message["body"].as_str()?.to_string();
+                    let start = body.find(&marker)? + marker.len();
+                    Some(
+                        body[start..]
+                            .chars()
+                            .take_while(|c| c.is_ascii_alphanumeric())
+                            .collect::<String>(),
+                    )
+                })
+                .next();
+            if let Some(token) = token {
+                return token;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("no {path} link sent to {email}");
+}
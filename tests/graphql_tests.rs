@@ -0,0 +1,177 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use blog_api::{models, moderation};
+use serde_json::{json, Value};
+
+mod common;
+
+async fn graphql(server: &TestServer, token: Option<&str>, query: &str, variables: Value) -> Value {
+    let mut request = server
+        .post("/graphql")
+        .json(&json!({ "query": query, "variables": variables }));
+    if let Some(token) = token {
+        request = request.add_header("Authorization", format!("Bearer {token}"));
+    }
+    let response = request.await;
+    response.assert_status_ok();
+    response.json()
+}
+
+const CREATE_POST: &str = r#"
+    mutation Create($input: CreatePostInput!) {
+        createPost(input: $input) { id title author { username } }
+    }
+"#;
+
+#[tokio::test]
+async fn test_graphql_posts_and_authors() {
+    let (server, _) = common::setup_test_server().await;
+    let author = common::register(&server, "gql").await;
+
+    for title in ["First", "Second"] {
+        let created = graphql(
+            &server,
+            Some(&author.token),
+            CREATE_POST,
+            json!({ "input": { "title": title, "content": "# Heading\n\nBody text" } }),
+        )
+        .await;
+        assert_eq!(
+            created["data"]["createPost"]["author"]["username"],
+            json!(author.user.username)
+        );
+    }
+
+    let result = graphql(
+        &server,
+        None,
+        r#"
+            query Posts($authorId: Int!) {
+                posts(authorId: $authorId) {
+                    title
+                    readingTimeMinutes
+                    toc { anchor }
+                    author { id posts { id } }
+                    comments { content }
+                    likeCount
+                }
+            }
+        "#,
+        json!({ "authorId": author.user.id }),
+    )
+    .await;
+
+    let posts = result["data"]["posts"].as_array().unwrap();
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0]["title"], "Second");
+    assert_eq!(posts[0]["toc"][0]["anchor"], "heading");
+    assert_eq!(posts[0]["author"]["posts"].as_array().unwrap().len(), 2);
+    assert_eq!(posts[1]["likeCount"], 0);
+
+    let me = graphql(
+        &server,
+        Some(&author.token),
+        "{ me { username } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(me["data"]["me"]["username"], json!(author.user.username));
+}
+
+#[tokio::test]
+async fn test_graphql_mutation_rules() {
+    let (server, pool) = common::setup_test_server().await;
+    let author = common::register(&server, "gql").await;
+    let other = common::register(&server, "gql").await;
+
+    let anonymous = graphql(
+        &server,
+        None,
+        CREATE_POST,
+        json!({ "input": { "title": "Nope", "content": "Nope" } }),
+    )
+    .await;
+    assert_eq!(anonymous["errors"][0]["extensions"]["status"], 401);
+
+    let created = graphql(
+        &server,
+        Some(&author.token),
+        CREATE_POST,
+        json!({ "input": { "title": "Mine", "content": "Mine" } }),
+    )
+    .await;
+    let post_id = created["data"]["createPost"]["id"].as_i64().unwrap();
+
+    let forbidden = graphql(
+        &server,
+        Some(&other.token),
+        "mutation Update($id: Int!) { updatePost(id: $id, input: { title: \"Stolen\" }) { id } }",
+        json!({ "id": post_id }),
+    )
+    .await;
+    assert_eq!(forbidden["errors"][0]["extensions"]["status"], 403);
+
+    // Without posts:write even one's own posts are off limits.
+    let demoted = common::register(&server, "gql").await;
+    let demoted_post = graphql(
+        &server,
+        Some(&demoted.token),
+        CREATE_POST,
+        json!({ "input": { "title": "Kept", "content": "Kept" } }),
+    )
+    .await;
+    let demoted_post_id = demoted_post["data"]["createPost"]["id"].as_i64().unwrap();
+    sqlx::query("UPDATE users SET role = 'reader' WHERE id = ?")
+        .bind(demoted.user.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let demoted: models::AuthResponse = server
+        .post("/token/refresh")
+        .json(&json!({ "refresh_token": demoted.refresh_token }))
+        .await
+        .json();
+    let update = graphql(
+        &server,
+        Some(&demoted.token),
+        "mutation Update($id: Int!) { updatePost(id: $id, input: { title: \"Edited\" }) { id } }",
+        json!({ "id": demoted_post_id }),
+    )
+    .await;
+    assert_eq!(update["errors"][0]["extensions"]["status"], 403);
+    let delete = graphql(
+        &server,
+        Some(&demoted.token),
+        "mutation Delete($id: Int!) { deletePost(id: $id) }",
+        json!({ "id": demoted_post_id }),
+    )
+    .await;
+    assert_eq!(delete["errors"][0]["extensions"]["status"], 403);
+
+    let banned = common::register(&server, "gql").await;
+    moderation::ban(&pool, banned.user.id, None).await.unwrap();
+    let rejected = graphql(
+        &server,
+        Some(&banned.token),
+        CREATE_POST,
+        json!({ "input": { "title": "Banned", "content": "Banned" } }),
+    )
+    .await;
+    assert_eq!(rejected["errors"][0]["extensions"]["status"], 403);
+
+    let deleted = graphql(
+        &server,
+        Some(&author.token),
+        "mutation Delete($id: Int!) { deletePost(id: $id) }",
+        json!({ "id": post_id }),
+    )
+    .await;
+    assert_eq!(deleted["data"]["deletePost"], true);
+
+    server
+        .post("/graphql")
+        .add_header("Authorization", "Bearer not-a-token")
+        .json(&json!({ "query": "{ posts { id } }" }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
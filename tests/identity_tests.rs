@@ -0,0 +1,416 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Form, Json, Router,
+};
+use axum_test::TestServer;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use blog_api::{activitypub, db, models, siwe};
+use chrono::Utc;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::utils::to_checksum;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use url::Url;
+use uuid::Uuid;
+
+mod common;
+
+async fn setup_test_server() -> (TestServer, db::DbPool) {
+    // Only the email scenario creates posts, so the other ones are unaffected.
+    std::env::set_var("REQUIRE_VERIFIED_EMAIL", "true");
+    common::setup_test_server().await
+}
+
+// Email verification
+
+#[tokio::test]
+async fn test_email_verification_flow() {
+    let _env = common::env_lock().await;
+    let (server, _) = setup_test_server().await;
+    let auth = common::register(&server, "verify").await;
+    assert!(!auth.user.email_verified);
+
+    let post = json!({ "title": "Hello", "content": "World" });
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&post)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    // The registration email was sent moments ago.
+    server
+        .post("/verify-email/resend")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    let token = common::link_token_sent_to(&auth.user.email, "/verify-email").await;
+
+    server
+        .get("/verify-email")
+        .add_query_param("token", &token)
+        .await
+        .assert_status_ok();
+
+    server
+        .get("/verify-email")
+        .add_query_param("token", &token)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&post)
+        .await
+        .assert_status_ok();
+
+    server
+        .post("/verify-email/resend")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+}
+
+// Sign-In with Ethereum
+
+async fn signed_request(
+    server: &TestServer,
+    wallet: &LocalWallet,
+    domain: &str,
+) -> serde_json::Value {
+    let nonce: models::SiweNonceResponse = server.get("/siwe/nonce").await.json();
+    let message = format!(
+        "{domain} wants you to sign in with your Ethereum account:\n\
+         {address}\n\
+         \n\
+         Sign in to the blog.\n\
+         \n\
+         URI: http://{domain}/login\n\
+         Version: 1\n\
+         Chain ID: 1\n\
+         Nonce: {nonce}\n\
+         Issued At: {issued_at}",
+        address = to_checksum(&wallet.address(), None),
+        nonce = nonce.nonce,
+        issued_at = Utc::now().to_rfc3339(),
+    );
+    let signature = wallet.sign_message(&message).await.unwrap();
+    json!({ "message": message, "signature": format!("0x{signature}") })
+}
+
+async fn siwe_login(server: &TestServer, wallet: &LocalWallet) -> models::AuthResponse {
+    let request = signed_request(server, wallet, &siwe::expected_domain()).await;
+    server.post("/siwe/login").json(&request).await.json()
+}
+
+#[tokio::test]
+async fn test_siwe_login_creates_account() {
+    let _env = common::env_lock().await;
+    let (server, _) = setup_test_server().await;
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let address = siwe::address_key(&wallet.address());
+
+    let request = signed_request(&server, &wallet, &siwe::expected_domain()).await;
+    let first: models::AuthResponse = server.post("/siwe/login").json(&request).await.json();
+    assert_eq!(first.user.username, address);
+    assert_eq!(
+        first.user.ethereum_address.as_deref(),
+        Some(address.as_str())
+    );
+
+    // The nonce is spent.
+    server
+        .post("/siwe/login")
+        .json(&request)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let second = siwe_login(&server, &wallet).await;
+    assert_eq!(second.user.id, first.user.id);
+
+    let wrong_domain = signed_request(&server, &wallet, "evil.example").await;
+    server
+        .post("/siwe/login")
+        .json(&wrong_domain)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // A signature from another key does not match the message address.
+    let mut forged = signed_request(&server, &wallet, &siwe::expected_domain()).await;
+    let other = LocalWallet::new(&mut rand::thread_rng());
+    let signature = other
+        .sign_message(forged["message"].as_str().unwrap())
+        .await
+        .unwrap();
+    forged["signature"] = json!(format!("0x{signature}"));
+    server
+        .post("/siwe/login")
+        .json(&forged)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_link_address_to_account() {
+    let _env = common::env_lock().await;
+    let (server, _) = setup_test_server().await;
+    let auth = common::register(&server, "siwe").await;
+
+    let wallet = LocalWallet::new(&mut rand::thread_rng());
+    let request = signed_request(&server, &wallet, &siwe::expected_domain()).await;
+    let linked: models::UserResponse = server
+        .post("/me/siwe")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&request)
+        .await
+        .json();
+    assert_eq!(
+        linked.ethereum_address,
+        Some(siwe::address_key(&wallet.address()))
+    );
+
+    let signed_in = siwe_login(&server, &wallet).await;
+    assert_eq!(signed_in.user.id, auth.user.id);
+
+    // An address already owned by a wallet-only account cannot be taken over.
+    let other_wallet = LocalWallet::new(&mut rand::thread_rng());
+    siwe_login(&server, &other_wallet).await;
+    let request = signed_request(&server, &other_wallet, &siwe::expected_domain()).await;
+    server
+        .post("/me/siwe")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&request)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+}
+
+// OpenID Connect
+
+const CLIENT_ID: &str = "blog";
+const KEY_ID: &str = "mock-key";
+
+/// A code the mock issuer handed out, with what the user "consented" to.
+struct PendingCode {
+    nonce: String,
+    code_challenge: String,
+    claims: Value,
+}
+
+#[derive(Clone)]
+struct MockIssuer {
+    base: String,
+    encoding_key: Arc<EncodingKey>,
+    jwks: Value,
+    codes: Arc<Mutex<HashMap<String, PendingCode>>>,
+}
+
+async fn discovery(State(issuer): State<MockIssuer>) -> Json<Value> {
+    Json(json!({
+        "issuer": issuer.base,
+        "authorization_endpoint": format!("{}/authorize", issuer.base),
+        "token_endpoint": format!("{}/token", issuer.base),
+        "jwks_uri": format!("{}/jwks", issuer.base),
+    }))
+}
+
+async fn jwks(State(issuer): State<MockIssuer>) -> Json<Value> {
+    Json(issuer.jwks)
+}
+
+async fn token(
+    State(issuer): State<MockIssuer>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let pending = issuer
+        .codes
+        .lock()
+        .unwrap()
+        .remove(&form["code"])
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let challenge = BASE64_URL.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+    if challenge != pending.code_challenge || form["client_id"] != CLIENT_ID {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut claims = pending.claims;
+    claims["iss"] = json!(issuer.base);
+    claims["aud"] = json!(CLIENT_ID);
+    claims["exp"] = json!(chrono::Utc::now().timestamp() + 300);
+    claims["iat"] = json!(chrono::Utc::now().timestamp());
+    if claims.get("nonce").is_none() {
+        claims["nonce"] = json!(pending.nonce);
+    }
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(KEY_ID.to_string());
+    let id_token = encode(&header, &claims, &issuer.encoding_key).unwrap();
+
+    Ok(Json(
+        json!({ "access_token": "unused", "token_type": "Bearer", "id_token": id_token }),
+    ))
+}
+
+async fn start_mock_issuer() -> MockIssuer {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+
+    let keys = activitypub::generate_keys().unwrap();
+    let public_key = RsaPublicKey::from_public_key_pem(&keys.public_key_pem).unwrap();
+
+    let issuer = MockIssuer {
+        base,
+        encoding_key: Arc::new(EncodingKey::from_rsa_pem(keys.private_key_pem.as_bytes()).unwrap()),
+        jwks: json!({
+            "keys": [{
+                "kty": "RSA",
+                "kid": KEY_ID,
+                "use": "sig",
+                "alg": "RS256",
+                "n": BASE64_URL.encode(public_key.n().to_bytes_be()),
+                "e": BASE64_URL.encode(public_key.e().to_bytes_be()),
+            }]
+        }),
+        codes: Arc::default(),
+    };
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/token", post(token))
+        .with_state(issuer.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    issuer
+}
+
+async fn setup_oidc_server(issuer: &MockIssuer) -> TestServer {
+    std::env::set_var("OIDC_PROVIDERS", "mock");
+    std::env::set_var("OIDC_MOCK_ISSUER", &issuer.base);
+    std::env::set_var("OIDC_MOCK_CLIENT_ID", CLIENT_ID);
+    std::env::set_var("OIDC_MOCK_CLIENT_SECRET", "mock-secret");
+    setup_test_server().await.0
+}
+
+/// Plays the provider's login page: accepts the authorization request as the
+/// user described by `claims` and returns the callback path.
+fn consent(issuer: &MockIssuer, authorization_url: &str, claims: Value) -> String {
+    let url = Url::parse(authorization_url).unwrap();
+    assert!(authorization_url.starts_with(&format!("{}/authorize", issuer.base)));
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    assert_eq!(params["code_challenge_method"], "S256");
+    assert_eq!(params["client_id"], CLIENT_ID);
+
+    let code = Uuid::new_v4().simple().to_string();
+    issuer.codes.lock().unwrap().insert(
+        code.clone(),
+        PendingCode {
+            nonce: params["nonce"].clone(),
+            code_challenge: params["code_challenge"].clone(),
+            claims,
+        },
+    );
+    format!("/oidc/mock/callback?code={code}&state={}", params["state"])
+}
+
+async fn sign_in(server: &TestServer, issuer: &MockIssuer, claims: Value) -> String {
+    let response = server.get("/oidc/mock/authorize").await;
+    response.assert_status(StatusCode::SEE_OTHER);
+    let location = response.header("location");
+    consent(issuer, location.to_str().unwrap(), claims)
+}
+
+#[tokio::test]
+async fn test_oidc_login_and_linking() {
+    let _env = common::env_lock().await;
+    let issuer = start_mock_issuer().await;
+    let server = setup_oidc_server(&issuer).await;
+
+    let providers: Vec<String> = server.get("/oidc").await.json();
+    assert_eq!(providers, vec!["mock".to_string()]);
+    server
+        .get("/oidc/unknown/authorize")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let suffix = Uuid::new_v4().simple().to_string();
+    let alice = json!({
+        "sub": format!("alice-{suffix}"),
+        "email": format!("alice-{suffix}@idp.test"),
+        "email_verified": true,
+        "preferred_username": format!("alice_{}", &suffix[..12]),
+    });
+
+    let callback = sign_in(&server, &issuer, alice.clone()).await;
+    let first: models::AuthResponse = server.get(&callback).await.json();
+    assert_eq!(first.user.username, alice["preferred_username"]);
+    assert!(first.user.email_verified);
+
+    // The login state is spent.
+    server
+        .get(&callback)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let callback = sign_in(&server, &issuer, alice.clone()).await;
+    let second: models::AuthResponse = server.get(&callback).await.json();
+    assert_eq!(second.user.id, first.user.id);
+
+    // ID tokens must echo the nonce of the login they answer.
+    let mut replayed = alice.clone();
+    replayed["nonce"] = json!("not-the-login-nonce");
+    let callback = sign_in(&server, &issuer, replayed).await;
+    server
+        .get(&callback)
+        .await
+        .assert_status(StatusCode::BAD_GATEWAY);
+
+    let local = common::register(&server, "oidc").await;
+
+    let bob = json!({ "sub": format!("bob-{suffix}") });
+    let link: models::OidcAuthorizationResponse = server
+        .post("/me/identities/mock")
+        .add_header("Authorization", format!("Bearer {}", local.token))
+        .await
+        .json();
+    let callback = consent(&issuer, &link.authorization_url, bob.clone());
+    let linked: models::AuthResponse = server.get(&callback).await.json();
+    assert_eq!(linked.user.id, local.user.id);
+
+    let identities: Vec<models::IdentityResponse> = server
+        .get("/me/identities")
+        .add_header("Authorization", format!("Bearer {}", local.token))
+        .await
+        .json();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].subject, bob["sub"]);
+
+    // Alice's identity already belongs to her own account.
+    let link: models::OidcAuthorizationResponse = server
+        .post("/me/identities/mock")
+        .add_header("Authorization", format!("Bearer {}", local.token))
+        .await
+        .json();
+    let callback = consent(&issuer, &link.authorization_url, alice);
+    server
+        .get(&callback)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    server
+        .delete("/me/identities/mock")
+        .add_header("Authorization", format!("Bearer {}", local.token))
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,203 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use blog_api::{mailer, newsletter};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+mod common;
+
+type Inbox = Arc<Mutex<Vec<String>>>;
+
+async fn start_smtp_sink() -> (u16, Inbox) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let inbox: Inbox = Arc::new(Mutex::new(Vec::new()));
+
+    let sink_inbox = inbox.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let inbox = sink_inbox.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer
+                    .write_all(b"220 localhost ESMTP sink\r\n")
+                    .await
+                    .unwrap();
+
+                let mut in_data = false;
+                let mut message = String::new();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if in_data {
+                        if line == "." {
+                            in_data = false;
+                            inbox.lock().unwrap().push(std::mem::take(&mut message));
+                            writer.write_all(b"250 OK\r\n").await.unwrap();
+                        } else {
+                            message.push_str(&line);
+                            message.push('\n');
+                        }
+                        continue;
+                    }
+
+                    let command = line.to_uppercase();
+                    let reply: &[u8] = if command.starts_with("EHLO") {
+                        b"250-localhost\r\n250 8BITMIME\r\n"
+                    } else if command.starts_with("DATA") {
+                        in_data = true;
+                        b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                    } else if command.starts_with("QUIT") {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    } else {
+                        b"250 OK\r\n"
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            });
+        }
+    });
+
+    (port, inbox)
+}
+
+fn decode_quoted_printable(message: &str) -> String {
+    message.replace("=\n", "").replace("=3D", "=")
+}
+
+fn extract_param(message: &str, name: &str) -> String {
+    let message = decode_quoted_printable(message);
+    let start = message
+        .find(&format!("{name}="))
+        .expect("parameter present in email")
+        + name.len()
+        + 1;
+    message[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_newsletter_subscription_flow() {
+    let (server, pool) = common::setup_test_server().await;
+    let (port, inbox) = start_smtp_sink().await;
+    let auth = common::register(&server, "author").await;
+
+    let subscriber = format!("reader_{}@test.com", Uuid::new_v4().simple());
+    server
+        .post("/newsletter/subscribe")
+        .json(&json!({
+            "email": subscriber,
+            "author_id": auth.user.id
+        }))
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+
+    // A pending address gets no further confirmation emails for a while,
+    // whichever author it asks for.
+    for author_id in [json!(auth.user.id), json!(null)] {
+        server
+            .post("/newsletter/subscribe")
+            .json(&json!({ "email": subscriber, "author_id": author_id }))
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let token = common::link_token_sent_to(&subscriber, "/newsletter/confirm").await;
+
+    server
+        .get(&format!("/newsletter/confirm?token={token}"))
+        .await
+        .assert_status(StatusCode::OK);
+    server
+        .get(&format!("/newsletter/confirm?token={token}"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+    let title = format!("Digest post {}", Uuid::new_v4().simple());
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": title,
+            "content": "Fresh content for subscribers"
+        }))
+        .await
+        .assert_status(StatusCode::OK);
+
+    let smtp =
+        mailer::SmtpMailer::plaintext("127.0.0.1", port, "Blog <no-reply@test.com>").unwrap();
+    newsletter::send_digests(&pool, &smtp).await.unwrap();
+
+    let digest = inbox
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|message| decode_quoted_printable(message).contains(&title))
+        .cloned()
+        .expect("digest email sent");
+    assert!(digest.contains("List-Unsubscribe"));
+
+    let subscription_id = extract_param(&digest, "id");
+    let unsubscribe_token = extract_param(&digest, "token");
+
+    server
+        .post(&format!(
+            "/newsletter/unsubscribe?id={subscription_id}&token={unsubscribe_token}"
+        ))
+        .await
+        .assert_status(StatusCode::OK);
+    server
+        .get(&format!(
+            "/newsletter/unsubscribe?id={subscription_id}&token=deadbeef"
+        ))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Posts published while unsubscribed are not mailed after subscribing
+    // again.
+    let missed = publish(&server, &auth.token).await;
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    server
+        .post("/newsletter/subscribe")
+        .json(&json!({ "email": subscriber, "author_id": auth.user.id }))
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+    let token = common::link_token_sent_to(&subscriber, "/newsletter/confirm").await;
+    server
+        .get(&format!("/newsletter/confirm?token={token}"))
+        .await
+        .assert_status(StatusCode::OK);
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let fresh = publish(&server, &auth.token).await;
+
+    newsletter::send_digests(&pool, &smtp).await.unwrap();
+    let received: Vec<String> = inbox
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|message| decode_quoted_printable(message))
+        .filter(|message| message.contains(&subscriber))
+        .collect();
+    assert!(received.iter().any(|message| message.contains(&fresh)));
+    assert!(!received.iter().any(|message| message.contains(&missed)));
+}
+
+/// Publishes a post with a unique title and returns the title.
+async fn publish(server: &TestServer, token: &str) -> String {
+    let title = format!("Digest post {}", Uuid::new_v4().simple());
+    server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {token}"))
+        .json(&json!({ "title": title, "content": "Fresh content for subscribers" }))
+        .await
+        .assert_status(StatusCode::OK);
+    title
+}
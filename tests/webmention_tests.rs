@@ -0,0 +1,203 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::Html,
+    routing::{get, post},
+    Form, Router,
+};
+use axum_test::TestServer;
+use blog_api::{http_client, models, webmention};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+mod common;
+
+#[derive(Clone, Default)]
+struct FakeSite {
+    pages: Arc<Mutex<HashMap<String, String>>>,
+    received: Arc<Mutex<Vec<HashMap<String, String>>>>,
+}
+
+async fn site_page(
+    State(site): State<FakeSite>,
+    Path(name): Path<String>,
+) -> Result<([(header::HeaderName, &'static str); 1], Html<String>), StatusCode> {
+    let page = site.pages.lock().unwrap().get(&name).cloned();
+    page.map(|html| {
+        (
+            [(header::LINK, "</webmention>; rel=\"webmention\"")],
+            Html(html),
+        )
+    })
+    .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn site_endpoint(
+    State(site): State<FakeSite>,
+    Form(form): Form<HashMap<String, String>>,
+) -> StatusCode {
+    site.received.lock().unwrap().push(form);
+    StatusCode::ACCEPTED
+}
+
+async fn start_fake_site() -> (String, FakeSite) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let site = FakeSite::default();
+
+    let app = Router::new()
+        .route("/pages/:name", get(site_page))
+        .route("/webmention", post(site_endpoint))
+        .with_state(site.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (base, site)
+}
+
+async fn setup_test_server() -> TestServer {
+    // The fake site listens on localhost.
+    std::env::set_var("ALLOW_PRIVATE_NETWORK_FETCHES", "true");
+    common::setup_test_server().await.0
+}
+
+#[tokio::test]
+async fn test_webmention_send_and_receive() {
+    let _env = common::env_lock().await;
+    let server = setup_test_server().await;
+    let (site_base, site) = start_fake_site().await;
+
+    let remote_article = format!("{site_base}/pages/article");
+    site.pages
+        .lock()
+        .unwrap()
+        .insert("article".to_string(), "<p>Remote article</p>".to_string());
+
+    let auth = common::register(&server, "wm").await;
+
+    let post: models::PostResponse = server
+        .post("/posts")
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({
+            "title": "Linking out",
+            "content": format!("Read {remote_article}.")
+        }))
+        .await
+        .json();
+    let post_url = webmention::post_url(post.id);
+
+    let mut sent = None;
+    for _ in 0..100 {
+        sent = site.received.lock().unwrap().first().cloned();
+        if sent.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let sent = sent.expect("remote site received a webmention");
+    assert_eq!(sent["source"], post_url);
+    assert_eq!(sent["target"], remote_article);
+
+    // Edits that leave the content alone do not ping linked sites again.
+    server
+        .put(&format!("/posts/{}", post.id))
+        .add_header("Authorization", format!("Bearer {}", auth.token))
+        .json(&json!({ "title": "Linking out, retitled" }))
+        .await
+        .assert_status_ok();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(site.received.lock().unwrap().len(), 1);
+
+    let response = server.get(&format!("/posts/{}", post.id)).await;
+    response.assert_status_ok();
+    assert!(response
+        .header(header::LINK)
+        .to_str()
+        .unwrap()
+        .contains("rel=\"webmention\""));
+
+    server
+        .post("/webmention")
+        .form(&[
+            ("source", "ftp://example.com/x"),
+            ("target", post_url.as_str()),
+        ])
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let reply = format!("{site_base}/pages/reply");
+    site.pages.lock().unwrap().insert(
+        "reply".to_string(),
+        format!("<html><head><title>A reply</title></head><body><a href=\"{post_url}\">nice</a></body></html>"),
+    );
+
+    server
+        .post("/webmention")
+        .form(&[("source", reply.as_str()), ("target", post_url.as_str())])
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+
+    let mut mentions = Vec::new();
+    for _ in 0..100 {
+        mentions = server
+            .get(&format!("/posts/{}/webmentions", post.id))
+            .await
+            .json::<Vec<models::WebmentionResponse>>();
+        if !mentions.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(mentions.len(), 1);
+    assert_eq!(mentions[0].source_url, reply);
+    assert_eq!(mentions[0].title.as_deref(), Some("A reply"));
+
+    let unlinked = format!("{site_base}/pages/article");
+    server
+        .post("/webmention")
+        .form(&[("source", unlinked.as_str()), ("target", post_url.as_str())])
+        .await
+        .assert_status(StatusCode::ACCEPTED);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mentions: Vec<models::WebmentionResponse> = server
+        .get(&format!("/posts/{}/webmentions", post.id))
+        .await
+        .json();
+    assert_eq!(mentions.len(), 1);
+}
+
+#[test]
+fn test_only_public_addresses_are_fetched() {
+    // The other test in this file allows private networks for its fake site,
+    // so addresses are checked directly rather than through the env switch.
+    for blocked in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "::1",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+    ] {
+        assert!(
+            !http_client::is_public_ip(blocked.parse().unwrap()),
+            "{blocked} should be refused"
+        );
+    }
+    for allowed in ["93.184.216.34", "2606:4700::1"] {
+        assert!(
+            http_client::is_public_ip(allowed.parse().unwrap()),
+            "{allowed}"
+        );
+    }
+
+    let file = url::Url::parse("file:///etc/passwd").unwrap();
+    assert!(http_client::check_public_url(&file).is_err());
+}